@@ -0,0 +1,334 @@
+use crate::cli::commands::ImgCommand;
+use crate::img::io::{STDIO_PATH, is_url};
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Expands a command whose input path is a glob into one command per matching file. With several
+/// matches the output path is treated as a directory and each output keeps its input's file name.
+/// Concat instead takes every file matched by each of its inputs, in order, as one more image
+pub fn expand_batch(mut command: ImgCommand) -> Result<Vec<ImgCommand>, Box<dyn Error>> {
+    if let ImgCommand::Concat { filepaths_in, .. } = &mut command {
+        *filepaths_in = expand_inputs(filepaths_in)?;
+        return Ok(vec![command]);
+    }
+
+    let pattern = match command.paths_mut() {
+        // A ? in a URL starts its query string rather than a wildcard
+        (Some(input), _) if is_glob(input) && !is_url(input) => input.clone(),
+        _ => return Ok(vec![command]),
+    };
+    let matches = glob(&pattern)?;
+    if matches.is_empty() {
+        return Err(format!("No files match '{}'", pattern).into());
+    }
+
+    if let [single] = matches.as_slice() {
+        if let (Some(input), _) = command.paths_mut() {
+            *input = single.clone();
+        }
+        return Ok(vec![command]);
+    }
+
+    if let (_, Some(output)) = command.paths_mut() {
+        if output == STDIO_PATH {
+            return Err(format!(
+                "'{}' matches {} files, which cannot all be written to stdout",
+                pattern,
+                matches.len()
+            )
+            .into());
+        }
+        fs::create_dir_all(output.as_str())?;
+    }
+
+    let mut commands = Vec::with_capacity(matches.len());
+    for path in matches {
+        let mut batch_command = command.clone();
+        let (input, output) = batch_command.paths_mut();
+        if let Some(output) = output {
+            let file_name = Path::new(&path)
+                .file_name()
+                .ok_or("Matched path has no file name")?;
+            *output = Path::new(output.as_str())
+                .join(file_name)
+                .to_string_lossy()
+                .into_owned();
+        }
+        if let Some(input) = input {
+            *input = path;
+        }
+        commands.push(batch_command);
+    }
+
+    Ok(commands)
+}
+
+/// Replaces every glob among the paths with the files it matches, leaving other paths as they are
+fn expand_inputs(paths: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        if !is_glob(path) || is_url(path) {
+            expanded.push(path.clone());
+            continue;
+        }
+        let matches = glob(path)?;
+        if matches.is_empty() {
+            return Err(format!("No files match '{}'", path).into());
+        }
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+/// Whether the path contains any wildcard characters
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Lists the files matching the pattern in sorted order. Wildcards are only supported in the final
+/// path component, where * matches any run of characters and ? matches exactly one
+fn glob(pattern: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let path = Path::new(pattern);
+    let file_pattern = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("Glob pattern must end in a file name")?;
+
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    if parent.is_some_and(|p| is_glob(&p.to_string_lossy())) {
+        return Err("Wildcards are only supported in the file name".into());
+    }
+
+    let mut matches = Vec::new();
+    for entry in fs::read_dir(parent.unwrap_or(Path::new(".")))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if entry.file_type()?.is_file() && wildcard_match(file_pattern, name) {
+            let matched = match parent {
+                Some(parent) => parent.join(name),
+                None => name.into(),
+            };
+            matches.push(matched.to_string_lossy().into_owned());
+        }
+    }
+
+    matches.sort();
+    Ok(matches)
+}
+
+/// Matches the name against a pattern of literal characters, * and ?
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last * seen and the name position it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::temp_path;
+
+    /// Creates an empty directory holding empty files with the given names
+    fn dir_with_files(name: &str, files: &[&str]) -> String {
+        let dir = temp_path(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            fs::write(Path::new(&dir).join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    fn invert(filepath_in: &str, filepath_out: &str) -> ImgCommand {
+        ImgCommand::Invert {
+            filepath_in: filepath_in.into(),
+            filepath_out: filepath_out.into(),
+        }
+    }
+
+    fn paths(mut command: ImgCommand) -> (Option<String>, Option<String>) {
+        let (input, output) = command.paths_mut();
+        (input.cloned(), output.cloned())
+    }
+
+    #[test]
+    fn wildcard_match_handles_stars_and_question_marks() {
+        assert!(wildcard_match("*.ppm", "a.ppm"));
+        assert!(wildcard_match("*.ppm", ".ppm"));
+        assert!(!wildcard_match("*.ppm", "a.ppm.bak"));
+        assert!(wildcard_match("img?.png", "img1.png"));
+        assert!(!wildcard_match("img?.png", "img.png"));
+        assert!(!wildcard_match("img?.png", "img12.png"));
+        assert!(wildcard_match("a*b*c", "aXXbYbZc"));
+        assert!(!wildcard_match("a*b*c", "aXXbYbZ"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("**", "anything"));
+        assert!(!wildcard_match("", "a"));
+        assert!(wildcard_match("exact", "exact"));
+        assert!(!wildcard_match("exact", "Exact"));
+    }
+
+    #[test]
+    fn plain_paths_are_left_alone() {
+        let commands = expand_batch(invert("in.ppm", "out.ppm")).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            paths(commands[0].clone()),
+            (Some("in.ppm".into()), Some("out.ppm".into()))
+        );
+    }
+
+    #[test]
+    fn glob_runs_once_per_match_into_the_output_directory() {
+        let dir = dir_with_files("batch", &["b.ppm", "a.ppm", "c.txt"]);
+        let out = Path::new(&dir).join("out").to_string_lossy().into_owned();
+        let pattern = Path::new(&dir).join("*.ppm").to_string_lossy().into_owned();
+
+        let commands = expand_batch(invert(&pattern, &out)).unwrap();
+        let expected: Vec<_> = ["a.ppm", "b.ppm"]
+            .iter()
+            .map(|name| {
+                let input = Path::new(&dir).join(name).to_string_lossy().into_owned();
+                let output = Path::new(&out).join(name).to_string_lossy().into_owned();
+                (Some(input), Some(output))
+            })
+            .collect();
+        assert_eq!(
+            commands.into_iter().map(paths).collect::<Vec<_>>(),
+            expected
+        );
+        assert!(Path::new(&out).is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn single_match_keeps_the_output_path() {
+        let dir = dir_with_files("batch-single", &["only.ppm"]);
+        let pattern = Path::new(&dir)
+            .join("onl?.ppm")
+            .to_string_lossy()
+            .into_owned();
+
+        let commands = expand_batch(invert(&pattern, "out.ppm")).unwrap();
+        let input = Path::new(&dir)
+            .join("only.ppm")
+            .to_string_lossy()
+            .into_owned();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            paths(commands[0].clone()),
+            (Some(input), Some("out.ppm".into()))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_without_matches_errors() {
+        let dir = dir_with_files("batch-empty", &["a.txt"]);
+        let pattern = Path::new(&dir).join("*.ppm").to_string_lossy().into_owned();
+        assert!(expand_batch(invert(&pattern, "out")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_is_never_batched() {
+        let command = ImgCommand::New {
+            filepath_out: "tile*.ppm".into(),
+            width: 2,
+            height: 2,
+            color: None,
+        };
+        assert_eq!(paths(command.clone()), (None, Some("tile*.ppm".into())));
+
+        let commands = expand_batch(command).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(paths(commands[0].clone()).1, Some("tile*.ppm".into()));
+    }
+
+    #[test]
+    fn concat_expands_every_input_in_place() {
+        let dir = dir_with_files("batch-concat", &["a1.ppm", "a2.ppm", "b1.ppm", "b2.ppm"]);
+        let join = |name: &str| Path::new(&dir).join(name).to_string_lossy().into_owned();
+
+        let command = ImgCommand::Concat {
+            filepaths_in: vec![join("b*.ppm"), "plain.ppm".into(), join("a?.ppm")],
+            filepath_out: "out.ppm".into(),
+            direction: None,
+            fill: None,
+        };
+        let commands = expand_batch(command).unwrap();
+        assert_eq!(commands.len(), 1);
+        let ImgCommand::Concat {
+            filepaths_in,
+            filepath_out,
+            ..
+        } = &commands[0]
+        else {
+            panic!("Expected a concat command");
+        };
+        assert_eq!(
+            filepaths_in,
+            &[
+                join("b1.ppm"),
+                join("b2.ppm"),
+                "plain.ppm".into(),
+                join("a1.ppm"),
+                join("a2.ppm")
+            ]
+        );
+        assert_eq!(filepath_out, "out.ppm");
+
+        let missing = ImgCommand::Concat {
+            filepaths_in: vec![join("a1.ppm"), join("z*.ppm")],
+            filepath_out: "out.ppm".into(),
+            direction: None,
+            fill: None,
+        };
+        assert!(expand_batch(missing).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn several_matches_cannot_share_stdout() {
+        let dir = dir_with_files("batch-stdout", &["a.ppm", "b.ppm"]);
+        let pattern = Path::new(&dir).join("*.ppm").to_string_lossy().into_owned();
+
+        let Err(error) = expand_batch(invert(&pattern, STDIO_PATH)) else {
+            panic!("several matches were expanded onto stdout");
+        };
+        assert!(error.to_string().contains("stdout"), "{}", error);
+        assert!(!Path::new(STDIO_PATH).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,12 @@
+use crate::img::concat::ConcatDirection;
 use crate::img::crop::CropMethod;
+use crate::img::cvd::CvdType;
+use crate::img::dither::DitherMethod;
+use crate::img::image::Channel;
 use crate::img::scale::ScaleMethod;
+use crate::img::seam::{EnergyBorder, EnergyMode, EnergySource, SeamDirection};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(
@@ -13,32 +18,100 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: TopLevelCommand,
+
+    #[arg(long, global = true, help = "Seeds randomness for reproducible output")]
+    pub seed: Option<u64>,
+
+    #[arg(long, global = true, help = "Reports errors on stderr as JSON")]
+    pub json_errors: bool,
+
+    #[arg(long, global = true, help = "Output format, required for - (stdout)")]
+    pub format: Option<String>,
+
+    #[arg(long, global = true, help = "Logs applied operations to a JSON file")]
+    pub log_ops: Option<String>,
 }
 
 #[derive(Subcommand)]
 pub enum TopLevelCommand {
     #[command(subcommand)]
     Img(ImgCommand),
+
+    #[command(about = "Times a fixed suite of operations on the input image")]
+    Bench { filepath_in: String },
+
+    #[command(about = "Lists the presets available to img preset")]
+    Presets,
 }
 
-#[derive(Subcommand)]
+#[derive(Subcommand, Clone)]
 pub enum ImgCommand {
     #[command(about = "Gets pertinent information about the input image")]
     Info { filepath_in: String },
 
+    #[command(about = "Prints per-channel intensity counts as CSV, or writes them to a file")]
+    Histogram {
+        filepath_in: String,
+
+        #[arg(long, required = false)]
+        out: Option<String>,
+    },
+
+    #[command(about = "Prints the image as Unicode braille characters")]
+    Braille {
+        filepath_in: String,
+
+        #[arg(long, help = "Characters per line, 80 by default")]
+        width: Option<usize>,
+
+        #[arg(long, help = "Pixels darker than this raise a dot, 128 by default")]
+        threshold: Option<u8>,
+    },
+
+    #[command(about = "Prints the image as ASCII characters graded by brightness")]
+    Preview {
+        filepath_in: String,
+
+        #[arg(long, help = "Most characters per line, 80 by default")]
+        cols: Option<usize>,
+    },
+
     #[command(about = "Resizes the image to the new height and width")]
     Resize {
         filepath_in: String,
         filepath_out: String,
         new_width: usize,
         new_height: usize,
-        method: ScaleMethod,
+        #[arg(help = "Method for axes that grow, defaulting to bilinear")]
+        method: Option<ScaleMethod>,
 
         #[arg(long, required = false)]
         crop_x: Option<CropMethod>,
 
         #[arg(long, required = false)]
         crop_y: Option<CropMethod>,
+
+        #[arg(long, required = false)]
+        round_to: Option<usize>,
+
+        #[arg(long, help = "Derives a dimension given as 0 from the other")]
+        preserve_aspect: bool,
+
+        #[arg(
+            long,
+            value_name = "AMOUNT",
+            help = "Unsharp masks by AMOUNT after a downscale, where 0.5 is mild"
+        )]
+        sharpen_after: Option<f64>,
+
+        #[arg(
+            long,
+            value_name = "BOOL",
+            default_value_t = true,
+            action = ArgAction::Set,
+            help = "Blends RGBA color premultiplied by alpha so transparent edges leave no halo"
+        )]
+        premultiply: bool,
     },
 
     #[command(about = "Scales the image up to the new height and width")]
@@ -50,6 +123,55 @@ pub enum ImgCommand {
 
         #[arg(long, required = false)]
         method: Option<ScaleMethod>,
+
+        #[arg(long, help = "Method for axes that grow, overriding --method")]
+        up_method: Option<ScaleMethod>,
+
+        #[arg(long, help = "Method for axes that shrink, overriding --method")]
+        down_method: Option<ScaleMethod>,
+
+        #[arg(long, required = false)]
+        round_to: Option<usize>,
+
+        #[arg(long, help = "Interpolates in linear light instead of sRGB")]
+        linear: bool,
+
+        #[arg(long, help = "Derives a dimension given as 0 from the other")]
+        preserve_aspect: bool,
+
+        #[arg(
+            long,
+            value_name = "AMOUNT",
+            help = "Unsharp masks by AMOUNT after a downscale, where 0.5 is mild"
+        )]
+        sharpen_after: Option<f64>,
+
+        #[arg(
+            long,
+            value_name = "BOOL",
+            default_value_t = true,
+            action = ArgAction::Set,
+            help = "Blends RGBA color premultiplied by alpha so transparent edges leave no halo"
+        )]
+        premultiply: bool,
+    },
+
+    #[command(about = "Blurs the image with a fast approximate Gaussian")]
+    Blur {
+        filepath_in: String,
+        filepath_out: String,
+        sigma: f64,
+    },
+
+    #[command(about = "Shrinks the image to fit within the box, never enlarging it")]
+    FitWithin {
+        filepath_in: String,
+        filepath_out: String,
+        max_width: usize,
+        max_height: usize,
+
+        #[arg(long, help = "Shrinks each axis to its limit independently")]
+        ignore_aspect: bool,
     },
 
     #[command(about = "Crops the image down to the new height and width")]
@@ -64,6 +186,122 @@ pub enum ImgCommand {
         center_x: Option<usize>,
         #[arg(long, required = false)]
         center_y: Option<usize>,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_name = "FX,FY",
+            conflicts_with_all = ["method", "center_x", "center_y"],
+            help = "Focus point as fractions of the size, like 0.7,0.3"
+        )]
+        focus: Option<Vec<f64>>,
+    },
+
+    #[command(about = "Crops to the rectangle with the given top-left corner and size")]
+    CropRect {
+        filepath_in: String,
+        filepath_out: String,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+
+    #[command(about = "Pastes a second image over the input with its top-left corner at x, y")]
+    Paste {
+        filepath_in: String,
+        overlay_path: String,
+        filepath_out: String,
+        #[arg(long, help = "Column of the overlay's left edge, defaulting to 0")]
+        x: Option<usize>,
+        #[arg(long, help = "Row of the overlay's top edge, defaulting to 0")]
+        y: Option<usize>,
+    },
+
+    #[command(about = "Writes a single frame of an animated GIF or WebP")]
+    Frame {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, help = "Zero-based frame to extract, defaulting to the first")]
+        index: Option<usize>,
+    },
+
+    #[command(about = "Tiles several images side by side or stacked into one")]
+    Concat {
+        #[arg(num_args = 2.., required = true)]
+        filepaths_in: Vec<String>,
+        filepath_out: String,
+
+        #[arg(long, value_enum, help = "Layout direction, defaulting to horizontal")]
+        direction: Option<ConcatDirection>,
+
+        #[arg(
+            long,
+            value_name = "#RRGGBB",
+            help = "Color padding images smaller than the largest instead of erroring"
+        )]
+        fill: Option<String>,
+    },
+
+    #[command(about = "Creates a solid-color image of the given size")]
+    New {
+        filepath_out: String,
+        width: usize,
+        height: usize,
+
+        #[arg(long, help = "Color as #RRGGBB or a name like white, black by default")]
+        color: Option<String>,
+    },
+
+    #[command(about = "Recolors every pixel of the image, keeping any transparency")]
+    Fill {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, help = "Color as #RRGGBB or a name like white")]
+        color: String,
+    },
+
+    #[command(about = "Makes pixels near a key color transparent, adding an alpha channel")]
+    Key {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, help = "Key color as #RRGGBB or a name like white")]
+        color: String,
+
+        #[arg(long, help = "8-bit distance from the key to clear, 0 by default")]
+        tolerance: Option<usize>,
+    },
+
+    #[command(about = "Composites a transparent image over a solid color or checkerboard")]
+    Flatten {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(
+            long,
+            value_name = "#RRGGBB",
+            help = "Background color, defaulting to white"
+        )]
+        background: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "SIZE",
+            conflicts_with = "background",
+            help = "Draws a gray checkerboard of SIZE pixel squares instead"
+        )]
+        checker: Option<usize>,
+    },
+
+    #[command(about = "Crops the image to the new height and width around its center")]
+    CenterCrop {
+        filepath_in: String,
+        filepath_out: String,
+        new_width: usize,
+        new_height: usize,
     },
 
     #[command(
@@ -75,6 +313,55 @@ pub enum ImgCommand {
         filepath_out: String,
         new_width: usize,
         new_height: usize,
+
+        #[arg(long, required = false)]
+        direction: Option<SeamDirection>,
+
+        #[arg(long, required = false)]
+        energy: Option<EnergyMode>,
+
+        #[arg(long, required = false)]
+        energy_source: Option<EnergySource>,
+
+        #[arg(long, help = "Prints the time spent in each carving phase")]
+        profile: bool,
+
+        #[arg(long, help = "Never cuts where this image is bright")]
+        protect: Option<String>,
+
+        #[arg(long, help = "Cuts first where this image is bright")]
+        remove: Option<String>,
+    },
+
+    #[command(
+        about = "Highlights edges using the Sobel gradient magnitude",
+        alias = "sobel"
+    )]
+    Edges {
+        filepath_in: String,
+        filepath_out: String,
+    },
+
+    #[command(
+        about = "Removes speckle noise by replacing each pixel with its neighborhood median",
+        alias = "median"
+    )]
+    Denoise {
+        filepath_in: String,
+        filepath_out: String,
+        radius: usize,
+    },
+
+    #[command(about = "Renders the gradient energy map seam carving uses as a grayscale image")]
+    Energy {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, required = false)]
+        border: Option<EnergyBorder>,
+
+        #[arg(long, required = false)]
+        source: Option<EnergySource>,
     },
 
     #[command(about = "Multiplies each pixel by the given scalars", alias = "tint")]
@@ -86,6 +373,34 @@ pub enum ImgCommand {
         b_scale: f64,
     },
 
+    #[command(about = "Adjusts brightness by an offset and contrast about the midpoint")]
+    Adjust {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, required = false, allow_hyphen_values = true)]
+        brightness: Option<i32>,
+
+        #[arg(long, required = false)]
+        contrast: Option<f64>,
+    },
+
+    #[command(
+        about = "Inverts every channel to produce a negative",
+        alias = "negative"
+    )]
+    Invert {
+        filepath_in: String,
+        filepath_out: String,
+    },
+
+    #[command(about = "Applies gamma correction, values above 1 brighten the mid-tones")]
+    Gamma {
+        filepath_in: String,
+        filepath_out: String,
+        gamma: f64,
+    },
+
     #[command(
         about = "Applies a hue shift wrapping the given number of degrees",
         alias = "hue"
@@ -94,6 +409,109 @@ pub enum ImgCommand {
         filepath_in: String,
         filepath_out: String,
         degrees: f64,
+
+        #[arg(long, requires = "to", help = "Start of the hue band, in degrees")]
+        from: Option<f64>,
+
+        #[arg(long, requires = "from", help = "End of the hue band, exclusive")]
+        to: Option<f64>,
+    },
+
+    #[command(
+        about = "Converts the image to grayscale using weighted luminance",
+        alias = "gray"
+    )]
+    Grayscale {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, required = false, num_args = 3, value_names = ["R", "G", "B"])]
+        weights: Option<Vec<f64>>,
+    },
+
+    #[command(about = "Exchanges two color channels, such as red and blue for BGR sources")]
+    SwapChannels {
+        filepath_in: String,
+        filepath_out: String,
+        a: Channel,
+        b: Channel,
+    },
+
+    #[command(about = "Writes one color channel as a grayscale image")]
+    ExtractChannel {
+        filepath_in: String,
+        filepath_out: String,
+        channel: Channel,
+    },
+
+    #[command(about = "Tones the image with the classic sepia matrix")]
+    Sepia {
+        filepath_in: String,
+        filepath_out: String,
+    },
+
+    #[command(about = "Grades the image through a 3D LUT read from a .cube file")]
+    Lut {
+        filepath_in: String,
+        filepath_out: String,
+        lut_path: String,
+    },
+
+    #[command(about = "Reduces each channel to the given number of evenly spaced levels")]
+    Posterize {
+        filepath_in: String,
+        filepath_out: String,
+        levels: usize,
+    },
+
+    #[command(about = "Turns pixels at or above the luminance level white and the rest black")]
+    Threshold {
+        filepath_in: String,
+        filepath_out: String,
+        level: usize,
+    },
+
+    #[command(about = "Boosts the saturation of muted colors more than vivid ones")]
+    Vibrance {
+        filepath_in: String,
+        filepath_out: String,
+        amount: f64,
+    },
+
+    #[command(
+        about = "Multiplies the saturation of every pixel by the given factor",
+        alias = "sat"
+    )]
+    Saturation {
+        filepath_in: String,
+        filepath_out: String,
+        factor: f64,
+    },
+
+    #[command(
+        about = "Simulates how the image looks with a color vision deficiency",
+        alias = "colorblind"
+    )]
+    Cvd {
+        filepath_in: String,
+        filepath_out: String,
+        kind: CvdType,
+
+        #[arg(long, help = "Corrects for the deficiency (daltonize) instead")]
+        correct: bool,
+    },
+
+    #[command(about = "Reduces each channel to the given number of levels with dithering")]
+    Dither {
+        filepath_in: String,
+        filepath_out: String,
+        levels: usize,
+
+        #[arg(long, required = false)]
+        method: Option<DitherMethod>,
+
+        #[arg(long, required = false)]
+        matrix_size: Option<usize>,
     },
 
     #[command(
@@ -144,12 +562,317 @@ pub enum ImgCommand {
         filepath_out: String,
     },
 
+    #[command(about = "Rotates and mirrors the image upright according to its EXIF orientation")]
+    Autorotate {
+        filepath_in: String,
+        filepath_out: String,
+    },
+
+    #[command(about = "Renders the image's dominant colors as a row of swatches")]
+    Palette {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long, required = false)]
+        colors: Option<usize>,
+    },
+
+    #[command(about = "Replaces square blocks of the image with their average color")]
+    Pixelate {
+        filepath_in: String,
+        filepath_out: String,
+        block_size: usize,
+
+        #[arg(long, help = "Only pixelates where this image is bright")]
+        mask: Option<String>,
+    },
+
+    #[command(about = "Mirrors a wedge of the image around its center into a symmetric pattern")]
+    Kaleidoscope {
+        filepath_in: String,
+        filepath_out: String,
+        segments: usize,
+    },
+
+    #[command(about = "Applies a sequence of operations while decoding and encoding only once")]
+    Pipeline {
+        filepath_in: String,
+        filepath_out: String,
+
+        #[arg(long = "op", required = true, help = "Spec like scale:640,480")]
+        ops: Vec<String>,
+    },
+
+    #[command(about = "Applies a named bundle of operations, see snap presets")]
+    Preset {
+        name: String,
+        filepath_in: String,
+        filepath_out: String,
+    },
+
+    #[command(about = "Re-applies the operations recorded by --log-ops")]
+    Replay { sidecar: String },
+
     #[command(
         about = "Converts any supported image to the output file specified",
         alias = "save"
     )]
     Convert {
         filepath_in: String,
-        filepath_out: String,
+        filepath_out: Option<String>,
+
+        #[arg(long, required = false, value_parser = clap::value_parser!(u8).range(1..=100))]
+        quality: Option<u8>,
+
+        #[arg(long, help = "Prints the encoded size in bytes")]
+        measure: bool,
+
+        #[arg(
+            long,
+            value_name = "SIZE",
+            help = "Flattens transparency onto a gray checkerboard of SIZE pixel squares"
+        )]
+        checker: Option<usize>,
     },
 }
+
+impl ImgCommand {
+    /// The input path batch mode may expand and, for commands that write a file, the output path.
+    /// New reads no input and Concat expands its own inputs, so neither has one here
+    pub fn paths_mut(&mut self) -> (Option<&mut String>, Option<&mut String>) {
+        match self {
+            Self::Info { filepath_in } => (Some(filepath_in), None),
+            Self::Braille { filepath_in, .. } => (Some(filepath_in), None),
+            Self::Preview { filepath_in, .. } => (Some(filepath_in), None),
+            Self::Histogram { filepath_in, out } => (Some(filepath_in), out.as_mut()),
+            Self::Replay { sidecar } => (Some(sidecar), None),
+            Self::New { filepath_out, .. } => (None, Some(filepath_out)),
+            Self::Concat { filepath_out, .. } => (None, Some(filepath_out)),
+            Self::Resize {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Scale {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::FitWithin {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Blur {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Crop {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::CropRect {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Paste {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Frame {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Fill {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Key {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Flatten {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::CenterCrop {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::SeamCarve {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Edges {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Energy {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::ScaleRGB {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Adjust {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Invert {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Gamma {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::HueShift {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Grayscale {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::SwapChannels {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::ExtractChannel {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Sepia {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Lut {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Posterize {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Threshold {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Vibrance {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Saturation {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Cvd {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Dither {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::RotateLeft {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::RotateRight {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Flip {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::MirrorX {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::MirrorY {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Transpose {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Autorotate {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Palette {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Denoise {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Pixelate {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Kaleidoscope {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Pipeline {
+                filepath_in,
+                filepath_out,
+                ..
+            }
+            | Self::Preset {
+                filepath_in,
+                filepath_out,
+                ..
+            } => (Some(filepath_in), Some(filepath_out)),
+            Self::Convert {
+                filepath_in,
+                filepath_out,
+                ..
+            } => (Some(filepath_in), filepath_out.as_mut()),
+        }
+    }
+}
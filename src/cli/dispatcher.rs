@@ -1,142 +1,1028 @@
+use crate::cli::batch::expand_batch;
 use crate::cli::commands::*;
+use crate::cli::errors;
+use crate::cli::sidecar::{self, SidecarEntry};
+use crate::img::ascii::DEFAULT_PREVIEW_COLS;
+use crate::img::bench::run_suite;
+use crate::img::braille::DEFAULT_BRAILLE_THRESHOLD;
+use crate::img::concat::ConcatDirection;
+use crate::img::crop::CropMethod;
+use crate::img::dither::DitherMethod;
+use crate::img::histogram::histogram_csv;
 use crate::img::image::Image;
-use crate::img::io::{convert, info};
+use crate::img::io::{
+    EncodeOptions, PPMFormat, STDIO_PATH, convert_with, format_from_extension, frame_from_file,
+    infer_type, info, read_orientation, write_atomic,
+};
+use crate::img::lut::Lut3D;
+use crate::img::matrix::Matrix;
+use crate::img::pipeline::Operation;
+use crate::img::presets::{self, PRESETS};
+use crate::img::random;
+use crate::img::scale::ScaleMethod;
+use crate::img::seam::{EnergyMode, EnergySource, SeamDirection, SeamOptions, SeamProfile};
+use crate::img::utils::{PixelRGB, clamp_channel, round_down_to};
 
 use clap::Parser;
+use image::ImageFormat;
 
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    if let Some(seed) = cli.seed {
+        random::set_seed(seed);
+    }
+
+    let json_errors = cli.json_errors;
+    let log_ops = cli.log_ops;
+    let result = cli
+        .format
+        .as_deref()
+        .map(format_from_extension)
+        .transpose()
+        .and_then(|format| dispatch(cli.command, format, log_ops.as_deref()));
+    if let Err(error) = &result
+        && json_errors
+    {
+        eprintln!("{}", errors::to_json(error.as_ref()));
+        std::process::exit(1);
+    }
+    result
+}
 
-    match cli.command {
-        TopLevelCommand::Img(img_cmd) => match img_cmd {
-            ImgCommand::Info { filepath_in } => {
-                _ = info(&filepath_in, true)?;
+/// Runs the parsed top level command
+fn dispatch(
+    command: TopLevelCommand,
+    format: Option<ImageFormat>,
+    log_ops: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        TopLevelCommand::Img(img_cmd) => {
+            let mut entries = Vec::new();
+            for img_cmd in expand_batch(img_cmd)? {
+                if log_ops.is_some() {
+                    entries.push(sidecar_entry(&img_cmd)?);
+                }
+                run_img(img_cmd, format)?;
             }
-            ImgCommand::Resize {
-                filepath_in,
-                filepath_out,
-                new_width,
-                new_height,
-                method,
-                crop_x,
-                crop_y,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.resize(new_width, new_height, method, crop_x, crop_y);
-                i.save(&filepath_out)?;
+            if let Some(path) = log_ops {
+                sidecar::write(path, &entries)?;
             }
-            ImgCommand::Scale {
-                filepath_in,
-                filepath_out,
-                new_width,
-                new_height,
-                method,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.scale(
-                    new_width,
-                    new_height,
-                    method.unwrap_or(crate::img::scale::ScaleMethod::Bilinear),
-                );
-                i.save(&filepath_out)?;
+        }
+        TopLevelCommand::Bench { filepath_in } => {
+            let i = Image::from_file(&filepath_in)?;
+            println!("Benchmarking {}x{} {}", i.width, i.height, filepath_in);
+            for result in run_suite(&i) {
+                println!("  {}", result);
+            }
+        }
+        TopLevelCommand::Presets => {
+            for preset in PRESETS {
+                println!("{:<16}{}", preset.name, preset.description);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The paths and operations of the command, for commands that can be recorded with --log-ops
+fn sidecar_entry(img_cmd: &ImgCommand) -> Result<SidecarEntry, Box<dyn std::error::Error>> {
+    let operations = recorded_operations(img_cmd)?;
+    let mut img_cmd = img_cmd.clone();
+    let (Some(input), Some(output)) = img_cmd.paths_mut() else {
+        return Err("Only commands that read and write one image can be recorded".into());
+    };
+
+    Ok(SidecarEntry {
+        input: input.clone(),
+        output: output.clone(),
+        operations,
+    })
+}
+
+/// The pipeline operations reproducing the command. Options that no operation expresses are
+/// rejected rather than silently dropped from the log
+fn recorded_operations(img_cmd: &ImgCommand) -> Result<Vec<Operation>, Box<dyn std::error::Error>> {
+    let unrecordable = |option: &str| -> Result<Vec<Operation>, Box<dyn std::error::Error>> {
+        Err(format!("{} cannot be recorded with --log-ops", option).into())
+    };
+
+    let operation = match img_cmd {
+        ImgCommand::Pipeline { ops, .. } => {
+            return ops.iter().map(|op| op.parse()).collect();
+        }
+        ImgCommand::Preset { name, .. } => return Ok(presets::find(name)?.operations()),
+        ImgCommand::Resize {
+            new_width,
+            new_height,
+            method,
+            crop_x,
+            crop_y,
+            round_to,
+            preserve_aspect,
+            sharpen_after,
+            premultiply,
+            ..
+        } => {
+            if !matches!(method, None | Some(ScaleMethod::Bilinear)) {
+                return unrecordable("A resize method other than bilinear");
+            }
+            if crop_x.is_some() || crop_y.is_some() {
+                return unrecordable("--crop-x or --crop-y");
+            }
+            if round_to.is_some() || *preserve_aspect {
+                return unrecordable("--round-to or --preserve-aspect");
+            }
+            if sharpen_after.is_some() || !premultiply {
+                return unrecordable("--sharpen-after or --premultiply false");
+            }
+            Operation::Resize {
+                width: *new_width,
+                height: *new_height,
+            }
+        }
+        ImgCommand::Scale {
+            new_width,
+            new_height,
+            method,
+            up_method,
+            down_method,
+            round_to,
+            linear,
+            preserve_aspect,
+            sharpen_after,
+            premultiply,
+            ..
+        } => {
+            if !matches!(method, None | Some(ScaleMethod::Bilinear))
+                || up_method.is_some()
+                || down_method.is_some()
+            {
+                return unrecordable("A scale method other than bilinear");
+            }
+            if round_to.is_some() || *preserve_aspect {
+                return unrecordable("--round-to or --preserve-aspect");
+            }
+            if sharpen_after.is_some() || !premultiply {
+                return unrecordable("--sharpen-after or --premultiply false");
+            }
+            let scale = Operation::Scale {
+                width: *new_width,
+                height: *new_height,
+            };
+            return Ok(match linear {
+                true => vec![Operation::Linear, scale, Operation::Srgb],
+                false => vec![scale],
+            });
+        }
+        ImgCommand::Blur { sigma, .. } => Operation::Blur { sigma: *sigma },
+        ImgCommand::FitWithin {
+            max_width,
+            max_height,
+            ignore_aspect,
+            ..
+        } => {
+            if *ignore_aspect {
+                return unrecordable("--ignore-aspect");
+            }
+            Operation::FitWithin {
+                width: *max_width,
+                height: *max_height,
+            }
+        }
+        ImgCommand::Crop {
+            new_width,
+            new_height,
+            method,
+            center_x,
+            center_y,
+            focus,
+            ..
+        } => {
+            if !matches!(method, None | Some(CropMethod::Rectangular))
+                || center_x.is_some()
+                || center_y.is_some()
+                || focus.is_some()
+            {
+                return unrecordable("A crop other than centered");
+            }
+            Operation::Crop {
+                width: *new_width,
+                height: *new_height,
+            }
+        }
+        ImgCommand::CropRect {
+            x,
+            y,
+            width,
+            height,
+            ..
+        } => Operation::CropRect {
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+        },
+        ImgCommand::CenterCrop {
+            new_width,
+            new_height,
+            ..
+        } => Operation::CenterCrop {
+            width: *new_width,
+            height: *new_height,
+        },
+        ImgCommand::SeamCarve {
+            new_width,
+            new_height,
+            direction,
+            energy,
+            energy_source,
+            protect,
+            remove,
+            ..
+        } => {
+            if !matches!(direction, None | Some(SeamDirection::Both))
+                || energy.is_some()
+                || energy_source.is_some()
+            {
+                return unrecordable("--direction, --energy or --energy-source");
+            }
+            if protect.is_some() || remove.is_some() {
+                return unrecordable("--protect or --remove");
+            }
+            Operation::SeamCarve {
+                width: *new_width,
+                height: *new_height,
+            }
+        }
+        ImgCommand::Edges { .. } => Operation::Edges,
+        ImgCommand::Denoise { radius, .. } => Operation::Median { radius: *radius },
+        ImgCommand::ScaleRGB {
+            r_scale,
+            g_scale,
+            b_scale,
+            ..
+        } => Operation::ScaleRGB {
+            r: *r_scale,
+            g: *g_scale,
+            b: *b_scale,
+        },
+        ImgCommand::Adjust {
+            brightness,
+            contrast,
+            ..
+        } => Operation::Adjust {
+            brightness: brightness.unwrap_or(0),
+            contrast: contrast.unwrap_or(1.0),
+        },
+        ImgCommand::Invert { .. } => Operation::Invert,
+        ImgCommand::Gamma { gamma, .. } => Operation::Gamma { gamma: *gamma },
+        ImgCommand::HueShift {
+            degrees, from, to, ..
+        } => {
+            if from.is_some() || to.is_some() {
+                return unrecordable("--from and --to");
             }
-            ImgCommand::Crop {
-                filepath_in,
-                filepath_out,
+            Operation::HueShift { degrees: *degrees }
+        }
+        ImgCommand::Grayscale { weights, .. } => {
+            if weights.is_some() {
+                return unrecordable("--weights");
+            }
+            Operation::Grayscale
+        }
+        ImgCommand::Sepia { .. } => Operation::Sepia,
+        ImgCommand::Posterize { levels, .. } => Operation::Posterize { levels: *levels },
+        ImgCommand::Threshold { level, .. } => Operation::Threshold { level: *level },
+        ImgCommand::Vibrance { amount, .. } => Operation::Vibrance { amount: *amount },
+        ImgCommand::Saturation { factor, .. } => Operation::Saturation { factor: *factor },
+        ImgCommand::Dither {
+            levels,
+            matrix_size,
+            ..
+        } => {
+            if !matches!(matrix_size, None | Some(4)) {
+                return unrecordable("A dither matrix size other than 4");
+            }
+            Operation::Dither { levels: *levels }
+        }
+        ImgCommand::RotateLeft { .. } => Operation::RotateLeft,
+        ImgCommand::RotateRight { .. } => Operation::RotateRight,
+        ImgCommand::Flip { .. } => Operation::Flip,
+        ImgCommand::MirrorX { .. } => Operation::MirrorX,
+        ImgCommand::MirrorY { .. } => Operation::MirrorY,
+        ImgCommand::Transpose { .. } => Operation::Transpose,
+        ImgCommand::Pixelate {
+            block_size, mask, ..
+        } => {
+            if mask.is_some() {
+                return unrecordable("--mask");
+            }
+            Operation::Pixelate {
+                block_size: *block_size,
+            }
+        }
+        ImgCommand::Kaleidoscope { segments, .. } => Operation::Kaleidoscope {
+            segments: *segments,
+        },
+        _ => return Err("Only commands that transform a single image can be recorded".into()),
+    };
+
+    Ok(vec![operation])
+}
+
+/// Runs a single image command against the files it names
+fn run_img(
+    img_cmd: ImgCommand,
+    format: Option<ImageFormat>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match img_cmd {
+        ImgCommand::Info { filepath_in } => {
+            _ = info(&filepath_in, true)?;
+        }
+        ImgCommand::Histogram { filepath_in, out } => {
+            let csv = histogram_csv(&Image::from_file(&filepath_in)?.histogram());
+            match out {
+                Some(path) => write_atomic(&path, csv.as_bytes())?,
+                None => print!("{}", csv),
+            }
+        }
+        ImgCommand::Braille {
+            filepath_in,
+            width,
+            threshold,
+        } => {
+            let i = Image::from_file(&filepath_in)?;
+            let threshold = threshold.unwrap_or(DEFAULT_BRAILLE_THRESHOLD);
+            print!("{}", i.to_braille_with(width.unwrap_or(80), threshold));
+        }
+        ImgCommand::Preview { filepath_in, cols } => {
+            let i = Image::from_file(&filepath_in)?;
+            print!("{}", i.ascii_preview(cols.unwrap_or(DEFAULT_PREVIEW_COLS)));
+        }
+        ImgCommand::Resize {
+            filepath_in,
+            filepath_out,
+            new_width,
+            new_height,
+            method,
+            crop_x,
+            crop_y,
+            round_to,
+            preserve_aspect,
+            sharpen_after,
+            premultiply,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            let (original_width, original_height) = (i.width, i.height);
+            let (new_width, new_height) = if preserve_aspect {
+                i.aspect_size(new_width, new_height)?
+            } else {
+                (new_width, new_height)
+            };
+            let round = |v| round_to.map_or(v, |n| round_down_to(v, n));
+            let (new_width, new_height) = (round(new_width), round(new_height));
+            i.resize(new_width, new_height, method, crop_x, crop_y, premultiply)?;
+            if let Some(amount) = sharpen_after {
+                i.sharpen_after_downscale(original_width, original_height, amount)?;
+            }
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Scale {
+            filepath_in,
+            filepath_out,
+            new_width,
+            new_height,
+            method,
+            up_method,
+            down_method,
+            round_to,
+            linear,
+            preserve_aspect,
+            sharpen_after,
+            premultiply,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            let (original_width, original_height) = (i.width, i.height);
+            let (new_width, new_height) = if preserve_aspect {
+                i.aspect_size(new_width, new_height)?
+            } else {
+                (new_width, new_height)
+            };
+            let round = |v| round_to.map_or(v, |n| round_down_to(v, n));
+            let (new_width, new_height) = (round(new_width), round(new_height));
+            let encoded_max_intensity = i.max_intensity;
+            if linear {
+                i.to_linear();
+            }
+            let method = method.unwrap_or_default();
+            i.scale_per_axis(
                 new_width,
                 new_height,
-                method,
-                center_x,
-                center_y,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.crop(
+                up_method.unwrap_or(method.clone()),
+                down_method.unwrap_or(method),
+                premultiply,
+            );
+            if linear {
+                i.to_srgb();
+                i.rescale_intensity(encoded_max_intensity);
+            }
+            if let Some(amount) = sharpen_after {
+                i.sharpen_after_downscale(original_width, original_height, amount)?;
+            }
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Blur {
+            filepath_in,
+            filepath_out,
+            sigma,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.fast_gaussian(sigma)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::FitWithin {
+            filepath_in,
+            filepath_out,
+            max_width,
+            max_height,
+            ignore_aspect,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.fit_within(max_width, max_height, !ignore_aspect);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Crop {
+            filepath_in,
+            filepath_out,
+            new_width,
+            new_height,
+            method,
+            center_x,
+            center_y,
+            focus,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            match focus.as_deref() {
+                Some(&[fx, fy]) => i.crop_focus(new_width, new_height, fx, fy)?,
+                Some(_) => return Err("--focus takes exactly two fractions, like 0.7,0.3".into()),
+                None => i.crop(
                     new_width,
                     new_height,
-                    method.unwrap_or(crate::img::crop::CropMethod::Rectangular),
+                    method.unwrap_or(CropMethod::Rectangular),
                     center_x,
                     center_y,
-                );
-                i.save(&filepath_out)?;
+                ),
             }
-            ImgCommand::SeamCarve {
-                filepath_in,
-                filepath_out,
-                new_width,
-                new_height,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.seam_carve(new_width, new_height);
-                i.save(&filepath_out)?;
-            }
-            ImgCommand::ScaleRGB {
-                filepath_in,
-                filepath_out,
-                r_scale,
-                g_scale,
-                b_scale,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.scale_rgb(r_scale, g_scale, b_scale)?;
-                i.save(&filepath_out)?;
-            }
-            ImgCommand::HueShift {
-                filepath_in,
-                filepath_out,
-                degrees,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.hue_shift(degrees)?;
-                i.save(&filepath_out)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::CropRect {
+            filepath_in,
+            filepath_out,
+            x,
+            y,
+            width,
+            height,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.crop_rect(width, height, x, y)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Paste {
+            filepath_in,
+            overlay_path,
+            filepath_out,
+            x,
+            y,
+        } => {
+            let overlay = Image::from_file(&overlay_path)?;
+            let mut i = Image::from_file(&filepath_in)?;
+            i.paste(&overlay, x.unwrap_or(0), y.unwrap_or(0));
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Frame {
+            filepath_in,
+            filepath_out,
+            index,
+        } => {
+            let frame = frame_from_file(&filepath_in, index.unwrap_or(0))?;
+            frame.image.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Concat {
+            filepaths_in,
+            filepath_out,
+            direction,
+            fill,
+        } => {
+            let images = filepaths_in
+                .iter()
+                .map(|path| Image::from_file(path))
+                .collect::<Result<Vec<_>, _>>()?;
+            let max_intensity = images.iter().map(|i| i.max_intensity).max().unwrap_or(0);
+            let fill = match fill {
+                Some(hex) => Some(parse_color(&hex, max_intensity)?),
+                None => None,
+            };
+            let direction = direction.unwrap_or(ConcatDirection::Horizontal);
+            let i = Image::concat(&images, direction, fill)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::New {
+            filepath_out,
+            width,
+            height,
+            color,
+        } => {
+            let mut i = Image::new(width, height, 255, PPMFormat::P6);
+            let color = color.as_deref().unwrap_or("black");
+            i.fill(parse_color(color, i.max_intensity)?);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Fill {
+            filepath_in,
+            filepath_out,
+            color,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.fill(parse_color(&color, i.max_intensity)?);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Key {
+            filepath_in,
+            filepath_out,
+            color,
+            tolerance,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            let key = parse_color(&color, i.max_intensity)?;
+            let tolerance = to_intensity(i.max_intensity, tolerance.unwrap_or(0));
+            i.color_to_alpha(key, tolerance);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Flatten {
+            filepath_in,
+            filepath_out,
+            background,
+            checker,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            match checker {
+                Some(size) => flatten_onto_checker(&mut i, size),
+                None => {
+                    let hex = background.as_deref().unwrap_or("#ffffff");
+                    i.flatten(parse_color(hex, i.max_intensity)?);
+                }
             }
-            ImgCommand::RotateLeft {
-                filepath_in,
-                filepath_out,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.rotate_left();
-                i.save(&filepath_out)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::CenterCrop {
+            filepath_in,
+            filepath_out,
+            new_width,
+            new_height,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.center_crop(new_width, new_height)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::SeamCarve {
+            filepath_in,
+            filepath_out,
+            new_width,
+            new_height,
+            direction,
+            energy,
+            energy_source,
+            profile,
+            protect,
+            remove,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            if protect.is_some() || remove.is_some() {
+                if matches!(energy, Some(EnergyMode::Forward))
+                    || matches!(energy_source, Some(EnergySource::Luminance))
+                {
+                    return Err("Seam carving masks only support backward RGB energy".into());
+                }
+                let load_mask = |path: Option<String>| match path {
+                    Some(path) => Image::from_file(&path).map(|mask| mask.to_mask()),
+                    None => Ok(Matrix::new_filled(i.width, i.height, false)),
+                };
+                let (protect, remove) = (load_mask(protect)?, load_mask(remove)?);
+                let (new_width, new_height) = match direction.unwrap_or(SeamDirection::Both) {
+                    SeamDirection::Vertical => (new_width, i.height),
+                    SeamDirection::Horizontal => (i.width, new_height),
+                    SeamDirection::Both => (new_width, new_height),
+                };
+                i.seam_carve_masked(new_width, new_height, &protect, &remove)?;
+            } else {
+                let options = SeamOptions {
+                    mode: energy.unwrap_or_default(),
+                    source: energy_source.unwrap_or_default(),
+                    ..Default::default()
+                };
+                let mut timings = SeamProfile::default();
+                i.seam_carve_directed(
+                    new_width,
+                    new_height,
+                    direction.unwrap_or(SeamDirection::Both),
+                    &options,
+                    profile.then_some(&mut timings),
+                )?;
+                if profile {
+                    print!("{}", timings);
+                }
             }
-            ImgCommand::RotateRight {
-                filepath_in,
-                filepath_out,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.rotate_right();
-                i.save(&filepath_out)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Edges {
+            filepath_in,
+            filepath_out,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.sobel();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Denoise {
+            filepath_in,
+            filepath_out,
+            radius,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.median_filter(radius);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Energy {
+            filepath_in,
+            filepath_out,
+            border,
+            source,
+        } => {
+            let i = Image::from_file(&filepath_in)?;
+            i.energy_visualization_with(border.unwrap_or_default(), source.unwrap_or_default())
+                .save_as(&filepath_out, format)?;
+        }
+        ImgCommand::ScaleRGB {
+            filepath_in,
+            filepath_out,
+            r_scale,
+            g_scale,
+            b_scale,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.scale_rgb(r_scale, g_scale, b_scale)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Adjust {
+            filepath_in,
+            filepath_out,
+            brightness,
+            contrast,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.adjust(brightness.unwrap_or(0), contrast.unwrap_or(1.0));
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Invert {
+            filepath_in,
+            filepath_out,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.invert();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Gamma {
+            filepath_in,
+            filepath_out,
+            gamma,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.gamma(gamma)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::HueShift {
+            filepath_in,
+            filepath_out,
+            degrees,
+            from,
+            to,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            match from.zip(to) {
+                Some((from, to)) => i.hue_shift_range(from, to, degrees),
+                None => i.hue_shift(degrees)?,
             }
-            ImgCommand::Flip {
-                filepath_in,
-                filepath_out,
-            } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.rotate_left();
-                i.rotate_left();
-                i.save(&filepath_out)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Grayscale {
+            filepath_in,
+            filepath_out,
+            weights,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.grayscale(weights.map(|w| (w[0], w[1], w[2])));
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::SwapChannels {
+            filepath_in,
+            filepath_out,
+            a,
+            b,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.swap_channels(a, b);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::ExtractChannel {
+            filepath_in,
+            filepath_out,
+            channel,
+        } => {
+            let i = Image::from_file(&filepath_in)?;
+            i.extract_channel(channel).save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Sepia {
+            filepath_in,
+            filepath_out,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.sepia();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Lut {
+            filepath_in,
+            filepath_out,
+            lut_path,
+        } => {
+            let lut = Lut3D::from_file(&lut_path)?;
+            let mut i = Image::from_file(&filepath_in)?;
+            i.apply_lut3d(&lut);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Posterize {
+            filepath_in,
+            filepath_out,
+            levels,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.posterize(levels)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Threshold {
+            filepath_in,
+            filepath_out,
+            level,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.threshold(level);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Vibrance {
+            filepath_in,
+            filepath_out,
+            amount,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.vibrance(amount);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Saturation {
+            filepath_in,
+            filepath_out,
+            factor,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.adjust_saturation(factor);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Cvd {
+            filepath_in,
+            filepath_out,
+            kind,
+            correct,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            if correct {
+                i.daltonize(kind);
+            } else {
+                i.simulate_cvd(kind);
             }
-            ImgCommand::MirrorX { filepath_in, filepath_out } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.mirror_x();
-                i.save(&filepath_out)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Dither {
+            filepath_in,
+            filepath_out,
+            levels,
+            method,
+            matrix_size,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            match method.unwrap_or(DitherMethod::Ordered) {
+                DitherMethod::Ordered => i.ordered_dither(levels, matrix_size.unwrap_or(4))?,
             }
-            ImgCommand::MirrorY { filepath_in, filepath_out } => {
-                let mut i = Image::from_file(&filepath_in)?;
-                i.mirror_y();
-                i.save(&filepath_out)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::RotateLeft {
+            filepath_in,
+            filepath_out,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.rotate_left();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::RotateRight {
+            filepath_in,
+            filepath_out,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.rotate_right();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Flip {
+            filepath_in,
+            filepath_out,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.rotate_left();
+            i.rotate_left();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::MirrorX { filepath_in, filepath_out } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.mirror_x();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::MirrorY { filepath_in, filepath_out } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.mirror_y();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Transpose { filepath_in, filepath_out } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.transpose();
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Autorotate {
+            filepath_in,
+            filepath_out,
+        } => {
+            // Saving re-encodes the pixels without the original metadata, dropping the tag
+            let orientation = read_orientation(&filepath_in)?;
+            let mut i = Image::from_file(&filepath_in)?;
+            i.apply_orientation(orientation);
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Palette {
+            filepath_in,
+            filepath_out,
+            colors,
+        } => {
+            let i = Image::from_file(&filepath_in)?;
+            let palette = i.palette(colors.unwrap_or(8));
+            Image::from_palette(&palette, i.max_intensity)?.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Pixelate {
+            filepath_in,
+            filepath_out,
+            block_size,
+            mask,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            match mask {
+                Some(mask_path) => {
+                    let mask = Image::from_file(&mask_path)?.to_mask();
+                    i.pixelate_masked(block_size, &mask)?;
+                }
+                None => i.pixelate(block_size)?,
+            }
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Kaleidoscope {
+            filepath_in,
+            filepath_out,
+            segments,
+        } => {
+            let mut i = Image::from_file(&filepath_in)?;
+            i.kaleidoscope(segments)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Pipeline {
+            filepath_in,
+            filepath_out,
+            ops,
+        } => {
+            let operations = ops
+                .iter()
+                .map(|op| op.parse())
+                .collect::<Result<Vec<Operation>, _>>()?;
+            let mut i = Image::from_file(&filepath_in)?;
+            i.apply_pipeline(&operations)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Preset {
+            name,
+            filepath_in,
+            filepath_out,
+        } => {
+            let operations = presets::find(&name)?.operations();
+            let mut i = Image::from_file(&filepath_in)?;
+            i.apply_pipeline(&operations)?;
+            i.save_as(&filepath_out, format)?;
+        }
+        ImgCommand::Replay { sidecar } => {
+            for entry in sidecar::read(&sidecar)? {
+                let mut i = Image::from_file(&entry.input)?;
+                i.apply_pipeline(&entry.operations)?;
+                i.save_as(&entry.output, format)?;
             }
-            ImgCommand::Transpose { filepath_in, filepath_out } => {
+        }
+        ImgCommand::Convert {
+            filepath_in,
+            filepath_out,
+            quality,
+            measure,
+            checker,
+        } => {
+            let options = EncodeOptions {
+                jpeg_quality: quality,
+                ..Default::default()
+            };
+            if measure || checker.is_some() {
                 let mut i = Image::from_file(&filepath_in)?;
-                i.transpose();
-                i.save(&filepath_out)?;
+                if let Some(size) = checker {
+                    flatten_onto_checker(&mut i, size);
+                }
+                let filepath_out = filepath_out.as_deref();
+                if measure {
+                    return measure_encoding(&filepath_in, &i, filepath_out, format, &options);
+                }
+                i.save_with(filepath_out.ok_or("Missing output path")?, format, &options)?;
+                return Ok(());
             }
-            ImgCommand::Convert {
-                filepath_in,
-                filepath_out,
-            } => {
-                convert(&filepath_in, &filepath_out)?;
+
+            let filepath_out = filepath_out.ok_or("Missing output path")?;
+            if filepath_in == STDIO_PATH || filepath_out == STDIO_PATH || format.is_some() {
+                Image::from_file(&filepath_in)?.save_with(&filepath_out, format, &options)?;
+            } else {
+                convert_with(&filepath_in, &filepath_out, &options)?;
             }
-        },
+        }
     }
 
     Ok(())
 }
+
+/// Rescales an 8-bit value given on the command line to the image's max intensity
+fn to_intensity(max_intensity: usize, value: usize) -> usize {
+    clamp_channel(value as f64 * max_intensity as f64 / 255.0, max_intensity)
+}
+
+/// Parses a color name or #RRGGBB given on the command line, rescaled to the max intensity
+fn parse_color(text: &str, max_intensity: usize) -> Result<PixelRGB, Box<dyn std::error::Error>> {
+    let color = match PixelRGB::from_name(text) {
+        Some(color) => color,
+        None => PixelRGB::from_hex(text)?,
+    };
+    let [r, g, b] = [color.r, color.g, color.b].map(|v| to_intensity(max_intensity, v));
+    Ok(PixelRGB { r, g, b })
+}
+
+/// Composites the image over the usual light and mid gray transparency checkerboard
+fn flatten_onto_checker(i: &mut Image, size: usize) {
+    let gray = |value: usize| {
+        let v = to_intensity(i.max_intensity, value);
+        PixelRGB { r: v, g: v, b: v }
+    };
+    let (light, dark) = (gray(204), gray(153));
+    i.flatten_checker(size, light, dark);
+}
+
+/// Encodes the image in memory and reports its size, also saving it when given an output path. The
+/// format comes from --format or else the output path
+fn measure_encoding(
+    filepath_in: &str,
+    i: &Image,
+    filepath_out: Option<&str>,
+    format: Option<ImageFormat>,
+    options: &EncodeOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = match (format, filepath_out) {
+        (Some(format), _) => format,
+        (None, Some(path)) if path != STDIO_PATH => infer_type(path)?,
+        _ => return Err("Measuring needs --format or an output path to choose a format".into()),
+    };
+
+    let size = i.encode(format, options)?.len();
+    if let Some(path) = filepath_out {
+        i.save_with(path, Some(format), options)?;
+    }
+
+    let report = format!("{}: {} bytes as {:?}", filepath_in, size, format);
+    // Stdout already carries the image itself when writing to "-"
+    if filepath_out == Some(STDIO_PATH) {
+        eprintln!("{}", report);
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use crate::img::crop::CropError;
+
+use image::ImageError;
+use serde::Serialize;
+use std::error::Error;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+
+/// Short machine-readable category for an error, based on its concrete type
+pub fn error_kind(error: &(dyn Error + 'static)) -> &'static str {
+    if error.is::<CropError>() {
+        "crop"
+    } else if error.is::<io::Error>() {
+        "io"
+    } else if let Some(error) = error.downcast_ref::<ImageError>() {
+        match error {
+            ImageError::IoError(_) => "io",
+            ImageError::Unsupported(_) => "unsupported",
+            _ => "codec",
+        }
+    } else if error.is::<ParseIntError>() || error.is::<ParseFloatError>() {
+        "parse"
+    } else {
+        "other"
+    }
+}
+
+/// JSON shape of an error reported under --json-errors
+#[derive(Serialize)]
+struct JsonError {
+    error: String,
+    kind: &'static str,
+}
+
+/// Renders the error as a single-line JSON object with error and kind fields
+pub fn to_json(error: &(dyn Error + 'static)) -> String {
+    let report = JsonError {
+        error: error.to_string(),
+        kind: error_kind(error),
+    };
+    serde_json::to_string(&report).expect("error reports always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_errors_escape_quotes_and_control_characters() {
+        let error: Box<dyn Error> = "a \"b\"\\\n\t\u{1}".into();
+        assert_eq!(
+            to_json(error.as_ref()),
+            "{\"error\":\"a \\\"b\\\"\\\\\\n\\t\\u0001\",\"kind\":\"other\"}"
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&to_json(error.as_ref())).unwrap();
+        assert_eq!(parsed["error"], "a \"b\"\\\n\t\u{1}");
+    }
+
+    #[test]
+    fn error_kind_follows_the_concrete_type() {
+        let missing: Box<dyn Error> = io::Error::from(io::ErrorKind::NotFound).into();
+        assert_eq!(error_kind(missing.as_ref()), "io");
+        let parse: Box<dyn Error> = "x".parse::<usize>().unwrap_err().into();
+        assert_eq!(error_kind(parse.as_ref()), "parse");
+        let other: Box<dyn Error> = "plain message".into();
+        assert_eq!(
+            to_json(other.as_ref()),
+            "{\"error\":\"plain message\",\"kind\":\"other\"}"
+        );
+    }
+}
@@ -1,2 +1,5 @@
+pub mod batch;
 pub mod commands;
 pub mod dispatcher;
+pub mod errors;
+pub mod sidecar;
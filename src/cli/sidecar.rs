@@ -0,0 +1,158 @@
+use crate::img::io::write_atomic;
+use crate::img::pipeline::Operation;
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+
+/// The operations applied to produce one output file, as recorded by --log-ops
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SidecarEntry {
+    pub input: String,
+    pub output: String,
+    pub operations: Vec<Operation>,
+}
+
+/// Writes the entries as a JSON array
+pub fn write(path: &str, entries: &[SidecarEntry]) -> Result<(), Box<dyn Error>> {
+    let mut json = serde_json::to_string_pretty(entries)?;
+    json.push('\n');
+    write_atomic(path, json.as_bytes())
+}
+
+/// Reads the entries written by write
+pub fn read(path: &str) -> Result<Vec<SidecarEntry>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid sidecar '{}': {}", path, e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, temp_path};
+
+    fn specs(operations: &[Operation]) -> Vec<String> {
+        operations.iter().map(Operation::to_string).collect()
+    }
+
+    #[test]
+    fn entries_survive_writing_and_reading() {
+        let entries = vec![
+            SidecarEntry {
+                input: "in \"quoted\" \\ dir/\u{e9}t\u{e9}.ppm".to_string(),
+                output: "out\tfile.png".to_string(),
+                operations: ["resize:40,30", "blur:1.5", "crop-rect:1,2,10,8", "invert"]
+                    .iter()
+                    .map(|spec| spec.parse().unwrap())
+                    .collect(),
+            },
+            SidecarEntry {
+                input: "a.ppm".to_string(),
+                output: "b.ppm".to_string(),
+                operations: Vec::new(),
+            },
+        ];
+        let path = temp_path("round-trip.json");
+        write(&path, &entries).unwrap();
+        let read_back = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_back.len(), entries.len());
+        for (read, written) in read_back.iter().zip(&entries) {
+            assert_eq!(read.input, written.input);
+            assert_eq!(read.output, written.output);
+            assert_eq!(specs(&read.operations), specs(&written.operations));
+        }
+    }
+
+    #[test]
+    fn replaying_read_operations_reproduces_the_output() {
+        let entry = SidecarEntry {
+            input: "in.ppm".to_string(),
+            output: "out.ppm".to_string(),
+            operations: ["scale:20,16", "rotate-right", "crop:12,10", "gamma:1.8"]
+                .iter()
+                .map(|spec| spec.parse().unwrap())
+                .collect(),
+        };
+        let path = temp_path("replay.json");
+        write(&path, std::slice::from_ref(&entry)).unwrap();
+        let replayed = read(&path).unwrap().remove(0);
+        fs::remove_file(&path).unwrap();
+
+        let source = noise_image(9, 7, 2028);
+        let mut original = source.clone();
+        original.apply_pipeline(&entry.operations).unwrap();
+        let mut replay = source.clone();
+        replay.apply_pipeline(&replayed.operations).unwrap();
+        assert_eq!(replay, original);
+        assert_eq!((replay.width, replay.height), (12, 10));
+    }
+
+    #[test]
+    fn malformed_sidecars_are_rejected() {
+        for contents in [
+            "{}",
+            "[{\"input\": \"a\"}]",
+            "[{\"input\": \"a\", \"output\": \"b\", \"operations\": [\"swirl\"]}]",
+            "[] trailing",
+            "[\"unterminated",
+            "[{\"input\": \"a\", \"output\": \"b\", \"operations\": [], \"extra\": 1}]",
+            "[{\"input\": \"a\", \"output\": \"b\", \"operations\": [{\"scale\": {\"width\": 2}}]}]",
+            "[{\"input\": \"a\", \"output\": \"b\", \"operations\": [{\"blur\": {\"sigma\": \"1\"}}]}]",
+        ] {
+            let path = temp_path("malformed.json");
+            fs::write(&path, contents).unwrap();
+            assert!(read(&path).is_err(), "{}", contents);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn operations_are_stored_as_structured_json() {
+        let entry = SidecarEntry {
+            input: "in.ppm".to_string(),
+            output: "out.ppm".to_string(),
+            operations: ["scale:20,16", "scale-rgb:1.5,1,0.5", "invert"]
+                .iter()
+                .map(|spec| spec.parse().unwrap())
+                .collect(),
+        };
+        let path = temp_path("structured.json");
+        write(&path, &[entry]).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "input": "in.ppm",
+                "output": "out.ppm",
+                "operations": [
+                    {"scale": {"width": 20, "height": 16}},
+                    {"scale-rgb": {"r": 1.5, "g": 1.0, "b": 0.5}},
+                    "invert"
+                ]
+            }])
+        );
+    }
+
+    #[test]
+    fn hand_written_sidecars_with_escapes_are_read() {
+        let path = temp_path("escapes.json");
+        fs::write(
+            &path,
+            r#"[{"output": "out\u00e9\n.ppm", "input": "a \"b\" \\ c.ppm",
+                 "operations": [{"crop-rect": {"x": 1, "y": 2, "width": 3, "height": 4}}]}]"#,
+        )
+        .unwrap();
+        let entries = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(entries[0].input, "a \"b\" \\ c.ppm");
+        assert_eq!(entries[0].output, "out\u{e9}\n.ppm");
+        assert_eq!(specs(&entries[0].operations), ["crop-rect:1,2,3,4"]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::img::image::*;
+use crate::img::io::PPMFormat;
+use crate::img::utils::clamp_channel;
+
+use ndarray::Array3;
+use std::error::Error;
+
+impl Image {
+    /// Copies the image into a height x width x 3 array of 8-bit RGB, rescaling from
+    /// max_intensity to 255. Alpha is dropped
+    pub fn to_ndarray(&self) -> Array3<u8> {
+        let max = self.max_intensity.max(1) as f64;
+        let channels = [&self.red_channel, &self.green_channel, &self.blue_channel];
+        Array3::from_shape_fn((self.height, self.width, 3), |(row, col, c)| {
+            clamp_channel(channels[c][(row, col)] as f64 * 255.0 / max, 255) as u8
+        })
+    }
+
+    /// Builds an 8-bit image from a height x width x 3 array of RGB, the layout to_ndarray
+    /// produces. Any other number of channels, or an empty array, is an error
+    pub fn from_ndarray(array: &Array3<u8>) -> Result<Image, Box<dyn Error>> {
+        let (height, width, channels) = array.dim();
+        if channels != 3 {
+            return Err(format!(
+                "Expected an array of shape (height, width, 3) but the last axis has {}",
+                channels
+            )
+            .into());
+        }
+        if width == 0 || height == 0 {
+            return Err(
+                format!("Array of shape ({}, {}, 3) holds no pixels", height, width).into(),
+            );
+        }
+
+        let mut image = Image::new(width, height, u8::MAX as usize, PPMFormat::P6);
+        for ((row, col, c), &value) in array.indexed_iter() {
+            let channel = match c {
+                0 => &mut image.red_channel,
+                1 => &mut image.green_channel,
+                _ => &mut image.blue_channel,
+            };
+            channel[(row, col)] = value as usize;
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, solid_image};
+    use crate::img::utils::PixelRGB;
+
+    #[test]
+    fn images_round_trip_through_an_array() {
+        let image = noise_image(7, 4, 2035);
+        let array = image.to_ndarray();
+        assert_eq!(array.dim(), (4, 7, 3));
+        let pixel = image.get_pixel(2, 5).unwrap();
+        assert_eq!(
+            [array[(2, 5, 0)], array[(2, 5, 1)], array[(2, 5, 2)]],
+            [pixel.r as u8, pixel.g as u8, pixel.b as u8]
+        );
+        assert_eq!(Image::from_ndarray(&array).unwrap(), image);
+    }
+
+    #[test]
+    fn deeper_images_are_rescaled_to_eight_bits() {
+        let mut image = solid_image(
+            2,
+            2,
+            PixelRGB {
+                r: 1000,
+                g: 500,
+                b: 0,
+            },
+        );
+        image.max_intensity = 1000;
+        let array = image.to_ndarray();
+        assert!(array.outer_iter().all(|row| {
+            row.outer_iter()
+                .all(|pixel| pixel.to_vec() == [255, 128, 0])
+        }));
+    }
+
+    #[test]
+    fn arrays_of_the_wrong_shape_are_rejected() {
+        for shape in [(4, 5, 4), (4, 5, 1), (0, 5, 3), (4, 0, 3)] {
+            assert!(
+                Image::from_ndarray(&Array3::zeros(shape)).is_err(),
+                "{:?}",
+                shape
+            );
+        }
+    }
+}
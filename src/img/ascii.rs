@@ -0,0 +1,115 @@
+use crate::img::image::*;
+use crate::img::scale::ScaleMethod;
+use crate::img::utils::REC601_WEIGHTS;
+
+use std::fmt;
+
+/// Characters from darkest to brightest
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Terminal character cells are about twice as tall as they are wide
+const CELL_ASPECT: usize = 2;
+
+/// Columns used when an Image is displayed
+pub const DEFAULT_PREVIEW_COLS: usize = 80;
+
+impl Image {
+    /// Renders the image as lines of ASCII characters at most max_cols across, one per resampled
+    /// pixel, picking brighter ramp characters for brighter luminance. Images narrower than
+    /// max_cols keep their width and rows are halved to make up for the tall character cells
+    pub fn ascii_preview(&self, max_cols: usize) -> String {
+        if max_cols == 0 || self.width == 0 || self.height == 0 {
+            return String::new();
+        }
+
+        let cols = max_cols.min(self.width);
+        let rows = (self.height * cols + self.width * CELL_ASPECT / 2) / (self.width * CELL_ASPECT);
+        let rows = rows.max(1);
+        let mut resampled = self.clone();
+        resampled.scale(cols, rows, ScaleMethod::Area);
+
+        let last = (ASCII_RAMP.len() - 1) as f64;
+        let max = self.max_intensity.max(1) as f64;
+        let mut rendered = String::with_capacity(rows * (cols + 1));
+        for row in 0..rows {
+            for col in 0..cols {
+                let pixel = resampled.get_pixel(row, col).unwrap();
+                let luminance = pixel.luminance(REC601_WEIGHTS);
+                let index = (luminance / max * last).round().clamp(0.0, last) as usize;
+                rendered.push(ASCII_RAMP[index] as char);
+            }
+            rendered.push('\n');
+        }
+
+        rendered
+    }
+}
+
+impl fmt::Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ascii_preview(DEFAULT_PREVIEW_COLS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::solid_image;
+    use crate::img::utils::PixelRGB;
+
+    #[test]
+    fn black_is_blank_and_white_is_solid() {
+        let black = solid_image(8, 8, PixelRGB { r: 0, g: 0, b: 0 });
+        assert_eq!(black.ascii_preview(8), "        \n".repeat(4));
+
+        let white = solid_image(
+            8,
+            8,
+            PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+        assert_eq!(white.ascii_preview(8), "@@@@@@@@\n".repeat(4));
+    }
+
+    #[test]
+    fn wide_images_shrink_to_max_cols_keeping_the_aspect() {
+        let mut image = solid_image(40, 20, PixelRGB { r: 0, g: 0, b: 0 });
+        for row in 0..20 {
+            for col in 20..40 {
+                image.set_pixel(
+                    row,
+                    col,
+                    PixelRGB {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    },
+                );
+            }
+        }
+
+        let preview = image.ascii_preview(10);
+        let lines: Vec<&str> = preview.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|&line| line == "     @@@@@"));
+        assert_eq!(image.to_string(), image.ascii_preview(DEFAULT_PREVIEW_COLS));
+        assert_eq!(image.ascii_preview(0), "");
+    }
+
+    #[test]
+    fn gray_picks_the_middle_of_the_ramp() {
+        let gray = solid_image(
+            2,
+            2,
+            PixelRGB {
+                r: 128,
+                g: 128,
+                b: 128,
+            },
+        );
+        assert_eq!(gray.ascii_preview(2), "++\n");
+    }
+}
@@ -0,0 +1,100 @@
+use crate::img::image::*;
+use crate::img::matrix::Matrix;
+use crate::img::scale::ScaleMethod;
+use crate::img::seam::EnergySource;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Time taken by one operation of the benchmark suite
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<16}{:>10.2} ms",
+            self.name,
+            self.elapsed.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// One benchmarked operation, applied in place to a copy of the input
+type BenchOperation = Box<dyn Fn(&mut Image)>;
+
+/// Runs a fixed suite of operations, each on its own copy of the image, and times them
+pub fn run_suite(image: &Image) -> Vec<BenchResult> {
+    let (width, height) = (image.width, image.height);
+    let suite: Vec<(&'static str, BenchOperation)> = vec![
+        (
+            "scale up 2x",
+            Box::new(move |i| i.scale(width * 2, height * 2, ScaleMethod::Bilinear)),
+        ),
+        (
+            "scale down 2x",
+            Box::new(move |i| i.scale(width / 2, height / 2, ScaleMethod::Bilinear)),
+        ),
+        ("edges", Box::new(|i| i.sobel())),
+        ("blur sigma 8", Box::new(|i| i.fast_gaussian(8.0).unwrap())),
+        ("vibrance", Box::new(|i| i.vibrance(0.5))),
+        (
+            "seam carve 10%",
+            Box::new(move |i| i.seam_carve_width(width - width / 10)),
+        ),
+        ("rotate", Box::new(|i| i.rotate_right())),
+        (
+            "energy scalar",
+            Box::new(move |i| {
+                let mut energy = Matrix::new_filled(width, height, 0);
+                i.interior_energy(&mut energy, EnergySource::Rgb);
+            }),
+        ),
+        #[cfg(feature = "simd")]
+        (
+            "energy simd",
+            Box::new(move |i| {
+                let mut energy = Matrix::new_filled(width, height, 0);
+                i.interior_energy_simd(&mut energy, EnergySource::Rgb);
+            }),
+        ),
+    ];
+
+    suite
+        .iter()
+        .map(|(name, operation)| {
+            let mut copy = image.clone();
+            let start = Instant::now();
+            operation(&mut copy);
+            BenchResult {
+                name,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::noise_image;
+
+    #[test]
+    fn suite_reports_one_line_per_operation() {
+        let results = run_suite(&noise_image(20, 12, 2019));
+        let expected = if cfg!(feature = "simd") { 9 } else { 8 };
+        assert_eq!(results.len(), expected);
+
+        for (index, result) in results.iter().enumerate() {
+            assert!(results[..index].iter().all(|r| r.name != result.name));
+            let line = result.to_string();
+            assert!(line.starts_with(result.name));
+            assert!(line.ends_with(" ms"));
+            assert!(!line.contains('\n'));
+        }
+    }
+}
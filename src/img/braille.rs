@@ -0,0 +1,118 @@
+use crate::img::image::*;
+use crate::img::scale::ScaleMethod;
+use crate::img::utils::REC601_WEIGHTS;
+
+/// First code point of the Unicode braille block, the pattern with no dots raised
+const BRAILLE_BASE: u32 = 0x2800;
+
+/// Bit for the dot at each (row, column) of a braille cell, which is 4 rows by 2 columns
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Luminance cutoff used by to_braille, on a 0 to 255 scale
+pub const DEFAULT_BRAILLE_THRESHOLD: u8 = 128;
+
+impl Image {
+    /// Renders the image as lines of braille characters, width characters across
+    pub fn to_braille(&self, width: usize) -> String {
+        self.to_braille_with(width, DEFAULT_BRAILLE_THRESHOLD)
+    }
+
+    /// Renders the image as lines of braille characters, width characters across. Each character
+    /// covers a 2x4 block of the resampled image, raising a dot for every pixel darker than the
+    /// threshold, given on a 0 to 255 scale regardless of max_intensity
+    pub fn to_braille_with(&self, width: usize, threshold: u8) -> String {
+        if width == 0 || self.width == 0 || self.height == 0 {
+            return String::new();
+        }
+
+        // Keep the aspect ratio, rounding the height up to whole 4 pixel cells
+        let dot_width = width * 2;
+        let dot_height = (self.height * dot_width).div_ceil(self.width).max(1);
+        let dot_height = dot_height.div_ceil(4) * 4;
+        let mut resampled = self.clone();
+        resampled.scale(dot_width, dot_height, ScaleMethod::Area);
+
+        let cutoff = threshold as f64 * self.max_intensity as f64 / 255.0;
+        let raised = |row: usize, col: usize| {
+            let pixel = resampled.get_pixel(row, col).unwrap();
+            pixel.luminance(REC601_WEIGHTS) < cutoff
+        };
+
+        let mut rendered = String::with_capacity(dot_height / 4 * (width * 3 + 1));
+        for cell_row in 0..dot_height / 4 {
+            for cell_col in 0..width {
+                let mut pattern = 0;
+                for (dot_row, bits) in BRAILLE_DOTS.iter().enumerate() {
+                    for (dot_col, bit) in bits.iter().enumerate() {
+                        if raised(cell_row * 4 + dot_row, cell_col * 2 + dot_col) {
+                            pattern |= bit;
+                        }
+                    }
+                }
+                rendered.push(char::from_u32(BRAILLE_BASE + pattern).unwrap());
+            }
+            rendered.push('\n');
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::img::image::tests::solid_image;
+    use crate::img::utils::PixelRGB;
+
+    #[test]
+    fn solid_black_raises_every_dot() {
+        let black = solid_image(40, 30, PixelRGB { r: 0, g: 0, b: 0 });
+        let rendered = black.to_braille(10);
+        let lines: Vec<&str> = rendered.lines().collect();
+        // 20 dots across keep the aspect ratio at 15 dots down, rounded up to 4 cells
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| *line == "\u{28ff}".repeat(10)));
+
+        let white = solid_image(
+            40,
+            30,
+            PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+        assert!(
+            white
+                .to_braille(10)
+                .lines()
+                .all(|line| line == "\u{2800}".repeat(10))
+        );
+    }
+
+    #[test]
+    fn each_dark_pixel_raises_its_own_dot() {
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let mut image = solid_image(2, 4, white);
+        image.set_pixel(0, 0, PixelRGB { r: 0, g: 0, b: 0 });
+        image.set_pixel(3, 1, PixelRGB { r: 0, g: 0, b: 0 });
+        assert_eq!(image.to_braille(1), "\u{2881}\n");
+
+        // Mid gray only raises dots once the threshold passes its luminance
+        let gray = solid_image(
+            2,
+            4,
+            PixelRGB {
+                r: 100,
+                g: 100,
+                b: 100,
+            },
+        );
+        assert_eq!(gray.to_braille_with(1, 100), "\u{2800}\n");
+        assert_eq!(gray.to_braille_with(1, 101), "\u{28ff}\n");
+        assert_eq!(gray.to_braille(0), "");
+    }
+}
@@ -0,0 +1,185 @@
+use crate::img::image::*;
+use crate::img::matrix::Matrix;
+use crate::img::utils::{PixelRGB, clamp_channel};
+
+use clap::ValueEnum;
+use std::error::Error;
+
+/// Which way concatenated images are laid out, left to right or top to bottom
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum ConcatDirection {
+    Horizontal,
+    Vertical,
+}
+
+impl Image {
+    /// Places the images side by side from left to right. Every image must have the same height
+    pub fn concat_horizontal(images: &[Image]) -> Result<Image, Box<dyn Error>> {
+        Self::concat(images, ConcatDirection::Horizontal, None)
+    }
+
+    /// Stacks the images from top to bottom. Every image must have the same width
+    pub fn concat_vertical(images: &[Image]) -> Result<Image, Box<dyn Error>> {
+        Self::concat(images, ConcatDirection::Vertical, None)
+    }
+
+    /// Tiles the images in the given direction into a new image with the largest max intensity
+    /// among them. Without a fill the images must match across the direction, otherwise smaller
+    /// ones are aligned to the top-left of their slot and the rest is painted opaque with the fill,
+    /// given in the output's max intensity
+    pub fn concat(
+        images: &[Image],
+        direction: ConcatDirection,
+        fill: Option<PixelRGB>,
+    ) -> Result<Image, Box<dyn Error>> {
+        let first = images
+            .first()
+            .ok_or("Concatenating needs at least one image")?;
+        // Length along the direction and span across it
+        let extent = |image: &Image| match direction {
+            ConcatDirection::Horizontal => (image.width, image.height),
+            ConcatDirection::Vertical => (image.height, image.width),
+        };
+
+        let span = images
+            .iter()
+            .map(|image| extent(image).1)
+            .max()
+            .unwrap_or(0);
+        if fill.is_none() && images.iter().any(|image| extent(image).1 != span) {
+            let across = match direction {
+                ConcatDirection::Horizontal => "height",
+                ConcatDirection::Vertical => "width",
+            };
+            return Err(format!("Every image must have the same {} without a fill", across).into());
+        }
+
+        let length = images.iter().map(|image| extent(image).0).sum();
+        let (width, height) = match direction {
+            ConcatDirection::Horizontal => (length, span),
+            ConcatDirection::Vertical => (span, length),
+        };
+        let max_intensity = images
+            .iter()
+            .map(|image| image.max_intensity)
+            .max()
+            .unwrap_or(0);
+        let max = max_intensity.max(1) as f64;
+
+        let mut result = Image::new(width, height, max_intensity, first.format.clone());
+        if let Some(color) = fill {
+            result.red_channel.fill(color.r);
+            result.green_channel.fill(color.g);
+            result.blue_channel.fill(color.b);
+        }
+        if images.iter().any(|image| image.alpha_channel.is_some()) {
+            result.alpha_channel = Some(Matrix::new_filled(width, height, max_intensity));
+        }
+
+        let mut offset = 0;
+        for image in images {
+            let scale = max / image.max_intensity.max(1) as f64;
+            let (row_offset, col_offset) = match direction {
+                ConcatDirection::Horizontal => (0, offset),
+                ConcatDirection::Vertical => (offset, 0),
+            };
+
+            for row in 0..image.height {
+                for col in 0..image.width {
+                    let pixel = image.get_pixel_rgba(row, col).unwrap();
+                    let [r, g, b, a] = [pixel.r, pixel.g, pixel.b, pixel.a]
+                        .map(|value| clamp_channel(value as f64 * scale, max_intensity));
+                    let (row, col) = (row_offset + row, col_offset + col);
+                    result.set_pixel(row, col, PixelRGB { r, g, b });
+                    if let Some(alpha) = &mut result.alpha_channel {
+                        alpha[(row, col)] = a;
+                    }
+                }
+            }
+            offset += extent(image).0;
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, solid_image};
+
+    const RED: PixelRGB = PixelRGB { r: 255, g: 0, b: 0 };
+    const BLUE: PixelRGB = PixelRGB { r: 0, g: 0, b: 255 };
+
+    #[test]
+    fn two_squares_meet_at_the_seam() {
+        let joined =
+            Image::concat_horizontal(&[solid_image(2, 2, RED), solid_image(2, 2, BLUE)]).unwrap();
+        assert_eq!((joined.width, joined.height), (4, 2));
+        for row in 0..2 {
+            assert_eq!(joined.get_pixel(row, 1), Some(RED));
+            assert_eq!(joined.get_pixel(row, 2), Some(BLUE));
+        }
+
+        let stacked =
+            Image::concat_vertical(&[solid_image(2, 2, RED), solid_image(2, 2, BLUE)]).unwrap();
+        assert_eq!((stacked.width, stacked.height), (2, 4));
+        assert_eq!(
+            (stacked.get_pixel(1, 1), stacked.get_pixel(2, 0)),
+            (Some(RED), Some(BLUE))
+        );
+    }
+
+    #[test]
+    fn inputs_are_copied_in_order() {
+        let (left, right) = (noise_image(3, 2, 1), noise_image(2, 2, 2));
+        let joined = Image::concat_horizontal(&[left.clone(), right.clone()]).unwrap();
+        for (row, col, pixel) in joined.iter_pixels() {
+            let expected = match col {
+                0..3 => left.get_pixel(row, col),
+                _ => right.get_pixel(row, col - 3),
+            };
+            assert_eq!(Some(pixel), expected, "({}, {})", row, col);
+        }
+    }
+
+    #[test]
+    fn mismatched_sizes_need_a_fill() {
+        let images = [solid_image(2, 2, RED), solid_image(1, 3, BLUE)];
+        assert!(Image::concat_horizontal(&images).is_err());
+        assert!(Image::concat_vertical(&images).is_err());
+        assert!(Image::concat_horizontal(&[]).is_err());
+
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let padded = Image::concat(&images, ConcatDirection::Horizontal, Some(white)).unwrap();
+        assert_eq!((padded.width, padded.height), (3, 3));
+        assert_eq!(padded.get_pixel(2, 0), Some(white));
+        assert_eq!(padded.get_pixel(2, 1), Some(white));
+        assert_eq!(padded.get_pixel(2, 2), Some(BLUE));
+    }
+
+    #[test]
+    fn shallower_images_are_rescaled_to_the_deepest() {
+        let mut shallow = solid_image(1, 1, PixelRGB { r: 1, g: 0, b: 0 });
+        shallow.max_intensity = 1;
+        let mut deep = solid_image(1, 1, BLUE);
+        deep.max_intensity = 1000;
+
+        let joined = Image::concat_horizontal(&[shallow, deep]).unwrap();
+        assert_eq!(joined.max_intensity, 1000);
+        assert_eq!(
+            joined.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 1000,
+                g: 0,
+                b: 0
+            })
+        );
+        assert_eq!(joined.get_pixel(0, 1), Some(BLUE));
+    }
+}
@@ -2,6 +2,8 @@ use crate::img::image::*;
 use crate::img::matrix::*;
 
 use clap::ValueEnum;
+use std::error::Error;
+use std::fmt;
 
 /// Method to use when cropping image
 #[derive(Debug, Clone, ValueEnum)]
@@ -24,6 +26,110 @@ pub enum CropMethod {
     Rectangular
 }
 
+impl CropMethod {
+    /// Errors unless the method only trims columns, making it usable on the width alone
+    pub fn check_width(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Left | Self::Right | Self::LeftRight => Ok(()),
+            _ => Err(format!(
+                "Crop method {} cannot trim the width, use left, right, or left-right",
+                self.name()
+            )
+            .into()),
+        }
+    }
+
+    /// Errors unless the method only trims rows, making it usable on the height alone
+    pub fn check_height(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Top | Self::Bottom | Self::TopBottom => Ok(()),
+            _ => Err(format!(
+                "Crop method {} cannot trim the height, use top, bottom, or top-bottom",
+                self.name()
+            )
+            .into()),
+        }
+    }
+
+    /// The method's name as written on the command line
+    fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// How to fill the parts of a padded crop that fall outside the image
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum PadMode {
+    Zero,
+    Edge,
+    Reflect,
+    Wrap,
+}
+
+impl PadMode {
+    /// Maps a possibly out of range index onto [0, len), or None when the pixel should be zero
+    fn resolve(self, index: isize, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        if (0..len as isize).contains(&index) {
+            return Some(index as usize);
+        }
+
+        let len = len as isize;
+        match self {
+            Self::Zero => None,
+            Self::Edge => Some(index.clamp(0, len - 1) as usize),
+            Self::Reflect if len == 1 => Some(0),
+            Self::Reflect => {
+                // Mirrors about the edge pixel, so -1 maps to 1 and len maps to len - 2
+                let period = 2 * (len - 1);
+                let folded = index.rem_euclid(period);
+                Some(folded.min(period - folded) as usize)
+            }
+            Self::Wrap => Some(index.rem_euclid(len) as usize),
+        }
+    }
+}
+
+/// Describes which part of a requested crop region falls outside the image, and by how many pixels
+#[derive(Clone, PartialEq)]
+pub enum CropError {
+    Empty,
+    X { excess: usize },
+    Y { excess: usize },
+    Width { excess: usize },
+    Height { excess: usize },
+}
+
+impl fmt::Display for CropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Crop width and height must be nonzero"),
+            Self::X { excess } => write!(f, "Crop x offset exceeds the image width by {}", excess),
+            Self::Y { excess } => write!(f, "Crop y offset exceeds the image height by {}", excess),
+            Self::Width { excess } => {
+                write!(f, "Crop width extends past the right edge by {}", excess)
+            }
+            Self::Height { excess } => {
+                write!(f, "Crop height extends past the bottom edge by {}", excess)
+            }
+        }
+    }
+}
+
+/// Matches Display, since main reports returned errors through Debug
+impl fmt::Debug for CropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for CropError {}
+
 impl Image {
     pub fn crop_left(&mut self, new_width: usize) {
         if new_width >= self.width || new_width == 0 {
@@ -31,19 +137,17 @@ impl Image {
         }
 
         let cols_to_trim = self.width - new_width;
+        let height = self.height;
 
-        for row in 0..self.height {
-            for col in 0..new_width {
-                self.red_channel[(row, col)] = self.red_channel[(row, col + cols_to_trim)];
-                self.green_channel[(row, col)] = self.green_channel[(row, col + cols_to_trim)];
-                self.blue_channel[(row, col)] = self.blue_channel[(row, col + cols_to_trim)];
+        for channel in self.all_channels_mut() {
+            for row in 0..height {
+                for col in 0..new_width {
+                    channel[(row, col)] = channel[(row, col + cols_to_trim)];
+                }
             }
+            channel.trim_width(new_width);
         }
-
         self.width = new_width;
-        self.red_channel.trim_width(new_width);
-        self.green_channel.trim_width(new_width);
-        self.blue_channel.trim_width(new_width);
     }
 
     pub fn crop_right(&mut self, new_width: usize) {
@@ -52,9 +156,9 @@ impl Image {
         }
 
         self.width = new_width;
-        self.red_channel.trim_width(new_width);
-        self.green_channel.trim_width(new_width);
-        self.blue_channel.trim_width(new_width);
+        for channel in self.all_channels_mut() {
+            channel.trim_width(new_width);
+        }
     }
 
     pub fn crop_top(&mut self, new_height: usize) {
@@ -63,19 +167,17 @@ impl Image {
         }
 
         let rows_to_trim = self.height - new_height;
+        let width = self.width;
 
-        for row in 0..new_height {
-            for col in 0..self.width {
-                self.red_channel[(row, col)] = self.red_channel[(row + rows_to_trim, col)];
-                self.green_channel[(row, col)] = self.green_channel[(row + rows_to_trim, col)];
-                self.blue_channel[(row, col)] = self.blue_channel[(row + rows_to_trim, col)];
+        for channel in self.all_channels_mut() {
+            for row in 0..new_height {
+                for col in 0..width {
+                    channel[(row, col)] = channel[(row + rows_to_trim, col)];
+                }
             }
+            channel.trim_height(new_height);
         }
-
         self.height = new_height;
-        self.red_channel.datum.truncate(new_height * self.width);
-        self.green_channel.datum.truncate(new_height * self.width);
-        self.blue_channel.datum.truncate(new_height * self.width);
     }
 
     pub fn crop_bottom(&mut self, new_height: usize) {
@@ -84,65 +186,423 @@ impl Image {
         }
 
         self.height = new_height;
-        self.red_channel.datum.truncate(new_height * self.width);
-        self.green_channel.datum.truncate(new_height * self.width);
-        self.blue_channel.datum.truncate(new_height * self.width);
+        for channel in self.all_channels_mut() {
+            channel.trim_height(new_height);
+        }
     }
 
+    /// Crops to the new_width x new_height region with top-left corner (x_offset, y_offset), which
+    /// must lie entirely inside the image
     pub fn crop_rect(
         &mut self,
         new_width: usize,
         new_height: usize,
         x_offset: usize,
         y_offset: usize,
-    ) {
-        let mut new_red = Matrix::new_filled(new_width, new_height, 0);
-        let mut new_green = Matrix::new_filled(new_width, new_height, 0);
-        let mut new_blue = Matrix::new_filled(new_width, new_height, 0);
-
-        for row in 0..new_height {
-            for col in 0..new_width {
-                new_red[(row, col)] = self.red_channel[(y_offset + row, x_offset + col)];
-                new_green[(row, col)] = self.green_channel[(y_offset + row, x_offset + col)];
-                new_blue[(row, col)] = self.blue_channel[(y_offset + row, x_offset + col)];
+    ) -> Result<(), CropError> {
+        self.validate_crop(x_offset, y_offset, new_width, new_height)?;
+
+        for channel in self.all_channels_mut() {
+            let mut cropped = Matrix::new_filled(new_width, new_height, 0);
+            for row in 0..new_height {
+                for col in 0..new_width {
+                    cropped[(row, col)] = channel[(y_offset + row, x_offset + col)];
+                }
             }
+            *channel = cropped;
         }
 
         self.width = new_width;
         self.height = new_height;
-        self.red_channel = new_red;
-        self.green_channel = new_green;
-        self.blue_channel = new_blue;
+        Ok(())
+    }
+
+    /// Copies the w x h region with top-left corner (x, y) into a new image. The region may extend
+    /// past any edge, with the missing pixels filled according to the pad mode
+    pub fn crop_rect_padded(
+        &self,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+        pad: PadMode,
+    ) -> Image {
+        let mut cropped = Image::new(width, height, self.max_intensity, self.format.clone());
+        if self.alpha_channel.is_some() {
+            // Zero padding comes out fully transparent
+            cropped.alpha_channel = Some(Matrix::new_filled(width, height, 0));
+        }
+
+        for row in 0..height {
+            let Some(src_row) = pad.resolve(y + row as isize, self.height) else {
+                continue;
+            };
+            for col in 0..width {
+                let Some(src_col) = pad.resolve(x + col as isize, self.width) else {
+                    continue;
+                };
+                if let Some(pixel) = self.get_pixel(src_row, src_col) {
+                    cropped.set_pixel(row, col, pixel);
+                }
+                if let (Some(alpha), Some(source)) =
+                    (&mut cropped.alpha_channel, &self.alpha_channel)
+                {
+                    alpha[(row, col)] = source[(src_row, src_col)];
+                }
+            }
+        }
+
+        cropped
+    }
+
+    /// Checks that the region with top-left corner (x, y) lies entirely inside the image
+    pub fn validate_crop(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), CropError> {
+        if width == 0 || height == 0 {
+            return Err(CropError::Empty);
+        }
+        if x >= self.width {
+            return Err(CropError::X {
+                excess: x - self.width + 1,
+            });
+        }
+        if y >= self.height {
+            return Err(CropError::Y {
+                excess: y - self.height + 1,
+            });
+        }
+        // x and y are inside, so subtracting them cannot underflow where adding could overflow
+        if width > self.width - x {
+            return Err(CropError::Width {
+                excess: width - (self.width - x),
+            });
+        }
+        if height > self.height - y {
+            return Err(CropError::Height {
+                excess: height - (self.height - y),
+            });
+        }
+        Ok(())
+    }
+
+    /// Crops to the region with top-left corner (x, y), reporting which bound failed if it doesn't fit
+    pub fn crop_xywh(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), CropError> {
+        self.crop_rect(width, height, x, y)
+    }
+
+    /// Crops the image to the given dimensions, keeping the region centered in the original
+    pub fn center_crop(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        if new_width == 0 || new_height == 0 {
+            return Err("Center crop dimensions must be nonzero".into());
+        }
+        if new_width > self.width || new_height > self.height {
+            return Err(format!(
+                "Center crop of {}x{} exceeds the {}x{} source",
+                new_width, new_height, self.width, self.height
+            )
+            .into());
+        }
+
+        let x_offset = (self.width - new_width) / 2;
+        let y_offset = (self.height - new_height) / 2;
+        Ok(self.crop_rect(new_width, new_height, x_offset, y_offset)?)
     }
 
-    pub fn crop_width(&mut self, new_width: usize, method: CropMethod) {
+    /// Crops to the given dimensions around a focus point given as fractions of the width and
+    /// height, shifting the region just enough to keep it inside the image. A focus of (0.5, 0.5)
+    /// matches center_crop
+    pub fn crop_focus(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+        fx: f64,
+        fy: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        if new_width == 0 || new_height == 0 {
+            return Err("Focus crop dimensions must be nonzero".into());
+        }
+        if new_width > self.width || new_height > self.height {
+            return Err(format!(
+                "Focus crop of {}x{} exceeds the {}x{} source",
+                new_width, new_height, self.width, self.height
+            )
+            .into());
+        }
+        if !(0.0..=1.0).contains(&fx) || !(0.0..=1.0).contains(&fy) {
+            return Err(format!("Focus point ({}, {}) must lie within 0 to 1", fx, fy).into());
+        }
+
+        // Flooring keeps a centered focus on the same pixel center_crop picks for odd slack
+        let offset = |len: usize, new_len: usize, fraction: f64| {
+            let start = (len as f64 * fraction - new_len as f64 / 2.0).floor();
+            start.clamp(0.0, (len - new_len) as f64) as usize
+        };
+        let x_offset = offset(self.width, new_width, fx);
+        let y_offset = offset(self.height, new_height, fy);
+        Ok(self.crop_rect(new_width, new_height, x_offset, y_offset)?)
+    }
+
+    /// Crops the largest centered square out of the image
+    pub fn center_crop_square(&mut self) -> Result<(), Box<dyn Error>> {
+        let side = self.width.min(self.height);
+        self.center_crop(side, side)
+    }
+
+    /// Trims the width with a left, right, or left-right method
+    pub fn crop_width(
+        &mut self,
+        new_width: usize,
+        method: CropMethod,
+    ) -> Result<(), Box<dyn Error>> {
+        method.check_width()?;
+
         match method {
             CropMethod::Left => self.crop_left(new_width),
             CropMethod::Right => self.crop_right(new_width),
             CropMethod::LeftRight => {
+                if new_width >= self.width || new_width == 0 {
+                    return Ok(());
+                }
                 // Crop evenly from both sides
-                let total_trim = self.width.saturating_sub(new_width);
+                let total_trim = self.width - new_width;
                 let left_trim = total_trim / 2;
                 let new_x_offset = left_trim;
 
-                self.crop_rect(new_width, self.height, new_x_offset, 0);
+                self.crop_rect(new_width, self.height, new_x_offset, 0)?;
             }
-            _ => panic!("Invalid crop method for width"),
+            _ => unreachable!("Checked by check_width"),
         }
+        Ok(())
     }
 
-    pub fn crop_height(&mut self, new_height: usize, method: CropMethod) {
+    /// Trims the height with a top, bottom, or top-bottom method
+    pub fn crop_height(
+        &mut self,
+        new_height: usize,
+        method: CropMethod,
+    ) -> Result<(), Box<dyn Error>> {
+        method.check_height()?;
+
         match method {
             CropMethod::Top => self.crop_top(new_height),
             CropMethod::Bottom => self.crop_bottom(new_height),
             CropMethod::TopBottom => {
-                let total_trim = self.height.saturating_sub(new_height);
+                if new_height >= self.height || new_height == 0 {
+                    return Ok(());
+                }
+                let total_trim = self.height - new_height;
                 let top_trim = total_trim / 2;
                 let new_y_offset = top_trim;
 
-                self.crop_rect(self.width, new_height, 0, new_y_offset);
+                self.crop_rect(self.width, new_height, 0, new_y_offset)?;
             }
-            _ => panic!("Invalid crop method for height"),
+            _ => unreachable!("Checked by check_height"),
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{load_fixture, noise_image};
+    use crate::img::utils::PixelRGB;
+
+    #[test]
+    fn center_crop_keeps_the_middle() {
+        let source = noise_image(100, 100, 1994);
+        let mut image = source.clone();
+        image.center_crop(50, 40).unwrap();
+
+        assert_eq!((image.width, image.height), (50, 40));
+        assert_eq!(image.get_pixel(0, 0), source.get_pixel(30, 25));
+        assert_eq!(image.get_pixel(39, 49), source.get_pixel(69, 74));
+    }
+
+    #[test]
+    fn center_crop_rejects_larger_targets() {
+        let mut image = noise_image(100, 100, 1994);
+        assert!(image.center_crop(101, 40).is_err());
+        assert!(image.center_crop(50, 0).is_err());
+        assert_eq!((image.width, image.height), (100, 100));
+    }
+
+    #[test]
+    fn crop_errors_name_the_failing_bound() {
+        let mut image = noise_image(10, 8, 2001);
+        let cases = [
+            ((0, 0, 0, 4), CropError::Empty),
+            ((12, 0, 1, 1), CropError::X { excess: 3 }),
+            ((0, 8, 1, 1), CropError::Y { excess: 1 }),
+            ((4, 0, 7, 2), CropError::Width { excess: 1 }),
+            ((0, 3, 2, 9), CropError::Height { excess: 4 }),
+        ];
+        for ((x, y, width, height), expected) in cases {
+            assert_eq!(image.crop_xywh(x, y, width, height), Err(expected));
+        }
+        assert_eq!((image.width, image.height), (10, 8));
+
+        image.crop_xywh(4, 3, 6, 5).unwrap();
+        assert_eq!((image.width, image.height), (6, 5));
+    }
+
+    #[test]
+    fn padded_crops_fill_each_corner_by_mode() {
+        let source = load_fixture("fixture");
+        // Source row and column each output row and column reads from, None for zero padding
+        let cases = [
+            (
+                PadMode::Zero,
+                [None, Some(0), Some(1)],
+                [None, None, Some(0)],
+            ),
+            (
+                PadMode::Edge,
+                [Some(0), Some(0), Some(1)],
+                [Some(0), Some(0), Some(0)],
+            ),
+            (
+                PadMode::Reflect,
+                [Some(1), Some(0), Some(1)],
+                [Some(2), Some(1), Some(0)],
+            ),
+            (
+                PadMode::Wrap,
+                [Some(2), Some(0), Some(1)],
+                [Some(3), Some(4), Some(0)],
+            ),
+        ];
+        let black = Some(PixelRGB { r: 0, g: 0, b: 0 });
+
+        for (pad, rows, cols) in cases {
+            let cropped = source.crop_rect_padded(-2, -1, 3, 3, pad);
+            for (row, src_row) in rows.iter().enumerate() {
+                for (col, src_col) in cols.iter().enumerate() {
+                    let expected = match (src_row, src_col) {
+                        (Some(src_row), Some(src_col)) => source.get_pixel(*src_row, *src_col),
+                        _ => black,
+                    };
+                    assert_eq!(cropped.get_pixel(row, col), expected, "{:?}", pad);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn padded_crops_wrap_past_the_bottom_right_corner() {
+        let source = load_fixture("fixture");
+        let cases = [
+            (PadMode::Edge, [2, 2, 2], [3, 4, 4]),
+            (PadMode::Reflect, [2, 1, 0], [3, 4, 3]),
+            (PadMode::Wrap, [2, 0, 1], [3, 4, 0]),
+        ];
+        for (pad, rows, cols) in cases {
+            let cropped = source.crop_rect_padded(3, 2, 3, 3, pad);
+            for (row, src_row) in rows.into_iter().enumerate() {
+                for (col, src_col) in cols.into_iter().enumerate() {
+                    assert_eq!(
+                        cropped.get_pixel(row, col),
+                        source.get_pixel(src_row, src_col),
+                        "{:?}",
+                        pad
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn centered_focus_matches_a_center_crop() {
+        let source = noise_image(11, 8, 2031);
+        for (width, height) in [(5, 4), (6, 3), (11, 8), (1, 1)] {
+            let mut focused = source.clone();
+            focused.crop_focus(width, height, 0.5, 0.5).unwrap();
+            let mut centered = source.clone();
+            centered.center_crop(width, height).unwrap();
+            assert_eq!(focused, centered, "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn focus_near_an_edge_clamps_inside_the_image() {
+        let source = noise_image(10, 6, 2031);
+        let mut corner = source.clone();
+        corner.crop_focus(4, 3, 1.0, 0.0).unwrap();
+        let mut expected = source.clone();
+        expected.crop_rect(4, 3, 6, 0).unwrap();
+        assert_eq!(corner, expected);
+
+        let mut focused = source.clone();
+        focused.crop_focus(4, 2, 0.7, 0.3).unwrap();
+        let mut expected = source.clone();
+        expected.crop_rect(4, 2, 5, 0).unwrap();
+        assert_eq!(focused, expected);
+
+        let mut image = source.clone();
+        assert!(image.crop_focus(4, 3, 1.2, 0.5).is_err());
+        assert!(image.crop_focus(11, 3, 0.5, 0.5).is_err());
+        assert!(image.crop_focus(0, 3, 0.5, 0.5).is_err());
+        assert_eq!(image, source);
+    }
+
+    #[test]
+    fn crop_rect_copies_the_region_and_rejects_rectangles_outside() {
+        let source = noise_image(10, 8, 2037);
+        let mut image = source.clone();
+        image.crop_rect(3, 2, 6, 5).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        for (row, col, pixel) in image.iter_pixels() {
+            assert_eq!(Some(pixel), source.get_pixel(row + 5, col + 6));
+        }
+
+        let mut image = source.clone();
+        for (width, height, x, y) in [
+            (5, 2, 6, 0),
+            (1, 9, 0, 0),
+            (1, 1, usize::MAX, 0),
+            (usize::MAX, 1, 2, 0),
+        ] {
+            let error = image.crop_rect(width, height, x, y).unwrap_err();
+            assert!(!error.to_string().is_empty());
+        }
+        assert_eq!(image, source);
+    }
+
+    #[test]
+    fn rectangular_crop_offsets_stay_inside_the_image() {
+        let source = noise_image(10, 8, 2038);
+        let cropped = |x: Option<usize>, y: Option<usize>| {
+            let mut image = source.clone();
+            image.crop(4, 3, CropMethod::Rectangular, x, y);
+            image
+        };
+        let rect = |x: usize, y: usize| {
+            let mut image = source.clone();
+            image.crop_rect(4, 3, x, y).unwrap();
+            image
+        };
+
+        assert_eq!(cropped(None, None), rect(3, 2));
+        assert_eq!(cropped(Some(0), Some(0)), rect(0, 0));
+        assert_eq!(cropped(Some(6), Some(5)), rect(6, 5));
+        // Offsets past the last position that fits, however far, are pulled back to it
+        assert_eq!(cropped(Some(7), Some(6)), rect(6, 5));
+        assert_eq!(cropped(Some(usize::MAX), Some(usize::MAX)), rect(6, 5));
+        assert_eq!(cropped(Some(usize::MAX), None), rect(6, 2));
     }
 }
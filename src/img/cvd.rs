@@ -0,0 +1,169 @@
+use crate::img::image::*;
+use crate::img::utils::{PixelRGB, clamp_channel, linear_to_srgb, srgb_to_linear};
+
+use clap::ValueEnum;
+
+/// The color vision deficiencies that can be simulated or corrected for
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum CvdType {
+    /// Missing long wavelength (red) cones
+    Protanopia,
+    /// Missing medium wavelength (green) cones
+    Deuteranopia,
+    /// Missing short wavelength (blue) cones
+    Tritanopia,
+}
+
+type ColorMatrix = [[f64; 3]; 3];
+
+impl CvdType {
+    /// Machado et al. (2009) full severity simulation matrix, applied to linear RGB
+    fn simulation(self) -> ColorMatrix {
+        match self {
+            Self::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Self::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Self::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+
+    /// Moves the color information lost to the deficiency into the channels that are still seen
+    fn redistribution(self) -> ColorMatrix {
+        match self {
+            Self::Protanopia | Self::Deuteranopia => {
+                [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]]
+            }
+            Self::Tritanopia => [[1.0, 0.0, 0.7], [0.0, 1.0, 0.7], [0.0, 0.0, 0.0]],
+        }
+    }
+}
+
+fn multiply(matrix: &ColorMatrix, [r, g, b]: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * r + row[1] * g + row[2] * b)
+}
+
+impl Image {
+    /// Replaces every pixel with how it would appear to someone with the given deficiency
+    pub fn simulate_cvd(&mut self, kind: CvdType) {
+        let simulation = kind.simulation();
+        self.map_linear_rgb(|rgb| multiply(&simulation, rgb));
+    }
+
+    /// Shifts colors that the given deficiency confuses towards ones it can tell apart, by adding
+    /// the difference between each pixel and its simulation back into the perceivable channels
+    pub fn daltonize(&mut self, kind: CvdType) {
+        let (simulation, redistribution) = (kind.simulation(), kind.redistribution());
+        self.map_linear_rgb(|rgb| {
+            let simulated = multiply(&simulation, rgb);
+            let error = [0, 1, 2].map(|i| rgb[i] - simulated[i]);
+            let shift = multiply(&redistribution, error);
+            [0, 1, 2].map(|i| rgb[i] + shift[i])
+        });
+    }
+
+    /// Applies the function to every pixel as linear light RGB in [0, 1], clamping the result
+    fn map_linear_rgb<F>(&mut self, f: F)
+    where
+        F: Fn([f64; 3]) -> [f64; 3],
+    {
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+        let to_linear = |v: usize| srgb_to_linear(v as f64 / max);
+        let to_channel =
+            |v: f64| clamp_channel(linear_to_srgb(v.clamp(0.0, 1.0)) * max, max_intensity);
+
+        for (_, _, mut pixel) in self.iter_pixels_mut() {
+            let PixelRGB { r, g, b } = pixel.get();
+            let [r, g, b] = f([to_linear(r), to_linear(g), to_linear(b)]);
+            pixel.set(PixelRGB {
+                r: to_channel(r),
+                g: to_channel(g),
+                b: to_channel(b),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::solid_image;
+
+    /// A red and a green that protanopes and deuteranopes struggle to tell apart, side by side
+    fn confusable_pair() -> Image {
+        let mut image = solid_image(
+            2,
+            1,
+            PixelRGB {
+                r: 180,
+                g: 90,
+                b: 60,
+            },
+        );
+        image.set_pixel(
+            0,
+            1,
+            PixelRGB {
+                r: 110,
+                g: 130,
+                b: 60,
+            },
+        );
+        image
+    }
+
+    /// How far apart the two pixels look to someone with the deficiency
+    fn perceived_separation(image: &Image, kind: CvdType) -> isize {
+        let mut seen = image.clone();
+        seen.simulate_cvd(kind);
+        let (left, right) = (seen.get_pixel(0, 0).unwrap(), seen.get_pixel(0, 1).unwrap());
+        left.squared_difference(&right)
+    }
+
+    #[test]
+    fn daltonize_separates_a_confusable_pair() {
+        for kind in [CvdType::Protanopia, CvdType::Deuteranopia] {
+            let before = perceived_separation(&confusable_pair(), kind);
+            let mut corrected = confusable_pair();
+            corrected.daltonize(kind);
+            let after = perceived_separation(&corrected, kind);
+            assert!(after > before, "{:?}: {} then {}", kind, before, after);
+        }
+    }
+
+    #[test]
+    fn simulation_keeps_grays() {
+        for kind in [
+            CvdType::Protanopia,
+            CvdType::Deuteranopia,
+            CvdType::Tritanopia,
+        ] {
+            let gray = PixelRGB {
+                r: 128,
+                g: 128,
+                b: 128,
+            };
+            let mut image = solid_image(1, 1, gray);
+            image.simulate_cvd(kind);
+            let seen = image.get_pixel(0, 0).unwrap();
+            assert!(
+                seen.squared_difference(&gray) <= 3,
+                "{:?}: {:?}",
+                kind,
+                seen
+            );
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::img::image::*;
+use crate::img::matrix::*;
+use crate::img::utils::clamp_channel;
+
+use clap::ValueEnum;
+use std::error::Error;
+
+/// Methods available for reducing the number of intensity levels in an image
+#[derive(Debug, Clone, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum DitherMethod {
+    Ordered,
+}
+
+/// Builds the n x n Bayer index matrix, where n is a power of two
+pub fn bayer_matrix(n: usize) -> Matrix<usize> {
+    let mut bayer = Matrix::new_filled(1, 1, 0);
+
+    while bayer.width < n {
+        let size = bayer.width;
+        let mut next = Matrix::new_filled(size * 2, size * 2, 0);
+        for row in 0..size {
+            for col in 0..size {
+                let base = 4 * bayer[(row, col)];
+                next[(row, col)] = base;
+                next[(row, col + size)] = base + 2;
+                next[(row + size, col)] = base + 3;
+                next[(row + size, col + size)] = base + 1;
+            }
+        }
+        bayer = next;
+    }
+
+    bayer
+}
+
+impl Image {
+    /// Quantizes each channel to the given number of levels using a tiled Bayer threshold matrix
+    pub fn ordered_dither(
+        &mut self,
+        levels: usize,
+        matrix_size: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        if levels < 2 {
+            return Err("Dithering requires at least 2 levels".into());
+        }
+        if ![2, 4, 8].contains(&matrix_size) {
+            return Err("Bayer matrix size must be 2, 4, or 8".into());
+        }
+
+        let bayer = bayer_matrix(matrix_size);
+        let cells = (matrix_size * matrix_size) as f64;
+        let steps = (levels - 1) as f64;
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+
+        let quantize = |value: usize, threshold: f64| {
+            let level = (value as f64 / max * steps + threshold).floor().min(steps);
+            clamp_channel(level * max / steps, max_intensity)
+        };
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let index = bayer[(row % matrix_size, col % matrix_size)] as f64;
+                let threshold = (index + 0.5) / cells;
+
+                self.red_channel[(row, col)] = quantize(self.red_channel[(row, col)], threshold);
+                self.green_channel[(row, col)] =
+                    quantize(self.green_channel[(row, col)], threshold);
+                self.blue_channel[(row, col)] = quantize(self.blue_channel[(row, col)], threshold);
+            }
+        }
+
+        Ok(())
+    }
+    /// Snaps each channel to the nearest of the given number of evenly spaced levels across
+    /// [0, max_intensity], leaving flat bands of color. Quantization is symmetric, so values the
+    /// same distance from either end land the same distance from it, except that a middle level
+    /// falling between two integers rounds up
+    pub fn posterize(&mut self, levels: usize) -> Result<(), Box<dyn Error>> {
+        if levels < 2 {
+            return Err("Posterizing requires at least 2 levels".into());
+        }
+        let max = self.max_intensity;
+        if max == 0 {
+            return Ok(());
+        }
+
+        // Integer nearest-level rounding, with ties broken toward the middle of the range
+        let steps = levels - 1;
+        let nearest_level = |value: usize| (2 * value * steps + max) / (2 * max);
+        let quantize = |value: usize| {
+            let level = if 2 * value <= max {
+                nearest_level(value)
+            } else {
+                steps - nearest_level(max - value)
+            };
+            (2 * level * max + steps) / (2 * steps)
+        };
+
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            channel.apply_in_place(|value| *value = quantize(*value));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::solid_image;
+    use crate::img::utils::PixelRGB;
+
+    const MID_GRAY: PixelRGB = PixelRGB {
+        r: 128,
+        g: 128,
+        b: 128,
+    };
+
+    #[test]
+    fn bayer_matrix_orders_every_cell() {
+        let bayer = bayer_matrix(2);
+        assert_eq!(
+            [bayer[(0, 0)], bayer[(0, 1)], bayer[(1, 0)], bayer[(1, 1)]],
+            [0, 2, 3, 1]
+        );
+
+        let mut cells: Vec<usize> = (0..8)
+            .flat_map(|row| (0..8).map(move |col| (row, col)))
+            .map(|index| bayer_matrix(8)[index])
+            .collect();
+        cells.sort();
+        assert_eq!(cells, (0..64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn half_gray_with_two_levels_is_a_checkerboard() {
+        let mut image = solid_image(6, 4, MID_GRAY);
+        image.ordered_dither(2, 2).unwrap();
+
+        for row in 0..image.height {
+            for col in 0..image.width {
+                let expected = if (row + col) % 2 == 1 { 255 } else { 0 };
+                let pixel = image.get_pixel(row, col).unwrap();
+                assert_eq!((pixel.r, pixel.g, pixel.b), (expected, expected, expected));
+            }
+        }
+    }
+
+    #[test]
+    fn ordered_dither_rejects_bad_arguments() {
+        let mut image = solid_image(2, 2, MID_GRAY);
+        assert!(image.ordered_dither(1, 2).is_err());
+        assert!(image.ordered_dither(2, 3).is_err());
+    }
+
+    /// One pixel of every intensity from 0 to 255, gray so each channel sees the same ramp
+    fn gray_ramp() -> Image {
+        let mut ramp = solid_image(256, 1, MID_GRAY);
+        for value in 0..256 {
+            ramp.set_pixel(
+                0,
+                value,
+                PixelRGB {
+                    r: value,
+                    g: value,
+                    b: value,
+                },
+            );
+        }
+        ramp
+    }
+
+    #[test]
+    fn two_level_posterize_leaves_only_the_extremes() {
+        let mut ramp = gray_ramp();
+        ramp.posterize(2).unwrap();
+        for (_, col, pixel) in ramp.iter_pixels() {
+            let expected = if col < 128 { 0 } else { 255 };
+            assert_eq!(pixel.r, expected, "{}", col);
+        }
+    }
+
+    #[test]
+    fn posterize_is_symmetric_about_the_middle() {
+        for levels in [2, 4, 6, 16] {
+            let mut ramp = gray_ramp();
+            ramp.posterize(levels).unwrap();
+            let values: Vec<usize> = ramp.iter_pixels().map(|(_, _, pixel)| pixel.r).collect();
+            let mut distinct = values.clone();
+            distinct.dedup();
+            assert_eq!(distinct.len(), levels);
+            for value in 0..256 {
+                assert_eq!(
+                    values[value] + values[255 - value],
+                    255,
+                    "{} levels",
+                    levels
+                );
+            }
+        }
+
+        // An odd level count puts a level on the middle, which rounds up
+        let mut ramp = gray_ramp();
+        ramp.posterize(3).unwrap();
+        assert_eq!(ramp.get_pixel(0, 127).unwrap().r, 128);
+        assert_eq!(ramp.get_pixel(0, 64).unwrap().r, 128);
+        assert_eq!(ramp.get_pixel(0, 63).unwrap().r, 0);
+    }
+
+    #[test]
+    fn posterize_needs_two_levels() {
+        let mut image = solid_image(2, 2, MID_GRAY);
+        assert!(image.posterize(1).is_err());
+        assert!(image.posterize(0).is_err());
+        assert_eq!(image.get_pixel(0, 0), Some(MID_GRAY));
+    }
+}
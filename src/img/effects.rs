@@ -0,0 +1,199 @@
+use crate::img::image::*;
+use crate::img::matrix::*;
+use crate::img::utils::{PixelRGB, REC601_WEIGHTS};
+
+use std::error::Error;
+use std::f64::consts::TAU;
+
+impl Image {
+    /// Mirrors a wedge of the image around its center the given number of times.
+    /// Four segments is a plain four-way mirror of the top-left quadrant
+    pub fn kaleidoscope(&mut self, segments: usize) -> Result<(), Box<dyn Error>> {
+        if segments == 0 {
+            return Err("Kaleidoscope needs at least one segment".into());
+        }
+        if self.width == 0 || self.height == 0 {
+            return Ok(());
+        }
+
+        let source = self.clone();
+        let (width, height) = (self.width, self.height);
+
+        if segments == 4 {
+            for row in 0..height {
+                for col in 0..width {
+                    let src_row = row.min(height - 1 - row);
+                    let src_col = col.min(width - 1 - col);
+                    let pixel = source
+                        .get_pixel(src_row, src_col)
+                        .ok_or("Pixel out of bounds")?;
+                    self.set_pixel(row, col, pixel);
+                }
+            }
+            return Ok(());
+        }
+
+        let center_x = (width - 1) as f64 / 2.0;
+        let center_y = (height - 1) as f64 / 2.0;
+        let wedge = TAU / segments as f64;
+
+        for row in 0..height {
+            for col in 0..width {
+                let dx = col as f64 - center_x;
+                let dy = row as f64 - center_y;
+                let radius = dx.hypot(dy);
+                let angle = dy.atan2(dx).rem_euclid(TAU);
+
+                // Every other wedge is reflected so neighboring wedges meet seamlessly
+                let mut folded = angle.rem_euclid(wedge);
+                if (angle / wedge).floor() as usize % 2 == 1 {
+                    folded = wedge - folded;
+                }
+
+                let src_col = (center_x + radius * folded.cos())
+                    .round()
+                    .clamp(0.0, (width - 1) as f64) as usize;
+                let src_row = (center_y + radius * folded.sin())
+                    .round()
+                    .clamp(0.0, (height - 1) as f64) as usize;
+
+                let pixel = source
+                    .get_pixel(src_row, src_col)
+                    .ok_or("Pixel out of bounds")?;
+                self.set_pixel(row, col, pixel);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces each block_size x block_size tile with its average color
+    pub fn pixelate(&mut self, block_size: usize) -> Result<(), Box<dyn Error>> {
+        let mask = Matrix::new_filled(self.width, self.height, true);
+        self.pixelate_masked(block_size, &mask)
+    }
+
+    /// Pixelates only the masked pixels, averaging each tile over its masked pixels so the
+    /// unmasked surroundings stay crisp and never bleed into the mosaic
+    pub fn pixelate_masked(
+        &mut self,
+        block_size: usize,
+        mask: &Matrix<bool>,
+    ) -> Result<(), Box<dyn Error>> {
+        if block_size == 0 {
+            return Err("Pixelation block size must be nonzero".into());
+        }
+        if (mask.width, mask.height) != (self.width, self.height) {
+            return Err("Mask dimensions must match the image".into());
+        }
+
+        for block_row in (0..self.height).step_by(block_size) {
+            for block_col in (0..self.width).step_by(block_size) {
+                let rows = block_row..(block_row + block_size).min(self.height);
+                let cols = block_col..(block_col + block_size).min(self.width);
+                let cells: Vec<(usize, usize)> = rows
+                    .flat_map(|row| cols.clone().map(move |col| (row, col)))
+                    .filter(|&cell| mask[cell])
+                    .collect();
+                if cells.is_empty() {
+                    continue;
+                }
+
+                let count = cells.len();
+                let (mut r, mut g, mut b) = (0, 0, 0);
+                for &(row, col) in &cells {
+                    r += self.red_channel[(row, col)];
+                    g += self.green_channel[(row, col)];
+                    b += self.blue_channel[(row, col)];
+                }
+                let average = PixelRGB {
+                    r: (r + count / 2) / count,
+                    g: (g + count / 2) / count,
+                    b: (b + count / 2) / count,
+                };
+
+                for (row, col) in cells {
+                    self.set_pixel(row, col, average);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Interprets the image as a mask, selecting pixels brighter than half intensity
+    pub fn to_mask(&self) -> Matrix<bool> {
+        let threshold = self.max_intensity as f64 / 2.0;
+        let mut mask = Matrix::new_filled(self.width, self.height, false);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                if let Some(pixel) = self.get_pixel(row, col) {
+                    mask[(row, col)] = pixel.luminance(REC601_WEIGHTS) > threshold;
+                }
+            }
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::img::image::tests::noise_image;
+    use crate::img::matrix::Matrix;
+
+    #[test]
+    fn four_segment_kaleidoscope_is_symmetric_about_both_axes() {
+        for (width, height) in [(9, 6), (8, 7)] {
+            let source = noise_image(width, height, 2003);
+            let mut image = source.clone();
+            image.kaleidoscope(4).unwrap();
+
+            let mut mirrored = image.clone();
+            mirrored.mirror_x();
+            assert_eq!(mirrored, image);
+            mirrored.mirror_y();
+            assert_eq!(mirrored, image);
+
+            // The top-left quadrant is the wedge being mirrored, so it is left as is
+            for row in 0..height.div_ceil(2) {
+                for col in 0..width.div_ceil(2) {
+                    assert_eq!(image.get_pixel(row, col), source.get_pixel(row, col));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kaleidoscope_needs_a_segment() {
+        assert!(noise_image(4, 4, 2003).kaleidoscope(0).is_err());
+    }
+
+    #[test]
+    fn masked_pixelate_leaves_the_border_crisp() {
+        let source = noise_image(12, 12, 2013);
+        let mut mask = Matrix::new_filled(12, 12, false);
+        for row in 4..8 {
+            for col in 4..8 {
+                mask[(row, col)] = true;
+            }
+        }
+
+        let mut image = source.clone();
+        image.pixelate_masked(4, &mask).unwrap();
+        for (row, col, pixel) in image.iter_pixels() {
+            if mask[(row, col)] {
+                assert_eq!(pixel, image.get_pixel(4, 4).unwrap());
+            } else {
+                assert_eq!(Some(pixel), source.get_pixel(row, col));
+            }
+        }
+        assert_ne!(image, source);
+    }
+
+    #[test]
+    fn masked_pixelate_rejects_a_mismatched_mask() {
+        let mask = Matrix::new_filled(3, 3, true);
+        assert!(noise_image(4, 4, 2013).pixelate_masked(2, &mask).is_err());
+        assert!(noise_image(3, 3, 2013).pixelate_masked(0, &mask).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use crate::img::image::*;
+use crate::img::matrix::*;
+use crate::img::utils::clamp_channel;
+
+use std::error::Error;
+
+/// Horizontal Sobel kernel, responding to changes from left to right
+const SOBEL_X: [[isize; 3]; 3] = [[-1, 0, 1], [-2, 0, 2], [-1, 0, 1]];
+
+/// Vertical Sobel kernel, responding to changes from top to bottom
+const SOBEL_Y: [[isize; 3]; 3] = [[-1, -2, -1], [0, 0, 0], [1, 2, 1]];
+
+/// Gradient magnitude of the channel, sampling past the border from the nearest edge pixel
+fn sobel_channel(channel: &Matrix<usize>, max_intensity: usize) -> Matrix<usize> {
+    let (width, height) = (channel.width, channel.height);
+    let mut edges = Matrix::new_filled(width, height, 0);
+
+    for row in 0..height {
+        for col in 0..width {
+            let window = channel.neighborhood(row, col, 1, BorderMode::Clamp);
+            let (mut gx, mut gy) = (0, 0);
+            for (index, &value) in window.iter().enumerate() {
+                let (k_row, k_col) = (index / 3, index % 3);
+                gx += SOBEL_X[k_row][k_col] * value as isize;
+                gy += SOBEL_Y[k_row][k_col] * value as isize;
+            }
+            let magnitude = ((gx * gx + gy * gy) as f64).sqrt();
+            edges[(row, col)] = clamp_channel(magnitude, max_intensity);
+        }
+    }
+
+    edges
+}
+
+/// Median of each pixel's (2 * radius + 1) square neighborhood, sampling past the border from the
+/// nearest edge pixel
+fn median_channel(channel: &Matrix<usize>, radius: usize) -> Matrix<usize> {
+    let (width, height) = (channel.width, channel.height);
+    let mut filtered = Matrix::new_filled(width, height, 0);
+
+    for row in 0..height {
+        for col in 0..width {
+            let mut window = channel.neighborhood(row, col, radius, BorderMode::Clamp);
+            let middle = window.len() / 2;
+            filtered[(row, col)] = *window.select_nth_unstable(middle).1;
+        }
+    }
+
+    filtered
+}
+
+/// Sigma of the unsharp mask applied after downscaling, small enough to only restore fine detail
+pub const DOWNSCALE_SHARPEN_SIGMA: f64 = 1.0;
+
+/// Box blur passes approximating a Gaussian in fast_gaussian
+const GAUSSIAN_BOX_PASSES: usize = 3;
+
+/// Radii of the box blurs whose successive passes best approximate a Gaussian of the given sigma.
+/// A box of width w has variance (w^2 - 1) / 12, so widths are picked from the two odd integers
+/// around the ideal width in the mix whose variances sum closest to sigma^2
+fn gaussian_box_radii(sigma: f64) -> [usize; GAUSSIAN_BOX_PASSES] {
+    let passes = GAUSSIAN_BOX_PASSES as f64;
+    let target = 12.0 * sigma * sigma;
+    let mut lower = (target / passes + 1.0).sqrt().floor() as usize;
+    if lower.is_multiple_of(2) {
+        lower -= 1;
+    }
+    let upper = lower + 2;
+    let (lower_f, upper_f) = (lower as f64, upper as f64);
+    let lower_passes = ((passes * (upper_f * upper_f - 1.0) - target)
+        / (upper_f * upper_f - lower_f * lower_f))
+        .round() as usize;
+
+    std::array::from_fn(|pass| {
+        let width = if pass < lower_passes { lower } else { upper };
+        (width - 1) / 2
+    })
+}
+
+/// Replaces each value with the mean of the 2 * radius + 1 values around it along every line of a
+/// row-major buffer, repeating the end values past either edge. Line i starts at i * line_step and
+/// steps by stride, so rows and columns share this code. A running sum makes the cost per value
+/// independent of the radius
+fn box_blur_lines(
+    data: &mut [f64],
+    lines: usize,
+    line_len: usize,
+    line_step: usize,
+    stride: usize,
+    radius: usize,
+) {
+    if radius == 0 || line_len == 0 {
+        return;
+    }
+
+    let window = (2 * radius + 1) as f64;
+    let last = line_len - 1;
+    let mut line = vec![0.0; line_len];
+    for index in 0..lines {
+        let start = index * line_step;
+        for (k, value) in line.iter_mut().enumerate() {
+            *value = data[start + k * stride];
+        }
+
+        let mut sum = line[0] * (radius + 1) as f64;
+        sum += (1..=radius).map(|j| line[j.min(last)]).sum::<f64>();
+        for k in 0..line_len {
+            data[start + k * stride] = sum / window;
+            sum += line[(k + radius + 1).min(last)] - line[k.saturating_sub(radius)];
+        }
+    }
+}
+
+/// Runs a horizontal then a vertical box blur of each radius over the channel
+fn box_blur_channel(
+    channel: &Matrix<usize>,
+    radii: &[usize],
+    max_intensity: usize,
+) -> Matrix<usize> {
+    let (width, height) = (channel.width, channel.height);
+    let mut data: Vec<f64> = channel.datum.iter().map(|&value| value as f64).collect();
+
+    for &radius in radii {
+        box_blur_lines(&mut data, height, width, width, 1, radius);
+        box_blur_lines(&mut data, width, height, 1, width, radius);
+    }
+
+    let datum = data
+        .into_iter()
+        .map(|value| clamp_channel(value, max_intensity))
+        .collect();
+    Matrix::from_vec(width, height, datum).expect("Blur changed the channel size")
+}
+
+impl Image {
+    /// Replaces each channel with its Sobel gradient magnitude, clamped to max_intensity
+    pub fn sobel(&mut self) {
+        self.red_channel = sobel_channel(&self.red_channel, self.max_intensity);
+        self.green_channel = sobel_channel(&self.green_channel, self.max_intensity);
+        self.blue_channel = sobel_channel(&self.blue_channel, self.max_intensity);
+    }
+
+    /// Replaces each channel value with the median of its neighborhood, removing isolated noise
+    /// while keeping edges sharp. A radius of 0 leaves the image unchanged
+    pub fn median_filter(&mut self, radius: usize) {
+        if radius == 0 {
+            return;
+        }
+        self.red_channel = median_channel(&self.red_channel, radius);
+        self.green_channel = median_channel(&self.green_channel, radius);
+        self.blue_channel = median_channel(&self.blue_channel, radius);
+    }
+
+    /// Averages each channel value with its (2 * radius + 1) square neighborhood, sampling past the
+    /// border from the nearest edge pixel. A radius of 0 leaves the image unchanged
+    pub fn box_blur(&mut self, radius: usize) {
+        self.blur_channels(&[radius]);
+    }
+
+    /// Approximates a Gaussian blur of the given standard deviation with three box blurs, which
+    /// costs the same at any sigma where a true kernel grows with it. Sigmas below about 0.7 are
+    /// narrower than any box and leave the image unchanged
+    pub fn fast_gaussian(&mut self, sigma: f64) -> Result<(), Box<dyn Error>> {
+        if !sigma.is_finite() || sigma < 0.0 {
+            return Err("Blur sigma must be a non-negative number".into());
+        }
+        self.blur_channels(&gaussian_box_radii(sigma));
+        Ok(())
+    }
+
+    /// Sharpens by adding back amount times the detail that a Gaussian blur of the given sigma
+    /// removes, so 0 leaves the image unchanged and 1 doubles the local contrast
+    pub fn unsharp_mask(&mut self, sigma: f64, amount: f64) -> Result<(), Box<dyn Error>> {
+        if !amount.is_finite() || amount < 0.0 {
+            return Err("Sharpen amount must be a non-negative number".into());
+        }
+        let mut blurred = self.clone();
+        blurred.fast_gaussian(sigma)?;
+
+        let max_intensity = self.max_intensity;
+        let channels = [
+            (&mut self.red_channel, &blurred.red_channel),
+            (&mut self.green_channel, &blurred.green_channel),
+            (&mut self.blue_channel, &blurred.blue_channel),
+        ];
+        for (channel, soft) in channels {
+            for (value, &soft) in channel.datum.iter_mut().zip(&soft.datum) {
+                let detail = *value as f64 - soft as f64;
+                *value = clamp_channel(*value as f64 + amount * detail, max_intensity);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a mild unsharp mask of the given amount when the image has shrunk from the
+    /// original size on either axis, restoring detail the downscale softened. Upscales and
+    /// unchanged sizes are left alone
+    pub fn sharpen_after_downscale(
+        &mut self,
+        original_width: usize,
+        original_height: usize,
+        amount: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.width < original_width || self.height < original_height {
+            self.unsharp_mask(DOWNSCALE_SHARPEN_SIGMA, amount)?;
+        }
+        Ok(())
+    }
+
+    fn blur_channels(&mut self, radii: &[usize]) {
+        if radii.iter().all(|&radius| radius == 0) {
+            return;
+        }
+        let max_intensity = self.max_intensity;
+        self.red_channel = box_blur_channel(&self.red_channel, radii, max_intensity);
+        self.green_channel = box_blur_channel(&self.green_channel, radii, max_intensity);
+        self.blue_channel = box_blur_channel(&self.blue_channel, radii, max_intensity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, solid_image};
+    use crate::img::scale::ScaleMethod;
+    use crate::img::utils::PixelRGB;
+
+    #[test]
+    fn sobel_lights_up_only_a_vertical_boundary() {
+        let mut image = solid_image(8, 5, PixelRGB { r: 0, g: 0, b: 0 });
+        for row in 0..5 {
+            for col in 4..8 {
+                image.set_pixel(
+                    row,
+                    col,
+                    PixelRGB {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    },
+                );
+            }
+        }
+
+        image.sobel();
+        for (row, col, pixel) in image.iter_pixels() {
+            // The boundary falls between columns 3 and 4, so both straddle it
+            let expected = if col == 3 || col == 4 { 255 } else { 0 };
+            assert_eq!(pixel.r, expected, "({}, {})", row, col);
+            assert_eq!(
+                pixel,
+                PixelRGB {
+                    r: pixel.r,
+                    g: pixel.r,
+                    b: pixel.r
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn median_removes_isolated_noise_and_keeps_flat_regions() {
+        let flat = PixelRGB {
+            r: 90,
+            g: 120,
+            b: 60,
+        };
+        let mut image = solid_image(9, 7, flat);
+        for (row, col) in [(0, 0), (3, 4), (6, 8), (2, 1)] {
+            image.set_pixel(
+                row,
+                col,
+                PixelRGB {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+            );
+        }
+
+        image.median_filter(1);
+        assert!(image.iter_pixels().all(|(_, _, pixel)| pixel == flat));
+
+        // Radius zero leaves every pixel as it was
+        let noisy = noise_image(6, 5, 2030);
+        let mut unchanged = noisy.clone();
+        unchanged.median_filter(0);
+        assert_eq!(unchanged, noisy);
+    }
+
+    /// Blurs the channel with a sampled Gaussian kernel out to four sigma, repeating edge values
+    fn true_gaussian(channel: &Matrix<usize>, sigma: f64) -> Vec<f64> {
+        let radius = (4.0 * sigma).ceil() as isize;
+        let kernel: Vec<f64> = (-radius..=radius)
+            .map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let total: f64 = kernel.iter().sum();
+        let (width, height) = (channel.width as isize, channel.height as isize);
+        let convolve = |at: &dyn Fn(isize, isize) -> f64, row: isize, col: isize, across: bool| {
+            (-radius..=radius)
+                .zip(&kernel)
+                .map(|(offset, weight)| {
+                    let (r, c) = match across {
+                        true => (row, (col + offset).clamp(0, width - 1)),
+                        false => ((row + offset).clamp(0, height - 1), col),
+                    };
+                    at(r, c) * weight
+                })
+                .sum::<f64>()
+                / total
+        };
+
+        let source = |r: isize, c: isize| channel[(r as usize, c as usize)] as f64;
+        let rows: Vec<f64> = (0..height * width)
+            .map(|i| convolve(&source, i / width, i % width, true))
+            .collect();
+        let blurred_rows = |r: isize, c: isize| rows[(r * width + c) as usize];
+        (0..height * width)
+            .map(|i| convolve(&blurred_rows, i / width, i % width, false))
+            .collect()
+    }
+
+    #[test]
+    fn fast_gaussian_closely_matches_a_true_gaussian() {
+        let mut square = solid_image(41, 41, PixelRGB { r: 0, g: 0, b: 0 });
+        for row in 16..25 {
+            for col in 16..25 {
+                square.set_pixel(
+                    row,
+                    col,
+                    PixelRGB {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    },
+                );
+            }
+        }
+
+        // Sharp edges in the square and per-pixel noise are where three boxes stray the most
+        let cases = [
+            (square, 3.0, 10.0, 1.0),
+            (noise_image(30, 20, 2037), 2.0, 25.0, 2.5),
+        ];
+        for (image, sigma, worst_allowed, mean_allowed) in cases {
+            let expected = true_gaussian(&image.red_channel, sigma);
+            let mut blurred = image.clone();
+            blurred.fast_gaussian(sigma).unwrap();
+
+            let errors: Vec<f64> = blurred
+                .red_channel
+                .datum
+                .iter()
+                .zip(&expected)
+                .map(|(&value, &expected)| (value as f64 - expected).abs())
+                .collect();
+            let worst = errors.iter().cloned().fold(0.0, f64::max);
+            let mean = errors.iter().sum::<f64>() / errors.len() as f64;
+            assert!(
+                worst <= worst_allowed,
+                "sigma {} worst error {}",
+                sigma,
+                worst
+            );
+            assert!(mean <= mean_allowed, "sigma {} mean error {}", sigma, mean);
+        }
+    }
+
+    /// Sum of absolute differences between horizontally adjacent red samples
+    fn local_contrast(image: &Image) -> usize {
+        image
+            .red_channel
+            .datum
+            .chunks(image.width)
+            .flat_map(|row| row.windows(2).map(|pair| pair[0].abs_diff(pair[1])))
+            .sum()
+    }
+
+    #[test]
+    fn sharpening_after_a_downscale_restores_local_contrast() {
+        let source = noise_image(32, 24, 2045);
+        let mut plain = source.clone();
+        plain.scale(16, 12, ScaleMethod::Area);
+        let mut sharpened = plain.clone();
+        sharpened.sharpen_after_downscale(32, 24, 0.8).unwrap();
+
+        assert_eq!((sharpened.width, sharpened.height), (16, 12));
+        assert!(
+            local_contrast(&sharpened) > local_contrast(&plain) * 11 / 10,
+            "{} vs {}",
+            local_contrast(&sharpened),
+            local_contrast(&plain)
+        );
+
+        // Upscales and unchanged sizes are left alone
+        let mut upscaled = source.clone();
+        upscaled.scale(64, 48, ScaleMethod::Bilinear);
+        let before = upscaled.clone();
+        upscaled.sharpen_after_downscale(32, 24, 0.8).unwrap();
+        assert_eq!(upscaled, before);
+        let mut same = source.clone();
+        same.sharpen_after_downscale(32, 24, 0.8).unwrap();
+        assert_eq!(same, source);
+    }
+}
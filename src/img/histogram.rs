@@ -0,0 +1,105 @@
+use crate::img::image::*;
+
+/// Number of buckets per channel, one per 8-bit intensity
+pub const HISTOGRAM_BUCKETS: usize = 256;
+
+/// Per-channel pixel counts in red, green, blue order
+pub type Histogram = [[usize; HISTOGRAM_BUCKETS]; 3];
+
+impl Image {
+    /// Counts the pixels at each intensity of every color channel. Images whose max_intensity is
+    /// not 255 are rescaled into the 256 buckets, rounding to the nearest one
+    pub fn histogram(&self) -> Histogram {
+        let mut histogram = [[0; HISTOGRAM_BUCKETS]; 3];
+        let max = self.max_intensity.max(1);
+        let last = HISTOGRAM_BUCKETS - 1;
+
+        let channels = [&self.red_channel, &self.green_channel, &self.blue_channel];
+        for (counts, channel) in histogram.iter_mut().zip(channels) {
+            for &value in &channel.datum {
+                let bucket = (value.min(max) * last + max / 2) / max;
+                counts[bucket] += 1;
+            }
+        }
+
+        histogram
+    }
+}
+
+/// Formats the histogram as CSV with a header and one row per bucket
+pub fn histogram_csv(histogram: &Histogram) -> String {
+    let mut csv = String::from("value,red,green,blue\n");
+    for bucket in 0..HISTOGRAM_BUCKETS {
+        let [r, g, b] = histogram.map(|counts| counts[bucket]);
+        csv.push_str(&format!("{},{},{},{}\n", bucket, r, g, b));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::solid_image;
+    use crate::img::utils::PixelRGB;
+
+    #[test]
+    fn synthetic_image_has_exact_counts() {
+        let mut image = solid_image(
+            3,
+            2,
+            PixelRGB {
+                r: 10,
+                g: 200,
+                b: 0,
+            },
+        );
+        image.set_pixel(
+            0,
+            0,
+            PixelRGB {
+                r: 255,
+                g: 200,
+                b: 7,
+            },
+        );
+        image.set_pixel(1, 2, PixelRGB { r: 10, g: 0, b: 7 });
+        let before = image.clone();
+
+        let histogram = image.histogram();
+        assert_eq!(image, before);
+        for (counts, expected) in histogram.iter().zip([
+            vec![(10, 5), (255, 1)],
+            vec![(200, 5), (0, 1)],
+            vec![(0, 4), (7, 2)],
+        ]) {
+            for (bucket, &count) in counts.iter().enumerate() {
+                let want = expected
+                    .iter()
+                    .find(|(b, _)| *b == bucket)
+                    .map_or(0, |e| e.1);
+                assert_eq!(count, want, "bucket {}", bucket);
+            }
+        }
+
+        let csv = histogram_csv(&histogram);
+        assert_eq!(csv.lines().count(), HISTOGRAM_BUCKETS + 1);
+        assert_eq!(csv.lines().nth(11), Some("10,5,0,0"));
+        assert_eq!(csv.lines().nth(1), Some("0,0,1,4"));
+    }
+
+    #[test]
+    fn deeper_images_are_rescaled_into_the_buckets() {
+        let mut image = solid_image(
+            2,
+            1,
+            PixelRGB {
+                r: 1000,
+                g: 500,
+                b: 0,
+            },
+        );
+        image.max_intensity = 1000;
+        let [red, green, blue] = image.histogram();
+        assert_eq!((red[255], green[128], blue[0]), (2, 2, 2));
+    }
+}
@@ -1,15 +1,21 @@
 use crate::img::crop::CropMethod;
-use crate::img::io::{PPMFormat, ppm_bytes_to_img};
+use crate::img::io::{EncodeOptions, PPMFormat, STDIO_PATH, infer_type, write_atomic};
 use crate::img::matrix::*;
 use crate::img::scale::ScaleMethod;
-use crate::img::utils::PixelRGB;
+use crate::img::seam::{SeamDirection, SeamOptions, SeamProfile};
+use crate::img::utils::{PixelRGB, REC601_WEIGHTS, clamp_channel, linear_to_srgb, srgb_to_linear};
 
+use clap::ValueEnum;
+use image::ImageFormat;
+use image::metadata::Orientation;
+use std::cmp::Ordering;
 use std::error::Error;
 use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
 
 /// Representation of a 2D RGB image
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Image {
     pub width: usize,
     pub height: usize,
@@ -17,58 +23,290 @@ pub struct Image {
     pub red_channel: Matrix<usize>,
     pub blue_channel: Matrix<usize>,
     pub green_channel: Matrix<usize>,
+    pub alpha_channel: Option<Matrix<usize>>,
     pub format: PPMFormat,
 }
 
+/// One of the three color channels of an image
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+}
+
+/// Largest per-channel factor accepted by scale_rgb
+pub const MAX_RGB_SCALE: f64 = 16.0;
+
+/// Classic sepia tone weights. Each row gives the red, green, and blue contributions to one output
+/// channel, in red, green, blue order
+pub const SEPIA_MATRIX: [[f64; 3]; 3] = [
+    [0.393, 0.769, 0.189],
+    [0.349, 0.686, 0.168],
+    [0.272, 0.534, 0.131],
+];
+
+/// Scales a dimension by the factor, rounding to the nearest pixel. A result that rounds to 0 is
+/// raised to 1 with a warning, since an image cannot have an empty axis
+fn scaled_dimension(len: usize, factor: f64) -> usize {
+    let scaled = (len as f64 * factor).round() as usize;
+    if scaled == 0 && len > 0 {
+        eprintln!(
+            "Warning: scaling {} pixels by {} rounds to 0, using 1 instead",
+            len, factor
+        );
+        return 1;
+    }
+    scaled
+}
+
 impl Image {
     /// Initializes an Image with the given width, height, and intensity with all channels set to 0
     pub fn new(width: usize, height: usize, intensity: usize, format: PPMFormat) -> Image {
         Image {
-            width: width,
-            height: height,
+            width,
+            height,
             max_intensity: intensity,
             red_channel: Matrix::new_filled(width, height, 0),
             blue_channel: Matrix::new_filled(width, height, 0),
             green_channel: Matrix::new_filled(width, height, 0),
-            format: format,
+            alpha_channel: None,
+            format,
+        }
+    }
+
+    /// Builds an Image from separate channel matrices, which must all share the same dimensions
+    pub fn from_channels(
+        red_channel: Matrix<usize>,
+        green_channel: Matrix<usize>,
+        blue_channel: Matrix<usize>,
+        max_intensity: usize,
+        format: PPMFormat,
+    ) -> Result<Image, Box<dyn Error>> {
+        let dimensions = (red_channel.width, red_channel.height);
+        if (green_channel.width, green_channel.height) != dimensions
+            || (blue_channel.width, blue_channel.height) != dimensions
+        {
+            return Err("Channel matrices must share the same dimensions".into());
         }
+
+        Ok(Image {
+            width: dimensions.0,
+            height: dimensions.1,
+            max_intensity,
+            red_channel,
+            blue_channel,
+            green_channel,
+            alpha_channel: None,
+            format,
+        })
     }
 
+    /// Every channel matrix including alpha when present, for operations that move pixels around
+    pub fn all_channels_mut(&mut self) -> impl Iterator<Item = &mut Matrix<usize>> {
+        [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ]
+        .into_iter()
+        .chain(self.alpha_channel.as_mut())
+    }
+
+    /// The matrix holding the given color channel
+    pub fn channel(&self, channel: Channel) -> &Matrix<usize> {
+        match channel {
+            Channel::Red => &self.red_channel,
+            Channel::Green => &self.green_channel,
+            Channel::Blue => &self.blue_channel,
+        }
+    }
+
+    /// Mutable access to the matrix holding the given color channel
+    pub fn channel_mut(&mut self, channel: Channel) -> &mut Matrix<usize> {
+        match channel {
+            Channel::Red => &mut self.red_channel,
+            Channel::Green => &mut self.green_channel,
+            Channel::Blue => &mut self.blue_channel,
+        }
+    }
+
+    /// Grows each axis by scaling with the method, bilinear when None. Shrinking an axis crops it
+    /// when a crop method is given for that axis and area averages it down otherwise, so the
+    /// method goes unused unless an axis grows. Both crop methods are checked before anything
+    /// changes, so an unusable one leaves the image as it was. Scaling premultiplies alpha as in
+    /// scale_with
     pub fn resize(
         &mut self,
         target_width: usize,
         target_height: usize,
-        method: ScaleMethod,
+        method: Option<ScaleMethod>,
         crop_x: Option<CropMethod>,
         crop_y: Option<CropMethod>,
-    ) {
+        premultiply: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(crop) = &crop_x {
+            crop.check_width()?;
+        }
+        if let Some(crop) = &crop_y {
+            crop.check_height()?;
+        }
+        let method = method.unwrap_or_default();
+
         if target_width > self.width {
-            self.scale(target_width, self.height, method.clone());
+            self.scale_with(target_width, self.height, method.clone(), premultiply);
         } else if target_width < self.width {
-            let crop_method = crop_x.expect("Crop method for the x-axis needed for this resize");
-            self.crop_width(target_width, crop_method);
+            match crop_x {
+                Some(crop_method) => self.crop_width(target_width, crop_method)?,
+                None => self.scale_with(target_width, self.height, ScaleMethod::Area, premultiply),
+            }
         }
 
         if target_height > self.height {
-            self.scale(self.width, target_height, method);
+            self.scale_with(self.width, target_height, method, premultiply);
         } else if target_height < self.height {
-            let crop_method = crop_y.expect("Crop method for the y-axis needed for this resize");
-            self.crop_height(target_height, crop_method);
+            match crop_y {
+                Some(crop_method) => self.crop_height(target_height, crop_method)?,
+                None => self.scale_with(self.width, target_height, ScaleMethod::Area, premultiply),
+            }
         }
+        Ok(())
     }
 
-    /// Scales the image up to a higher width and height
+    /// Scales the image up to a higher width and height, premultiplying alpha when present
     pub fn scale(&mut self, new_width: usize, new_height: usize, method: ScaleMethod) {
+        self.scale_with(new_width, new_height, method, true);
+    }
+
+    /// Scales the image to the new width and height. With premultiply, methods that blend
+    /// neighboring pixels work on color premultiplied by alpha so transparent pixels leave no halo
+    pub fn scale_with(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+        method: ScaleMethod,
+        premultiply: bool,
+    ) {
         if self.width == 0 || self.height == 0 || new_width == 0 || new_height == 0 {
             return;
         }
 
-        match method {
-            ScaleMethod::Linear => self.linear_scale(new_width, new_height),
-            ScaleMethod::Bilinear => self.bilinear_scale(new_width, new_height),
+        // Whole-factor downscales average each block exactly instead of sampling from it
+        let shrinks = new_width < self.width || new_height < self.height;
+        let whole = self.width.is_multiple_of(new_width) && self.height.is_multiple_of(new_height);
+        let (width, height) = (self.width, self.height);
+        let resample = |image: &mut Image| match method {
+            _ if shrinks && whole => image.block_average(width / new_width, height / new_height),
+            ScaleMethod::Linear => image.linear_scale(new_width, new_height),
+            ScaleMethod::Bilinear => image.bilinear_scale(new_width, new_height),
+            ScaleMethod::Area => image.area_scale(new_width, new_height),
+        };
+
+        // Nearest neighbor copies whole pixels, so there is nothing to premultiply for
+        let blends = shrinks && whole || method != ScaleMethod::Linear;
+        if premultiply && blends {
+            self.with_premultiplied_alpha(resample);
+        } else {
+            resample(self);
+        }
+    }
+
+    /// Scales each axis with the up method when it grows and the down method when it shrinks. An
+    /// image growing along one axis and shrinking along the other is scaled one axis at a time.
+    /// Alpha is premultiplied as in scale_with
+    pub fn scale_per_axis(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+        up: ScaleMethod,
+        down: ScaleMethod,
+        premultiply: bool,
+    ) {
+        let pick = |old: usize, new: usize| match new.cmp(&old) {
+            Ordering::Greater => Some(up.clone()),
+            Ordering::Less => Some(down.clone()),
+            Ordering::Equal => None,
+        };
+
+        match (pick(self.width, new_width), pick(self.height, new_height)) {
+            (Some(x_method), Some(y_method)) if x_method != y_method => {
+                self.scale_with(new_width, self.height, x_method, premultiply);
+                self.scale_with(new_width, new_height, y_method, premultiply);
+            }
+            (Some(method), _) | (None, Some(method)) => {
+                self.scale_with(new_width, new_height, method, premultiply)
+            }
+            (None, None) => {}
         }
     }
 
+    /// Scales the image to the largest size fitting within the box that keeps its aspect ratio
+    pub fn thumbnail(&mut self, max_width: usize, max_height: usize) {
+        if self.width == 0 || self.height == 0 || max_width == 0 || max_height == 0 {
+            return;
+        }
+
+        let factor = f64::min(
+            max_width as f64 / self.width as f64,
+            max_height as f64 / self.height as f64,
+        );
+        let new_width = scaled_dimension(self.width, factor).min(max_width);
+        let new_height = scaled_dimension(self.height, factor).min(max_height);
+        self.scale(new_width, new_height, ScaleMethod::Bilinear);
+    }
+
+    /// Fills in whichever of the new dimensions is 0 so the image keeps its aspect ratio. The
+    /// derived dimension is never less than 1
+    pub fn aspect_size(
+        &self,
+        new_width: usize,
+        new_height: usize,
+    ) -> Result<(usize, usize), Box<dyn Error>> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Cannot preserve the aspect ratio of an empty image".into());
+        }
+
+        match (new_width, new_height) {
+            (0, 0) => Err("Give a width or height to derive the other from".into()),
+            (0, height) => {
+                let factor = height as f64 / self.height as f64;
+                Ok((scaled_dimension(self.width, factor), height))
+            }
+            (width, 0) => {
+                let factor = width as f64 / self.width as f64;
+                Ok((width, scaled_dimension(self.height, factor)))
+            }
+            _ => Err("Set the width or height to 0 to derive it from the aspect ratio".into()),
+        }
+    }
+
+    /// Shrinks the image to fit within the box, leaving images that already fit untouched. The
+    /// limiting axis sets a single factor for both when keeping the aspect ratio, and otherwise
+    /// each oversized axis is shrunk to its limit on its own
+    pub fn fit_within(&mut self, max_width: usize, max_height: usize, keep_aspect: bool) {
+        if max_width == 0 || max_height == 0 || self.width == 0 || self.height == 0 {
+            return;
+        }
+        if self.width <= max_width && self.height <= max_height {
+            return;
+        }
+
+        let (new_width, new_height) = if keep_aspect {
+            let factor = f64::min(
+                max_width as f64 / self.width as f64,
+                max_height as f64 / self.height as f64,
+            );
+            (
+                scaled_dimension(self.width, factor).min(max_width),
+                scaled_dimension(self.height, factor).min(max_height),
+            )
+        } else {
+            (self.width.min(max_width), self.height.min(max_height))
+        };
+        self.scale(new_width, new_height, ScaleMethod::Area);
+    }
+
     /// Crops the image using the given cropping method
     pub fn crop(&mut self, new_width: usize, new_height: usize, method: CropMethod, rect_center_x: Option<usize>, rect_center_y: Option<usize>) {
         if new_width == 0 || new_height == 0 || new_width > self.width || new_height > self.height {
@@ -123,21 +361,79 @@ impl Image {
             }
 
             CropMethod::Rectangular => {
-                let x_offset = rect_center_x.unwrap_or((self.width - new_width) / 2);
-                let y_offset = rect_center_y.unwrap_or((self.height - new_height) / 2);
+                // Offsets past the last position that fits are pulled back so the region stays inside
+                let x_offset = rect_center_x.unwrap_or(w_diff / 2).min(w_diff);
+                let y_offset = rect_center_y.unwrap_or(h_diff / 2).min(h_diff);
 
-                self.crop_rect(new_width, new_height, x_offset, y_offset);
+                self.crop_rect(new_width, new_height, x_offset, y_offset)
+                    .expect("Clamped offsets keep the region inside the image");
             }
         }
     }
 
-    /// Reduces the width and height of the Image to the given values
-    pub fn seam_carve(&mut self, new_width: usize, new_height: usize) {
-        self.seam_carve_width(new_width);
-        self.seam_carve_height(new_height);
+    /// Carves or grows the width and height of the Image to the given values
+    pub fn seam_carve(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.seam_carve_directed(
+            new_width,
+            new_height,
+            SeamDirection::Both,
+            &SeamOptions::default(),
+            None,
+        )
+    }
+
+    /// Seam carves only along the given direction, leaving the other dimension untouched. Errors
+    /// when a carved dimension cannot be reached by removing or inserting seams
+    pub fn seam_carve_directed(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+        direction: SeamDirection,
+        options: &SeamOptions,
+        mut profile: Option<&mut SeamProfile>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Cannot seam carve an empty image".into());
+        }
+        let check = |axis: &str, current: usize, target: usize| -> Result<(), Box<dyn Error>> {
+            if target == 0 {
+                return Err(format!(
+                    "Seam carving cannot reduce the {axis} to 0, at least one seam must remain"
+                )
+                .into());
+            }
+            if target > current && current < 2 {
+                return Err(format!(
+                    "Seam carving cannot grow a {axis} of {current} to {target}, use scale instead"
+                )
+                .into());
+            }
+            Ok(())
+        };
+        if !matches!(direction, SeamDirection::Horizontal) {
+            check("width", self.width, new_width)?;
+        }
+        if !matches!(direction, SeamDirection::Vertical) {
+            check("height", self.height, new_height)?;
+        }
+
+        match direction {
+            SeamDirection::Vertical => self.seam_carve_width_with(new_width, options, profile),
+            SeamDirection::Horizontal => self.seam_carve_height_with(new_height, options, profile),
+            SeamDirection::Both => {
+                self.seam_carve_width_with(new_width, options, profile.as_deref_mut());
+                self.seam_carve_height_with(new_height, options, profile);
+            }
+        }
+        Ok(())
     }
 
-    /// Multiplies each pixels { r, g, b } values by the given scalars. Clamps to [0, 1]
+    /// Multiplies each pixels { r, g, b } values by the given scalars. Clamps the scalars to [0, 16]
+    /// and the results to max_intensity
     pub fn scale_rgb(
         &mut self,
         r_scale: f64,
@@ -145,75 +441,1486 @@ impl Image {
         b_scale: f64,
     ) -> Result<(), Box<dyn Error>> {
         let (r_scale, g_scale, b_scale) = (
-            r_scale.clamp(0.0, 1.0),
-            g_scale.clamp(0.0, 1.0),
-            b_scale.clamp(0.0, 1.0),
-        );
-
-        for row in 0..self.height {
-            for col in 0..self.width {
-                let curr_color = self
-                    .get_pixel(row, col)
-                    .ok_or("Pixel indices out of bounds")?;
-                let new_color = PixelRGB {
-                    r: (curr_color.r as f64 * r_scale) as usize,
-                    g: (curr_color.g as f64 * g_scale) as usize,
-                    b: (curr_color.b as f64 * b_scale) as usize,
-                };
-                self.set_pixel(row, col, new_color);
-            }
+            r_scale.clamp(0.0, MAX_RGB_SCALE),
+            g_scale.clamp(0.0, MAX_RGB_SCALE),
+            b_scale.clamp(0.0, MAX_RGB_SCALE),
+        );
+
+        let max_intensity = self.max_intensity;
+        for (_, _, mut pixel) in self.iter_pixels_mut() {
+            let curr_color = pixel.get();
+            pixel.set(PixelRGB {
+                r: clamp_channel(curr_color.r as f64 * r_scale, max_intensity),
+                g: clamp_channel(curr_color.g as f64 * g_scale, max_intensity),
+                b: clamp_channel(curr_color.b as f64 * b_scale, max_intensity),
+            });
+        }
+        Ok(())
+    }
+
+    /// Offsets every channel by the brightness and stretches it by the contrast about the midpoint
+    pub fn adjust(&mut self, brightness: i32, contrast: f64) {
+        let max_intensity = self.max_intensity;
+        let mid = (max_intensity + 1) as f64 / 2.0;
+        let offset = brightness as f64;
+
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            channel.apply_in_place(|value| {
+                let adjusted = (*value as f64 - mid) * contrast + mid + offset;
+                *value = clamp_channel(adjusted, max_intensity);
+            });
+        }
+    }
+
+    /// Replaces every channel value v with max_intensity - v, producing a negative
+    pub fn invert(&mut self) {
+        let max_intensity = self.max_intensity;
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            channel.apply_in_place(|value| *value = max_intensity.saturating_sub(*value));
+        }
+    }
+
+    /// Applies gamma correction, mapping each channel v to max * (v / max)^(1 / gamma) through a
+    /// lookup table built once per intensity
+    pub fn gamma(&mut self, gamma: f64) -> Result<(), Box<dyn Error>> {
+        if !gamma.is_finite() || gamma <= 0.0 {
+            return Err("Gamma must be a positive number".into());
+        }
+        if self.max_intensity == 0 {
+            return Ok(());
+        }
+
+        let max = self.max_intensity as f64;
+        let table: Vec<usize> = (0..=self.max_intensity)
+            .map(|v| (max * (v as f64 / max).powf(1.0 / gamma)).round() as usize)
+            .collect();
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            channel.apply_in_place(|value| *value = table[(*value).min(self.max_intensity)]);
         }
         Ok(())
     }
 
     /// Shifts the hue of every pixel by the given degrees (0–360), wraps around the color wheel.
     pub fn hue_shift(&mut self, degrees: f64) -> Result<(), Box<dyn std::error::Error>> {
-        for row in 0..self.height {
-            for col in 0..self.width {
-                let pixel = self.get_pixel(row, col).ok_or("Pixel out of bounds")?;
-                let (h, s, l) = Self::rgb_to_hsl(pixel.r as f64, pixel.g as f64, pixel.b as f64);
+        self.map_hsl(|h, s, l| ((h + degrees) % 360.0, s, l));
+        Ok(())
+    }
 
-                let new_h = (h + degrees) % 360.0;
-                let (r, g, b) = Self::hsl_to_rgb(new_h, s, l);
+    /// Shifts the hue of only the pixels whose hue lies in [from_deg, to_deg), measured around the
+    /// color wheel so a band like 330 to 30 wraps through red. Gray pixels have no hue and are kept
+    pub fn hue_shift_range(&mut self, from_deg: f64, to_deg: f64, shift: f64) {
+        let whole_wheel = to_deg - from_deg >= 360.0;
+        let span = (to_deg - from_deg).rem_euclid(360.0);
 
-                self.set_pixel(row, col, PixelRGB { r, g, b });
+        self.map_hsl(|h, s, l| {
+            let in_band = whole_wheel || (h - from_deg).rem_euclid(360.0) < span;
+            if s > 0.0 && in_band {
+                ((h + shift).rem_euclid(360.0), s, l)
+            } else {
+                (h, s, l)
             }
+        });
+    }
+
+    /// Boosts saturation more for muted pixels than for already vivid ones. Negative amounts mute
+    pub fn vibrance(&mut self, amount: f64) {
+        self.map_hsl(|h, s, l| (h, (s * (1.0 + amount * (1.0 - s))).clamp(0.0, 1.0), l));
+    }
+
+    /// Multiplies the saturation of every pixel by the factor, clamped to [0, 1]. A factor of 0
+    /// removes all color while factors above 1 make colors more vivid
+    pub fn adjust_saturation(&mut self, factor: f64) {
+        self.map_hsl(|h, s, l| (h, (s * factor).clamp(0.0, 1.0), l));
+    }
+
+    /// Replaces every pixel with its weighted luminance, defaulting to Rec. 601 weights
+    pub fn grayscale(&mut self, weights: Option<(f64, f64, f64)>) {
+        let weights = weights.unwrap_or(REC601_WEIGHTS);
+        let max_intensity = self.max_intensity;
+
+        for (_, _, mut pixel) in self.iter_pixels_mut() {
+            let gray = clamp_channel(pixel.get().luminance(weights), max_intensity);
+            pixel.set(PixelRGB {
+                r: gray,
+                g: gray,
+                b: gray,
+            });
         }
-        Ok(())
+    }
+
+    /// Exchanges the contents of two color channels, such as red and blue to fix BGR data
+    pub fn swap_channels(&mut self, a: Channel, b: Channel) {
+        if a == b {
+            return;
+        }
+        let first = self.channel(a).clone();
+        *self.channel_mut(a) = std::mem::replace(self.channel_mut(b), first);
+    }
+
+    /// Grayscale image whose gray levels are the given channel's values. Alpha is kept
+    pub fn extract_channel(&self, channel: Channel) -> Image {
+        let source = self.channel(channel);
+        Image {
+            width: self.width,
+            height: self.height,
+            max_intensity: self.max_intensity,
+            red_channel: source.clone(),
+            blue_channel: source.clone(),
+            green_channel: source.clone(),
+            alpha_channel: self.alpha_channel.clone(),
+            format: self.format.clone(),
+        }
+    }
+
+    /// Tones every pixel with SEPIA_MATRIX. The red and green rows sum past 1, so bright pixels
+    /// saturate at max_intensity instead of wrapping
+    pub fn sepia(&mut self) {
+        let max_intensity = self.max_intensity;
+
+        for (_, _, mut pixel) in self.iter_pixels_mut() {
+            let source = pixel.get();
+            let [r, g, b] = SEPIA_MATRIX.map(|[wr, wg, wb]| {
+                let value = source.r as f64 * wr + source.g as f64 * wg + source.b as f64 * wb;
+                clamp_channel(value, max_intensity)
+            });
+            pixel.set(PixelRGB { r, g, b });
+        }
+    }
+
+    /// Binarizes the image, turning pixels whose Rec. 601 luminance is at least level white and the
+    /// rest black. Luminance is rounded the same way grayscale rounds it
+    pub fn threshold(&mut self, level: usize) {
+        let max_intensity = self.max_intensity;
+
+        for (_, _, mut pixel) in self.iter_pixels_mut() {
+            let gray = clamp_channel(pixel.get().luminance(REC601_WEIGHTS), max_intensity);
+            let value = if gray >= level { max_intensity } else { 0 };
+            pixel.set(PixelRGB {
+                r: value,
+                g: value,
+                b: value,
+            });
+        }
+    }
+
+    /// Decodes the sRGB transfer function so channel values are proportional to light intensity.
+    /// The curve packs the darkest encoded levels into a sliver of linear light, so shallower
+    /// images are first raised to a max_intensity of 65535 to keep them distinct
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_linear(&mut self) {
+        self.rescale_intensity(self.max_intensity.max(u16::MAX as usize));
+        self.map_channels(srgb_to_linear);
+    }
+
+    /// Re-encodes linear light channel values with the sRGB transfer function, keeping the current
+    /// max_intensity. Follow with rescale_intensity to return to the depth before to_linear
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_srgb(&mut self) {
+        self.map_channels(linear_to_srgb);
+    }
+
+    /// Changes max_intensity, rescaling every channel including alpha to the new range
+    pub fn rescale_intensity(&mut self, max_intensity: usize) {
+        if max_intensity == self.max_intensity {
+            return;
+        }
+        let scale = max_intensity as f64 / self.max_intensity.max(1) as f64;
+        for channel in self.all_channels_mut() {
+            channel.apply_in_place(|value| {
+                *value = clamp_channel(*value as f64 * scale, max_intensity);
+            });
+        }
+        self.max_intensity = max_intensity;
     }
 
     /// Mirrors the images pixel maps about the horizontal axis
     pub fn mirror_x(&mut self) {
-        self.red_channel.mirror_x();
-        self.green_channel.mirror_x();
-        self.blue_channel.mirror_x();
+        for channel in self.all_channels_mut() {
+            channel.mirror_x();
+        }
     }
 
     /// Mirrors the images pixel maps about the vertical axis
     pub fn mirror_y(&mut self) {
-        self.red_channel.mirror_y();
-        self.green_channel.mirror_y();
-        self.blue_channel.mirror_y();
+        for channel in self.all_channels_mut() {
+            channel.mirror_y();
+        }
+    }
+
+    /// Applies the rotation and mirroring that brings an image stored with the orientation upright
+    pub fn apply_orientation(&mut self, orientation: Orientation) {
+        match orientation {
+            Orientation::NoTransforms => {}
+            Orientation::Rotate90 => self.rotate_right(),
+            Orientation::Rotate180 => {
+                self.mirror_x();
+                self.mirror_y();
+            }
+            Orientation::Rotate270 => self.rotate_left(),
+            Orientation::FlipHorizontal => self.mirror_y(),
+            Orientation::FlipVertical => self.mirror_x(),
+            Orientation::Rotate90FlipH => {
+                self.rotate_right();
+                self.mirror_y();
+            }
+            Orientation::Rotate270FlipH => {
+                self.rotate_left();
+                self.mirror_y();
+            }
+        }
     }
 
     /// Transposes the image
     pub fn transpose(&mut self) {
-        self.red_channel.transpose();
-        self.green_channel.transpose();
-        self.blue_channel.transpose();
+        for channel in self.all_channels_mut() {
+            channel.transpose();
+        }
         std::mem::swap(&mut self.width, &mut self.height);
     }
 
+    /// Draws other over the image with its top-left corner at (x, y), clipping whatever falls
+    /// outside. Pixels are alpha composited, so images without alpha are copied straight across
+    pub fn paste(&mut self, other: &Image, x: usize, y: usize) {
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+        let scale = max / other.max_intensity.max(1) as f64;
+        let rows = other.height.min(self.height.saturating_sub(y));
+        let cols = other.width.min(self.width.saturating_sub(x));
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let (dst_row, dst_col) = (y + row, x + col);
+                let src = other.get_pixel_rgba(row, col).unwrap();
+                let dst = self.get_pixel_rgba(dst_row, dst_col).unwrap();
+
+                // Porter-Duff source over destination on straight (unpremultiplied) alpha
+                let src_alpha = (src.a as f64 * scale / max).min(1.0);
+                let dst_weight = dst.a as f64 / max * (1.0 - src_alpha);
+                let alpha = src_alpha + dst_weight;
+                let blend = |s: usize, d: usize| match alpha {
+                    0.0 => 0,
+                    _ => {
+                        let value = s as f64 * scale * src_alpha + d as f64 * dst_weight;
+                        clamp_channel(value / alpha, max_intensity)
+                    }
+                };
+
+                let color = PixelRGB {
+                    r: blend(src.r, dst.r),
+                    g: blend(src.g, dst.g),
+                    b: blend(src.b, dst.b),
+                };
+                self.set_pixel(dst_row, dst_col, color);
+                if let Some(dst_alpha) = &mut self.alpha_channel {
+                    dst_alpha[(dst_row, dst_col)] = clamp_channel(alpha * max, max_intensity);
+                }
+            }
+        }
+    }
+
+    /// Composites the image over a solid background and drops its alpha channel. Images without
+    /// alpha are left untouched
+    pub fn flatten(&mut self, background: PixelRGB) {
+        self.flatten_over(|_, _| background);
+    }
+
+    /// Composites the image over a checkerboard of size x size squares alternating between c1,
+    /// which takes the top-left square, and c2, then drops its alpha channel
+    pub fn flatten_checker(&mut self, size: usize, c1: PixelRGB, c2: PixelRGB) {
+        let size = size.max(1);
+        self.flatten_over(|row, col| match (row / size + col / size) % 2 {
+            0 => c1,
+            _ => c2,
+        });
+    }
+
+    /// Makes pixels within tolerance of the key color fully transparent, adding an alpha channel
+    /// when the image has none. Distance is Euclidean across the channels, and pixels up to one
+    /// more tolerance away fade back to opaque for a softer matte edge. Existing transparency is
+    /// never reduced
+    pub fn color_to_alpha(&mut self, key: PixelRGB, tolerance: usize) {
+        let max_intensity = self.max_intensity;
+        let (width, height) = (self.width, self.height);
+        let mut alpha = self
+            .alpha_channel
+            .take()
+            .unwrap_or_else(|| Matrix::new_filled(width, height, max_intensity));
+        let tolerance = tolerance as f64;
+
+        for row in 0..height {
+            for col in 0..width {
+                let pixel = self.get_pixel(row, col).unwrap();
+                let distance = (pixel.squared_difference(&key) as f64).sqrt();
+                let coverage = if distance <= tolerance {
+                    0.0
+                } else if distance >= 2.0 * tolerance {
+                    1.0
+                } else {
+                    (distance - tolerance) / tolerance
+                };
+                let keyed = clamp_channel(coverage * max_intensity as f64, max_intensity);
+                alpha[(row, col)] = alpha[(row, col)].min(keyed);
+            }
+        }
+
+        self.alpha_channel = Some(alpha);
+    }
+
+    /// Blends every pixel with the background color at its position by the pixel's alpha
+    fn flatten_over<F>(&mut self, background: F)
+    where
+        F: Fn(usize, usize) -> PixelRGB,
+    {
+        let Some(alpha) = self.alpha_channel.take() else {
+            return;
+        };
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+
+        for (row, col, mut pixel) in self.iter_pixels_mut() {
+            let weight = alpha[(row, col)].min(max_intensity) as f64 / max;
+            let (source, backdrop) = (pixel.get(), background(row, col));
+            let mix = |s: usize, b: usize| {
+                clamp_channel(s as f64 * weight + b as f64 * (1.0 - weight), max_intensity)
+            };
+            pixel.set(PixelRGB {
+                r: mix(source.r, backdrop.r),
+                g: mix(source.g, backdrop.g),
+                b: mix(source.b, backdrop.b),
+            });
+        }
+    }
+
     /// Saves the image to a file with the filetype inferred from the output path
     pub fn save(&self, output_path: &str) -> Result<(), Box<dyn Error>> {
+        self.save_with_options(output_path, &EncodeOptions::default())
+    }
+
+    /// Saves the image like save, passing the encoder settings through to formats that use them
+    pub fn save_with_options(
+        &self,
+        output_path: &str,
+        options: &EncodeOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        self.save_with(output_path, None, options)
+    }
+
+    /// Saves the image in the given format, or the one inferred from the path when None
+    pub fn save_as(
+        &self,
+        output_path: &str,
+        format: Option<ImageFormat>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.save_with(output_path, format, &EncodeOptions::default())
+    }
+
+    /// Saves the image in the given or inferred format with the given encoder settings. A path of
+    /// "-" writes to stdout, which has no extension to infer from and so requires a format
+    pub fn save_with(
+        &self,
+        output_path: &str,
+        format: Option<ImageFormat>,
+        options: &EncodeOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        if output_path == STDIO_PATH {
+            let format = format.ok_or("Writing to stdout requires an explicit --format")?;
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&self.encode(format, options)?)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+
         let path = Path::new(output_path);
 
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let bytes = self.bytes_format(PPMFormat::P6)?;
-        ppm_bytes_to_img(&bytes, output_path)?;
-        Ok(())
+        let format = match format {
+            Some(format) => format,
+            None => infer_type(output_path)?,
+        };
+        write_atomic(path, &self.encode(format, options)?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Directory holding the fixture images and the expected outputs they are compared against
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+    /// Set to rewrite the expected outputs from the current results instead of comparing
+    const BLESS_VAR: &str = "SNAP_BLESS";
+
+    fn golden_path(name: &str) -> String {
+        format!("{}/{}.ppm", GOLDEN_DIR, name)
+    }
+
+    /// Loads the named PPM from the golden directory
+    pub(crate) fn load_fixture(name: &str) -> Image {
+        let path = golden_path(name);
+        let bytes = fs::read(&path).unwrap_or_else(|e| panic!("Cannot read '{}': {}", path, e));
+        Image::from_bytes(&bytes).unwrap_or_else(|e| panic!("Cannot parse '{}': {}", path, e))
+    }
+
+    /// Path in the system temp directory that is unique to this test run and name
+    pub(crate) fn temp_path(name: &str) -> String {
+        let file = format!("snap-test-{}-{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// 8-bit image of a single color
+    pub(crate) fn solid_image(width: usize, height: usize, color: PixelRGB) -> Image {
+        let mut image = Image::new(width, height, 255, PPMFormat::P6);
+        image.fill(color);
+        image
+    }
+
+    /// Deterministic image of random 8-bit pixels, for checks that need more than a fixture
+    pub(crate) fn noise_image(width: usize, height: usize, seed: u64) -> Image {
+        let mut rng = crate::img::random::Rng::new(seed);
+        let mut image = Image::new(width, height, 255, PPMFormat::P6);
+        for row in 0..height {
+            for col in 0..width {
+                let [r, g, b] = [(); 3].map(|_| rng.below(256));
+                image.set_pixel(row, col, PixelRGB { r, g, b });
+            }
+        }
+        image
+    }
+
+    /// Compares the image byte for byte against the named golden as an ASCII PPM, pointing at the
+    /// first pixel that differs. With SNAP_BLESS set the golden is rewritten instead
+    pub(crate) fn assert_golden(image: &Image, name: &str) {
+        let actual = image.bytes_format(PPMFormat::P3).unwrap();
+        if std::env::var_os(BLESS_VAR).is_some() {
+            fs::write(golden_path(name), &actual).unwrap();
+            return;
+        }
+
+        let expected = load_fixture(name);
+        assert_eq!(
+            (image.width, image.height, image.max_intensity),
+            (expected.width, expected.height, expected.max_intensity),
+            "Golden '{}' has a different size or max intensity",
+            name
+        );
+        for row in 0..image.height {
+            for col in 0..image.width {
+                assert_eq!(
+                    image.get_pixel_rgba(row, col),
+                    expected.get_pixel_rgba(row, col),
+                    "Golden '{}' differs at row {}, col {}",
+                    name,
+                    row,
+                    col
+                );
+            }
+        }
+        assert_eq!(
+            actual,
+            fs::read(golden_path(name)).unwrap(),
+            "Golden '{}' bytes differ",
+            name
+        );
+    }
+
+    /// Panics if any channel value, alpha included, is above the image's max intensity
+    fn assert_within_max_intensity(image: &Image, operation: &str) {
+        let mut channels = vec![
+            &image.red_channel,
+            &image.green_channel,
+            &image.blue_channel,
+        ];
+        channels.extend(image.alpha_channel.as_ref());
+        for channel in channels {
+            for row in 0..channel.height {
+                for col in 0..channel.width {
+                    assert!(
+                        channel[(row, col)] <= image.max_intensity,
+                        "{} left {} above max intensity {}",
+                        operation,
+                        channel[(row, col)],
+                        image.max_intensity
+                    );
+                }
+            }
+        }
+    }
+
+    /// A named operation run against every test image
+    type NamedOperation = (&'static str, fn(&mut Image));
+
+    #[test]
+    fn no_operation_exceeds_max_intensity() {
+        let operations: [NamedOperation; 12] = [
+            ("scale_rgb", |i| i.scale_rgb(16.0, 9.0, 40.0).unwrap()),
+            ("adjust", |i| i.adjust(200, 8.0)),
+            ("invert", |i| i.invert()),
+            ("gamma", |i| i.gamma(0.05).unwrap()),
+            ("hue_shift", |i| i.hue_shift(123.0).unwrap()),
+            ("vibrance", |i| i.vibrance(10.0)),
+            ("adjust_saturation", |i| i.adjust_saturation(25.0)),
+            ("grayscale", |i| i.grayscale(Some((1.0, 1.0, 1.0)))),
+            ("sepia", |i| i.sepia()),
+            ("to_srgb", |i| i.to_srgb()),
+            ("bilinear scale", |i| i.scale(13, 9, ScaleMethod::Bilinear)),
+            ("area scale", |i| i.scale(3, 2, ScaleMethod::Area)),
+        ];
+
+        let bright = noise_image(7, 5, 1992);
+        let mut low_depth = bright.clone();
+        low_depth.max_intensity = 15;
+        for channel in low_depth.all_channels_mut() {
+            channel.apply_in_place(|value| *value /= 17);
+        }
+
+        for source in [bright, low_depth] {
+            for (name, operation) in operations {
+                let mut image = source.clone();
+                operation(&mut image);
+                assert_within_max_intensity(&image, name);
+            }
+        }
+    }
+
+    fn saturation(pixel: PixelRGB) -> f64 {
+        let (r, g, b) = (pixel.r as f64, pixel.g as f64, pixel.b as f64);
+        Image::rgb_to_hsl(r / 255.0, g / 255.0, b / 255.0).1
+    }
+
+    #[test]
+    fn vibrance_boosts_muted_colors_more() {
+        let saturated = PixelRGB { r: 255, g: 0, b: 0 };
+        let muted = PixelRGB {
+            r: 140,
+            g: 120,
+            b: 120,
+        };
+        let mut image = solid_image(2, 1, saturated);
+        image.set_pixel(0, 1, muted);
+        image.vibrance(1.0);
+
+        let saturated_gain = saturation(image.get_pixel(0, 0).unwrap()) - saturation(saturated);
+        let muted_gain = saturation(image.get_pixel(0, 1).unwrap()) - saturation(muted);
+        assert!(
+            saturated_gain.abs() < 0.01,
+            "saturated gained {}",
+            saturated_gain
+        );
+        assert!(muted_gain > 0.05, "muted gained {}", muted_gain);
+    }
+
+    #[test]
+    fn linear_round_trip_stays_within_tolerance() {
+        let source = noise_image(16, 16, 2005);
+        let mut image = source.clone();
+        image.to_linear();
+        image.to_srgb();
+
+        assert_eq!(image.max_intensity, 65535);
+        image.rescale_intensity(source.max_intensity);
+
+        // Linear values are held at 16 bits, so even the darkest levels come back within one step
+        for (row, col, pixel) in source.iter_pixels() {
+            let round_trip = image.get_pixel(row, col).unwrap();
+            for (before, after) in [
+                (pixel.r, round_trip.r),
+                (pixel.g, round_trip.g),
+                (pixel.b, round_trip.b),
+            ] {
+                assert!(before.abs_diff(after) <= 1, "{} became {}", before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn linear_round_trip_keeps_every_dark_level() {
+        let mut ramp = Image::new(32, 1, 255, PPMFormat::P6);
+        for col in 0..32 {
+            ramp.set_pixel(
+                0,
+                col,
+                PixelRGB {
+                    r: col,
+                    g: col,
+                    b: col,
+                },
+            );
+        }
+        let mut image = ramp.clone();
+        image.to_linear();
+        image.to_srgb();
+        image.rescale_intensity(255);
+        assert_eq!(image, ramp);
+    }
+
+    #[test]
+    fn rescale_intensity_maps_between_depths() {
+        let mut image = solid_image(
+            2,
+            1,
+            PixelRGB {
+                r: 255,
+                g: 128,
+                b: 1,
+            },
+        );
+        image.alpha_channel = Some(Matrix::new_filled(2, 1, 51));
+        image.rescale_intensity(65535);
+        assert_eq!(image.max_intensity, 65535);
+        assert_eq!(
+            image.get_pixel_rgba(0, 0).map(|p| (p.r, p.g, p.b, p.a)),
+            Some((65535, 32896, 257, 13107))
+        );
+        image.rescale_intensity(255);
+        assert_eq!(
+            image.get_pixel(0, 1).map(|p| (p.r, p.g, p.b)),
+            Some((255, 128, 1))
+        );
+    }
+
+    #[test]
+    fn linear_blend_of_black_and_white_is_brighter() {
+        let mut pair = solid_image(2, 1, PixelRGB { r: 0, g: 0, b: 0 });
+        pair.set_pixel(0, 1, PixelRGB::from_name("white").unwrap());
+
+        // Averaging both pixels into one is a 50% blend
+        let mut srgb = pair.clone();
+        srgb.scale(1, 1, ScaleMethod::Area);
+        let mut linear = pair;
+        linear.to_linear();
+        linear.scale(1, 1, ScaleMethod::Area);
+        linear.to_srgb();
+        linear.rescale_intensity(255);
+
+        assert_eq!(srgb.get_pixel(0, 0).unwrap().r, 128);
+        assert_eq!(linear.get_pixel(0, 0).unwrap().r, 188);
+    }
+
+    #[test]
+    fn grayscale_maps_pure_red_by_its_weight() {
+        let mut image = solid_image(2, 1, PixelRGB { r: 255, g: 0, b: 0 });
+        image.set_pixel(0, 1, PixelRGB { r: 0, g: 0, b: 255 });
+        image.grayscale(None);
+        // 0.299 * 255 = 76.2 and 0.114 * 255 = 29.1, rounded rather than truncated
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 76,
+                g: 76,
+                b: 76
+            })
+        );
+        assert_eq!(
+            image.get_pixel(0, 1),
+            Some(PixelRGB {
+                r: 29,
+                g: 29,
+                b: 29
+            })
+        );
+
+        let mut image = solid_image(1, 1, PixelRGB { r: 255, g: 0, b: 0 });
+        image.grayscale(Some((0.5, 0.25, 0.25)));
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 128,
+                g: 128,
+                b: 128
+            })
+        );
+    }
+
+    #[test]
+    fn adjust_clamps_at_both_ends() {
+        let mut bright = solid_image(
+            1,
+            1,
+            PixelRGB {
+                r: 200,
+                g: 60,
+                b: 255,
+            },
+        );
+        bright.adjust(100, 1.0);
+        assert_eq!(
+            bright.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 255,
+                g: 160,
+                b: 255
+            })
+        );
+
+        let mut dark = solid_image(
+            1,
+            1,
+            PixelRGB {
+                r: 200,
+                g: 60,
+                b: 0,
+            },
+        );
+        dark.adjust(-100, 1.0);
+        assert_eq!(dark.get_pixel(0, 0), Some(PixelRGB { r: 100, g: 0, b: 0 }));
+
+        // Contrast stretches about the 128 midpoint of an 8-bit image
+        let mut stretched = solid_image(
+            1,
+            1,
+            PixelRGB {
+                r: 200,
+                g: 100,
+                b: 128,
+            },
+        );
+        stretched.adjust(0, 3.0);
+        assert_eq!(
+            stretched.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 255,
+                g: 44,
+                b: 128
+            })
+        );
+    }
+
+    #[test]
+    fn neutral_adjust_is_the_identity() {
+        let source = noise_image(9, 7, 2009);
+        let mut image = source.clone();
+        image.adjust(0, 1.0);
+        assert_eq!(image, source);
+    }
+
+    #[test]
+    fn from_channels_builds_an_image_from_constant_matrices() {
+        let image = Image::from_channels(
+            Matrix::new_filled(4, 3, 10),
+            Matrix::new_filled(4, 3, 20),
+            Matrix::new_filled(4, 3, 30),
+            255,
+            PPMFormat::P6,
+        )
+        .unwrap();
+
+        assert_eq!((image.width, image.height), (4, 3));
+        assert!(image.alpha_channel.is_none());
+        assert!(image.iter_pixels().all(|(_, _, pixel)| pixel
+            == PixelRGB {
+                r: 10,
+                g: 20,
+                b: 30
+            }));
+    }
+
+    #[test]
+    fn from_channels_rejects_mismatched_dimensions() {
+        let wide = Image::from_channels(
+            Matrix::new_filled(4, 3, 0),
+            Matrix::new_filled(5, 3, 0),
+            Matrix::new_filled(4, 3, 0),
+            255,
+            PPMFormat::P6,
+        );
+        assert!(wide.is_err());
+
+        let tall = Image::from_channels(
+            Matrix::new_filled(4, 3, 0),
+            Matrix::new_filled(4, 3, 0),
+            Matrix::new_filled(4, 2, 0),
+            255,
+            PPMFormat::P6,
+        );
+        assert!(tall.is_err());
+    }
+
+    #[test]
+    fn scale_rgb_amplifies_and_saturates() {
+        let mut mid = solid_image(
+            1,
+            1,
+            PixelRGB {
+                r: 100,
+                g: 100,
+                b: 100,
+            },
+        );
+        mid.scale_rgb(2.0, 1.0, 0.5).unwrap();
+        assert_eq!(
+            mid.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 200,
+                g: 100,
+                b: 50
+            })
+        );
+
+        let mut bright = solid_image(
+            1,
+            1,
+            PixelRGB {
+                r: 200,
+                g: 100,
+                b: 100,
+            },
+        );
+        bright.scale_rgb(2.0, 1.0, 1.0).unwrap();
+        assert_eq!(
+            bright.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 255,
+                g: 100,
+                b: 100
+            })
+        );
+    }
+
+    #[test]
+    fn rotate_right_matches_golden() {
+        let mut image = load_fixture("fixture");
+        image.rotate_right();
+        assert_golden(&image, "rotate_right");
+    }
+
+    #[test]
+    fn rotate_left_matches_golden() {
+        let mut image = load_fixture("fixture");
+        image.rotate_left();
+        assert_golden(&image, "rotate_left");
+    }
+
+    #[test]
+    fn mirror_x_matches_golden() {
+        let mut image = load_fixture("fixture");
+        image.mirror_x();
+        assert_golden(&image, "mirror_x");
+    }
+
+    #[test]
+    fn mirror_y_matches_golden() {
+        let mut image = load_fixture("fixture");
+        image.mirror_y();
+        assert_golden(&image, "mirror_y");
+    }
+
+    #[test]
+    fn crop_rect_matches_golden() {
+        let mut image = load_fixture("fixture");
+        image.crop_rect(3, 2, 1, 1).unwrap();
+        assert_golden(&image, "crop_rect");
+    }
+
+    #[test]
+    fn crop_right_top_matches_golden() {
+        let mut image = load_fixture("fixture");
+        image.crop(2, 2, CropMethod::RightTop, None, None);
+        assert_golden(&image, "crop_right_top");
+    }
+
+    #[test]
+    fn invert_maps_zero_to_max_and_twice_is_the_identity() {
+        let mut black = solid_image(2, 2, PixelRGB { r: 0, g: 0, b: 0 });
+        black.invert();
+        assert!(black.iter_pixels().all(|(_, _, pixel)| pixel
+            == PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255
+            }));
+
+        let source = noise_image(9, 7, 2011);
+        let mut image = source.clone();
+        image.invert();
+        assert_ne!(image, source);
+        image.invert();
+        assert_eq!(image, source);
+    }
+
+    #[test]
+    fn flatten_half_transparent_red_over_white_gives_pink() {
+        let mut image = solid_image(2, 2, PixelRGB { r: 255, g: 0, b: 0 });
+        image.alpha_channel = Some(Matrix::new_filled(2, 2, 128));
+        image.flatten(PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        });
+
+        assert!(image.alpha_channel.is_none());
+        assert!(image.iter_pixels().all(|(_, _, pixel)| pixel
+            == PixelRGB {
+                r: 255,
+                g: 127,
+                b: 127
+            }));
+    }
+
+    #[test]
+    fn flatten_leaves_opaque_images_untouched() {
+        let source = noise_image(5, 4, 2012);
+        let mut image = source.clone();
+        image.flatten(PixelRGB { r: 0, g: 0, b: 0 });
+        assert_eq!(image, source);
+    }
+
+    #[test]
+    fn hue_shift_range_moves_only_the_red_band() {
+        let red = PixelRGB { r: 255, g: 0, b: 0 };
+        let blue = PixelRGB { r: 0, g: 0, b: 255 };
+        let gray = PixelRGB {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        let mut image = solid_image(3, 1, red);
+        image.set_pixel(0, 1, blue);
+        image.set_pixel(0, 2, gray);
+
+        // The band wraps through 0 degrees
+        image.hue_shift_range(330.0, 30.0, 120.0);
+        assert_eq!(image.get_pixel(0, 0), Some(PixelRGB { r: 0, g: 255, b: 0 }));
+        assert_eq!(image.get_pixel(0, 1), Some(blue));
+        assert_eq!(image.get_pixel(0, 2), Some(gray));
+    }
+
+    #[test]
+    fn gamma_one_is_the_identity() {
+        let source = noise_image(9, 7, 2020);
+        let mut image = source.clone();
+        image.gamma(1.0).unwrap();
+        assert_eq!(image, source);
+    }
+
+    #[test]
+    fn gamma_brightens_mid_tones_and_fixes_the_ends() {
+        let mut image = solid_image(3, 1, PixelRGB { r: 0, g: 0, b: 0 });
+        image.set_pixel(
+            0,
+            1,
+            PixelRGB {
+                r: 64,
+                g: 128,
+                b: 192,
+            },
+        );
+        image.set_pixel(
+            0,
+            2,
+            PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+
+        image.gamma(2.2).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Some(PixelRGB { r: 0, g: 0, b: 0 }));
+        // 255 * (v / 255)^(1 / 2.2)
+        assert_eq!(
+            image.get_pixel(0, 1),
+            Some(PixelRGB {
+                r: 136,
+                g: 186,
+                b: 224
+            })
+        );
+        assert_eq!(
+            image.get_pixel(0, 2),
+            Some(PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+    }
+
+    #[test]
+    fn zero_saturation_leaves_only_gray() {
+        let mut image = noise_image(9, 7, 2021);
+        image.adjust_saturation(0.0);
+        for (_, _, pixel) in image.iter_pixels() {
+            assert_eq!(pixel.r, pixel.g);
+            assert_eq!(pixel.g, pixel.b);
+        }
+    }
+
+    #[test]
+    fn saturation_above_one_widens_the_channel_spread() {
+        let muted = PixelRGB {
+            r: 150,
+            g: 110,
+            b: 100,
+        };
+        let mut image = solid_image(1, 1, muted);
+        image.adjust_saturation(2.0);
+        let vivid = image.get_pixel(0, 0).unwrap();
+        assert!(vivid.r > muted.r && vivid.b < muted.b, "{:?}", vivid);
+
+        // Saturation clamps at fully vivid
+        image.adjust_saturation(100.0);
+        let clamped = image.get_pixel(0, 0).unwrap();
+        image.adjust_saturation(100.0);
+        assert_eq!(image.get_pixel(0, 0), Some(clamped));
+    }
+
+    #[test]
+    fn geometric_operations_move_alpha_with_the_color() {
+        let mut source = load_fixture("fixture");
+        // Tag every pixel's alpha with its red value so the two can be compared after moving
+        source.alpha_channel = Some(source.red_channel.clone());
+
+        let operations: [NamedOperation; 5] = [
+            ("rotate_left", |i| i.rotate_left()),
+            ("rotate_right", |i| i.rotate_right()),
+            ("mirror_x", |i| i.mirror_x()),
+            ("mirror_y", |i| i.mirror_y()),
+            ("crop_rect", |i| i.crop_rect(3, 2, 1, 1).unwrap()),
+        ];
+        for (name, operation) in operations {
+            let mut image = source.clone();
+            operation(&mut image);
+            assert_eq!(
+                image.alpha_channel.as_ref(),
+                Some(&image.red_channel),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn resize_downscales_a_checkerboard_to_uniform_gray() {
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let mut board = solid_image(16, 12, PixelRGB { r: 0, g: 0, b: 0 });
+        for row in 0..12 {
+            for col in (row % 2..16).step_by(2) {
+                board.set_pixel(row, col, white);
+            }
+        }
+
+        let mut area = board.clone();
+        area.resize(4, 3, None, None, None, true).unwrap();
+        assert_eq!((area.width, area.height), (4, 3));
+        assert!(
+            area.iter_pixels()
+                .all(|(_, _, pixel)| pixel.r.abs_diff(128) <= 1
+                    && pixel.r == pixel.g
+                    && pixel.g == pixel.b)
+        );
+
+        // Point sampling lands on one color of the board every time
+        let mut sampled = board.clone();
+        sampled.linear_scale(4, 3);
+        assert!(
+            sampled
+                .iter_pixels()
+                .all(|(_, _, pixel)| pixel.r == 0 || pixel.r == 255)
+        );
+    }
+
+    #[test]
+    fn sepia_maps_known_colors_and_saturates_bright_ones() {
+        for (input, expected) in [
+            ((100, 50, 20), (82, 73, 57)),
+            ((255, 255, 255), (255, 255, 239)),
+            ((0, 0, 0), (0, 0, 0)),
+            ((0, 0, 200), (38, 34, 26)),
+        ] {
+            let (r, g, b) = input;
+            let mut image = solid_image(2, 2, PixelRGB { r, g, b });
+            image.sepia();
+            let (r, g, b) = expected;
+            assert!(
+                image
+                    .iter_pixels()
+                    .all(|(_, _, pixel)| pixel == PixelRGB { r, g, b }),
+                "{:?} gave {:?}",
+                input,
+                image.get_pixel(0, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn threshold_splits_at_the_gray_luminance() {
+        let gray = PixelRGB {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        for (level, expected) in [
+            (0, white),
+            (128, white),
+            (129, PixelRGB { r: 0, g: 0, b: 0 }),
+        ] {
+            let mut image = solid_image(3, 2, gray);
+            image.threshold(level);
+            assert!(
+                image.iter_pixels().all(|(_, _, pixel)| pixel == expected),
+                "level {}",
+                level
+            );
+        }
+
+        // Colored pixels are judged by the same luminance grayscale produces
+        let source = noise_image(8, 6, 2032);
+        let mut thresholded = source.clone();
+        thresholded.threshold(100);
+        let mut grayed = source.clone();
+        grayed.grayscale(None);
+        for (row, col, pixel) in thresholded.iter_pixels() {
+            let bright = grayed.get_pixel(row, col).unwrap().r >= 100;
+            assert_eq!(pixel.r == 255, bright, "({}, {})", row, col);
+        }
+    }
+
+    #[test]
+    fn swapping_red_and_blue_twice_is_the_identity() {
+        let source = noise_image(7, 5, 2034);
+        let mut swapped = source.clone();
+        swapped.swap_channels(Channel::Red, Channel::Blue);
+        for ((_, _, swapped), (_, _, original)) in swapped.iter_pixels().zip(source.iter_pixels()) {
+            assert_eq!(
+                swapped,
+                PixelRGB {
+                    r: original.b,
+                    g: original.g,
+                    b: original.r
+                }
+            );
+        }
+        swapped.swap_channels(Channel::Blue, Channel::Red);
+        assert_eq!(swapped, source);
+
+        let mut same = source.clone();
+        same.swap_channels(Channel::Green, Channel::Green);
+        assert_eq!(same, source);
+    }
+
+    #[test]
+    fn extracting_red_from_pure_red_gives_white() {
+        let red = solid_image(4, 3, PixelRGB { r: 255, g: 0, b: 0 });
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let black = PixelRGB { r: 0, g: 0, b: 0 };
+        for (channel, expected) in [
+            (Channel::Red, white),
+            (Channel::Green, black),
+            (Channel::Blue, black),
+        ] {
+            let extracted = red.extract_channel(channel);
+            assert_eq!((extracted.width, extracted.height), (4, 3));
+            assert!(
+                extracted
+                    .iter_pixels()
+                    .all(|(_, _, pixel)| pixel == expected),
+                "{:?}",
+                channel
+            );
+        }
+    }
+
+    #[test]
+    fn fit_within_shrinks_oversized_images_to_the_limiting_side() {
+        let source = noise_image(40, 20, 2036);
+        let mut kept = source.clone();
+        kept.fit_within(10, 10, true);
+        assert_eq!((kept.width, kept.height), (10, 5));
+
+        let mut tall = noise_image(20, 40, 2036);
+        tall.fit_within(30, 8, true);
+        assert_eq!((tall.width, tall.height), (4, 8));
+
+        // Without the aspect ratio each axis only shrinks to its own limit
+        let mut stretched = source.clone();
+        stretched.fit_within(10, 30, false);
+        assert_eq!((stretched.width, stretched.height), (10, 20));
+    }
+
+    #[test]
+    fn fit_within_never_enlarges() {
+        let source = noise_image(12, 9, 2036);
+        for (max_width, max_height, keep_aspect) in [
+            (12, 9, true),
+            (100, 100, true),
+            (50, 9, false),
+            (0, 40, true),
+        ] {
+            let mut image = source.clone();
+            image.fit_within(max_width, max_height, keep_aspect);
+            assert_eq!(image, source, "{}x{}", max_width, max_height);
+        }
+    }
+
+    #[test]
+    fn preserved_aspect_never_derives_a_zero_dimension() {
+        let wide = noise_image(1000, 10, 2038);
+        assert_eq!(wide.aspect_size(3, 0).unwrap(), (3, 1));
+        assert_eq!(wide.aspect_size(500, 0).unwrap(), (500, 5));
+        assert_eq!(
+            noise_image(10, 1000, 2038).aspect_size(0, 2).unwrap(),
+            (1, 2)
+        );
+
+        let (width, height) = wide.aspect_size(3, 0).unwrap();
+        let mut scaled = wide.clone();
+        scaled.scale(width, height, ScaleMethod::Area);
+        assert_eq!((scaled.width, scaled.height), (3, 1));
+
+        assert!(wide.aspect_size(0, 0).is_err());
+        assert!(wide.aspect_size(3, 4).is_err());
+    }
+
+    #[test]
+    fn resize_reports_unusable_crop_methods_as_errors() {
+        let source = noise_image(12, 9, 2039);
+        let mut image = source.clone();
+        let error = image
+            .resize(8, 9, None, Some(CropMethod::Top), None, true)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("cannot trim the width"),
+            "{}",
+            error
+        );
+        let error = image
+            .resize(12, 5, None, None, Some(CropMethod::LeftRight), true)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("cannot trim the height"),
+            "{}",
+            error
+        );
+        assert_eq!(image, source);
+
+        // Shrinking without a crop method averages down instead of failing
+        image.resize(8, 5, None, None, None, true).unwrap();
+        assert_eq!((image.width, image.height), (8, 5));
+
+        let mut cropped = source.clone();
+        cropped
+            .resize(
+                8,
+                5,
+                None,
+                Some(CropMethod::Left),
+                Some(CropMethod::Top),
+                true,
+            )
+            .unwrap();
+        assert_eq!((cropped.width, cropped.height), (8, 5));
+    }
+
+    #[test]
+    fn paste_changes_exactly_the_covered_pixels() {
+        let black = PixelRGB { r: 0, g: 0, b: 0 };
+        let red = PixelRGB { r: 255, g: 0, b: 0 };
+        let mut image = solid_image(4, 4, black);
+        image.paste(&solid_image(2, 2, red), 1, 1);
+
+        for (row, col, pixel) in image.iter_pixels() {
+            let covered = (1..3).contains(&row) && (1..3).contains(&col);
+            assert_eq!(
+                pixel,
+                if covered { red } else { black },
+                "({}, {})",
+                row,
+                col
+            );
+        }
+    }
+
+    #[test]
+    fn paste_clips_at_the_edges() {
+        let source = noise_image(4, 3, 2042);
+        let overlay = noise_image(3, 3, 4202);
+        let mut image = source.clone();
+        image.paste(&overlay, 2, 1);
+
+        for (row, col, pixel) in image.iter_pixels() {
+            let expected = match (row.checked_sub(1), col.checked_sub(2)) {
+                (Some(r), Some(c)) => overlay.get_pixel(r, c).unwrap(),
+                _ => source.get_pixel(row, col).unwrap(),
+            };
+            assert_eq!(pixel, expected, "({}, {})", row, col);
+        }
+
+        let mut untouched = source.clone();
+        untouched.paste(&overlay, 4, 3);
+        assert_eq!(untouched, source);
+    }
+
+    #[test]
+    fn paste_composites_translucent_overlays() {
+        let mut image = solid_image(
+            2,
+            1,
+            PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+        let mut overlay = solid_image(1, 1, PixelRGB { r: 255, g: 0, b: 0 });
+        overlay.alpha_channel = Some(Matrix::new_filled(1, 1, 128));
+        image.paste(&overlay, 1, 0);
+
+        assert_eq!(
+            image.get_pixel(0, 1),
+            Some(PixelRGB {
+                r: 255,
+                g: 127,
+                b: 127
+            })
+        );
+        assert!(image.alpha_channel.is_none());
+    }
+
+    #[test]
+    fn flatten_checker_shows_the_pattern_through_transparency() {
+        let (c1, c2) = (
+            PixelRGB {
+                r: 200,
+                g: 200,
+                b: 200,
+            },
+            PixelRGB {
+                r: 100,
+                g: 100,
+                b: 100,
+            },
+        );
+        let red = PixelRGB { r: 255, g: 0, b: 0 };
+        let mut image = solid_image(5, 4, red);
+        let mut alpha = Matrix::new_filled(5, 4, 0);
+        alpha[(3, 4)] = 255;
+        image.alpha_channel = Some(alpha);
+        image.flatten_checker(2, c1, c2);
+
+        assert!(image.alpha_channel.is_none());
+        for (row, col, pixel) in image.iter_pixels() {
+            let expected = match (row, col) {
+                (3, 4) => red,
+                _ if (row / 2 + col / 2) % 2 == 0 => c1,
+                _ => c2,
+            };
+            assert_eq!(pixel, expected, "({}, {})", row, col);
+        }
+    }
+
+    #[test]
+    fn shrinking_both_dimensions_only_crops() {
+        let source = noise_image(12, 9, 2044);
+        let mut expected = source.clone();
+        expected.crop_rect(8, 5, 4, 4).unwrap();
+
+        // Every method, or none, leaves the copied pixels exactly as they were
+        for method in [
+            None,
+            Some(ScaleMethod::Linear),
+            Some(ScaleMethod::Bilinear),
+            Some(ScaleMethod::Area),
+        ] {
+            let mut image = source.clone();
+            image
+                .resize(
+                    8,
+                    5,
+                    method.clone(),
+                    Some(CropMethod::Left),
+                    Some(CropMethod::Top),
+                    true,
+                )
+                .unwrap();
+            assert_eq!(image, expected, "{:?}", method);
+        }
+
+        let mut same = source.clone();
+        same.resize(12, 9, Some(ScaleMethod::Linear), None, None, true)
+            .unwrap();
+        assert_eq!(same, source);
+    }
+
+    #[test]
+    fn color_to_alpha_keys_a_white_background() {
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let black = PixelRGB { r: 0, g: 0, b: 0 };
+        let mut logo = solid_image(6, 6, white);
+        for (row, col) in [(2, 2), (2, 3), (3, 2), (3, 3)] {
+            logo.set_pixel(row, col, black);
+        }
+        // Within tolerance, then half way through the fade back to opaque
+        logo.set_pixel(
+            0,
+            0,
+            PixelRGB {
+                r: 250,
+                g: 250,
+                b: 250,
+            },
+        );
+        logo.set_pixel(
+            5,
+            5,
+            PixelRGB {
+                r: 255,
+                g: 255,
+                b: 225,
+            },
+        );
+        let colors = logo.clone();
+        logo.color_to_alpha(white, 20);
+
+        let alpha = logo.alpha_channel.as_ref().unwrap();
+        for (row, col, _) in colors.iter_pixels() {
+            let expected = match (row, col) {
+                (2..=3, 2..=3) => 255,
+                (5, 5) => 128,
+                _ => 0,
+            };
+            assert_eq!(alpha[(row, col)], expected, "({}, {})", row, col);
+        }
+        assert!(logo.iter_pixels().eq(colors.iter_pixels()));
+
+        // Keying again never makes anything more opaque
+        logo.color_to_alpha(black, 0);
+        let alpha = logo.alpha_channel.as_ref().unwrap();
+        assert_eq!((alpha[(2, 2)], alpha[(5, 5)], alpha[(0, 1)]), (0, 128, 0));
     }
 }
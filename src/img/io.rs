@@ -1,18 +1,30 @@
 use crate::img::image::*;
 use crate::img::matrix::*;
+use crate::img::utils::clamp_channel;
 
-use image::{ImageFormat, ImageReader, load_from_memory};
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::codecs::webp::WebPDecoder;
+use image::metadata::Orientation;
+use image::{
+    AnimationDecoder, DynamicImage, ImageBuffer, ImageDecoder, ImageFormat, ImageReader, Pixel,
+    Rgb, RgbImage, Rgba, RgbaImage, load_from_memory,
+};
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::{self, BufRead, BufWriter, Cursor, Read, Write};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, Write};
 use std::path::Path;
 
 /// Represents the two common types of PPM files
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PPMFormat {
     P3,
     P6,
+    /// PAM, which can carry an alpha channel
+    P7,
 }
 
 impl fmt::Display for PPMFormat {
@@ -20,25 +32,49 @@ impl fmt::Display for PPMFormat {
         match self {
             Self::P3 => write!(f, "P3"),
             Self::P6 => write!(f, "P6"),
+            Self::P7 => write!(f, "P7"),
         }
     }
 }
 
+/// Path that stands for stdin when reading and stdout when writing
+pub const STDIO_PATH: &str = "-";
+
+/// Whether the path is an http or https URL rather than a file
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Downloads the bytes behind a URL
+#[cfg(feature = "net")]
+fn fetch_url(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    crate::img::net::fetch(url)
+}
+
+/// URLs can only be read when snap is built with the net feature
+#[cfg(not(feature = "net"))]
+fn fetch_url(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err(format!("Reading '{}' needs snap built with the net feature", url).into())
+}
+
 /// Infers the image type from a given file path and maps it to ImageFormat
 pub fn infer_type<P: AsRef<Path>>(path: P) -> Result<ImageFormat, Box<dyn Error>> {
     let ext = path
         .as_ref()
         .extension()
         .and_then(|e| e.to_str())
-        .ok_or("Missing or invalid file extension")?
-        .to_lowercase();
+        .ok_or("Missing or invalid file extension")?;
+    format_from_extension(ext)
+}
 
-    match ext.as_str() {
+/// Maps a format name or file extension like "png" or "JPG" to ImageFormat
+pub fn format_from_extension(ext: &str) -> Result<ImageFormat, Box<dyn Error>> {
+    match ext.to_lowercase().as_str() {
         "png" => Ok(ImageFormat::Png),
         "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
         "gif" => Ok(ImageFormat::Gif),
         "webp" => Ok(ImageFormat::WebP),
-        "pnm" => Ok(ImageFormat::Pnm),
+        "pnm" | "ppm" | "pam" => Ok(ImageFormat::Pnm),
         "tiff" | "tif" => Ok(ImageFormat::Tiff),
         "tga" => Ok(ImageFormat::Tga),
         "dds" => Ok(ImageFormat::Dds),
@@ -66,21 +102,202 @@ pub fn ppm_to_img(ppm_path: &str, out_path: &str) -> Result<(), Box<dyn Error>>
 
 /// Converts data in an in-memory buffer of ppm-valid bytes to an inferred image type
 pub fn ppm_bytes_to_img(ppm_bytes: &[u8], out_path: &str) -> Result<(), Box<dyn Error>> {
-    let out_format = infer_type(out_path)?;
+    ppm_bytes_to_img_with(ppm_bytes, out_path, &EncodeOptions::default())
+}
+
+/// Converts ppm-valid bytes to an inferred image type using the given encoder settings
+pub fn ppm_bytes_to_img_with(
+    ppm_bytes: &[u8],
+    out_path: &str,
+    options: &EncodeOptions,
+) -> Result<(), Box<dyn Error>> {
     let img = load_from_memory(ppm_bytes)?;
-    img.save_with_format(out_path, out_format)?;
+    save_dynamic_image(&img, out_path, options)
+}
+
+/// Encoder settings for formats that support them. Unset fields keep the encoder defaults
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    pub jpeg_quality: Option<u8>,
+    pub png_compression: Option<CompressionType>,
+}
+
+/// Saves the image with the format inferred from the output path, applying any options that
+/// format supports and ignoring the rest
+pub fn save_dynamic_image(
+    img: &DynamicImage,
+    out_path: &str,
+    options: &EncodeOptions,
+) -> Result<(), Box<dyn Error>> {
+    let out_format = infer_type(out_path)?;
+    let mut cursor = Cursor::new(Vec::new());
+    write_dynamic_image(img, &mut cursor, out_format, options)?;
+    write_atomic(out_path, &cursor.into_inner())
+}
+
+/// Writes the bytes to a hidden temp file beside the path and renames it into place, so readers
+/// never see a partially written file. The temp file is removed if any step fails
+pub fn write_atomic<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or("Output path has no file name")?
+        .to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let result = (|| -> io::Result<()> {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(error) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error.into());
+    }
     Ok(())
 }
 
-/// Converts an image file (jpg, png, ppm...) to binary PPM (P6) byte buffer
-pub fn to_ppm(img_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
-    let path = Path::new(img_path);
+/// Encodes the image in the given format into the writer, applying any options that format
+/// supports and ignoring the rest
+pub fn write_dynamic_image<W: Write + Seek>(
+    img: &DynamicImage,
+    writer: &mut W,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<(), Box<dyn Error>> {
+    match (format, options) {
+        (
+            ImageFormat::Jpeg,
+            EncodeOptions {
+                jpeg_quality: Some(quality),
+                ..
+            },
+        ) => {
+            let encoder = JpegEncoder::new_with_quality(writer, *quality);
+            // JPEG has no 16-bit or alpha support, matching what save_with_format would write
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+        }
+        (
+            ImageFormat::Png,
+            EncodeOptions {
+                png_compression: Some(compression),
+                ..
+            },
+        ) => {
+            let encoder = PngEncoder::new_with_quality(writer, *compression, FilterType::Adaptive);
+            img.write_with_encoder(encoder)?;
+        }
+        (ImageFormat::Pnm, _) if img.color().has_alpha() => {
+            // Only PAM can carry the alpha samples, matching what Image::encode writes
+            if has_16_bit_samples(img) {
+                // The encoder rejects 16-bit RGBA maps, so those are written by Image as well
+                Image::from_dynamic_image(img).write_format(writer, PPMFormat::P7)?;
+            } else {
+                let encoder = PnmEncoder::new(writer).with_subtype(PnmSubtype::ArbitraryMap);
+                DynamicImage::ImageRgba8(img.to_rgba8()).write_with_encoder(encoder)?;
+            }
+        }
+        (ImageFormat::Pnm, _) => {
+            // Left to itself the encoder picks PAM, but pnm and ppm outputs are meant to be P6
+            if has_16_bit_samples(img) {
+                // Nor does it take 16-bit pixmaps, which are written as a 65535 maxval P6
+                Image::from_dynamic_image(img).write_format(writer, PPMFormat::P6)?;
+            } else {
+                let subtype = PnmSubtype::Pixmap(SampleEncoding::Binary);
+                let encoder = PnmEncoder::new(writer).with_subtype(subtype);
+                DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+            }
+        }
+        (ImageFormat::Jpeg, _) => {
+            // Same reasoning as above, the encoder rejects alpha outright
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_to(writer, format)?
+        }
+        _ => img.write_to(writer, format)?,
+    }
+
+    Ok(())
+}
 
-    if path.extension().map(|s| s == "ppm").unwrap_or(false) {
+/// Whether the path names a PPM or PAM file, which snap parses itself
+fn has_netpbm_extension(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("ppm") || ext.eq_ignore_ascii_case("pam"))
+        .unwrap_or(false)
+}
+
+/// Reads the next number of a binary netpbm header, skipping whitespace and # comments before it.
+/// Exactly one whitespace byte after the number is consumed, so pixel data that begins with
+/// whitespace values such as 0x0A is left intact
+fn read_header_number<R: BufRead>(reader: &mut R, field: &str) -> Result<usize, Box<dyn Error>> {
+    let mut digits = String::new();
+    let mut byte = [0; 1];
+    while reader.read(&mut byte)? == 1 {
+        match byte[0] {
+            b'#' if digits.is_empty() => {
+                reader.read_until(b'\n', &mut Vec::new())?;
+            }
+            b if b.is_ascii_whitespace() && digits.is_empty() => {}
+            b if b.is_ascii_whitespace() => break,
+            b if b.is_ascii_digit() => digits.push(b as char),
+            b => return Err(format!("Unexpected byte {:#04x} in the {} field", b, field).into()),
+        }
+    }
+
+    if digits.is_empty() {
+        return Err(format!("Missing {}", field).into());
+    }
+    Ok(digits.parse()?)
+}
+
+/// Reads and decodes the big-endian samples of a binary raster whose dimensions multiply out to
+/// the sample count. Only as many bytes as the input holds are ever allocated, so a header
+/// claiming a huge size is an error rather than an allocation failure
+fn read_binary_samples<R: Read>(
+    reader: &mut R,
+    dimensions: &[usize],
+    sample_bytes: usize,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let length = dimensions
+        .iter()
+        .try_fold(sample_bytes, |acc, &dimension| acc.checked_mul(dimension))
+        .ok_or("Image dimensions in the header are too large")?;
+
+    let mut raw = Vec::new();
+    reader.take(length as u64).read_to_end(&mut raw)?;
+    if raw.len() < length {
+        return Err("Binary pixel data is shorter than the header describes".into());
+    }
+
+    Ok(raw
+        .chunks_exact(sample_bytes)
+        .map(|bytes| bytes.iter().fold(0, |acc, &b| (acc << 8) | b as usize))
+        .collect())
+}
+
+/// Whether the decoded image stores more than 8 bits per channel
+fn has_16_bit_samples(img: &DynamicImage) -> bool {
+    img.color().bytes_per_pixel() / img.color().channel_count() > 1
+}
+
+/// Converts an image file (jpg, png, ppm...) to binary PPM (P6) byte buffer. PPM and PAM files
+/// are passed through untouched
+pub fn to_ppm(img_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if has_netpbm_extension(img_path) {
         let bytes = std::fs::read(img_path)?;
-        return Ok(bytes);
+        Ok(bytes)
     } else {
-        let img = image::open(img_path)?.to_rgb8();
+        let img = image::open(img_path)?;
+        if img.color().has_alpha() || has_16_bit_samples(&img) {
+            // Only PAM can carry the alpha samples through to the parser, and 16-bit samples need
+            // a 65535 maxval, so both are written from an Image
+            return Image::from_dynamic_image(&img).bytes();
+        }
+
+        let img = img.to_rgb8();
         let (width, height) = img.dimensions();
 
         let mut buffer = Vec::new();
@@ -99,25 +316,94 @@ pub fn to_ppm(img_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
 
 /// Converts any supported image (including PPM) to another format based on output path extension
 pub fn convert(input_path: &str, output_path: &str) -> Result<(), Box<dyn Error>> {
-    let output_format = infer_type(output_path)?;
+    convert_with(input_path, output_path, &EncodeOptions::default())
+}
 
-    if Path::new(input_path)
-        .extension()
-        .and_then(|s| s.to_str())
-        .map(|ext| ext.eq_ignore_ascii_case("ppm"))
-        .unwrap_or(false)
-    {
-        let image = Image::from_file(input_path)?;
-        let bytes = image.bytes_format(PPMFormat::P6)?;
-        ppm_bytes_to_img(&bytes, output_path)?;
+/// Converts any supported image to another format using the given encoder settings
+pub fn convert_with(
+    input_path: &str,
+    output_path: &str,
+    options: &EncodeOptions,
+) -> Result<(), Box<dyn Error>> {
+    infer_type(output_path)?;
+
+    if has_netpbm_extension(input_path) || is_url(input_path) {
+        Image::from_file(input_path)?.save_with(output_path, None, options)?;
     } else {
         let img = image::open(input_path)?;
-        img.save_with_format(output_path, output_format)?;
+        save_dynamic_image(&img, output_path, options)?;
     }
 
     Ok(())
 }
 
+/// Reads the EXIF orientation stored in the file, treating a missing tag as no transform
+pub fn read_orientation(input_path: &str) -> Result<Orientation, Box<dyn Error>> {
+    let mut decoder = ImageReader::open(input_path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    Ok(decoder.orientation()?)
+}
+
+/// A single frame of a (possibly animated) image along with its display delay
+#[derive(Debug)]
+pub struct Frame {
+    pub image: Image,
+    pub delay_ms: u16,
+}
+
+/// Decodes every frame of an animated GIF or WebP. Single-frame formats yield one frame with no delay
+pub fn frames_from_file(input_path: &str) -> Result<Vec<Frame>, Box<dyn Error>> {
+    let frames = match infer_type(input_path)? {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(BufReader::new(File::open(input_path)?))?;
+            decoder.into_frames().collect_frames()?
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(BufReader::new(File::open(input_path)?))?;
+            if !decoder.has_animation() {
+                return Ok(vec![Frame {
+                    image: Image::from_file(input_path)?,
+                    delay_ms: 0,
+                }]);
+            }
+            decoder.into_frames().collect_frames()?
+        }
+        _ => {
+            return Ok(vec![Frame {
+                image: Image::from_file(input_path)?,
+                delay_ms: 0,
+            }]);
+        }
+    };
+
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay_ms = (numer / denom.max(1)).min(u16::MAX as u32) as u16;
+            Frame {
+                image: Image::from_rgba_buffer(&frame.into_buffer(), u8::MAX as usize),
+                delay_ms,
+            }
+        })
+        .collect())
+}
+
+/// Decodes the frame at the zero-based index of an animated GIF or WebP, erroring when the
+/// animation has no such frame
+pub fn frame_from_file(input_path: &str, index: usize) -> Result<Frame, Box<dyn Error>> {
+    let frames = frames_from_file(input_path)?;
+    let count = frames.len();
+    frames.into_iter().nth(index).ok_or_else(|| {
+        format!(
+            "Frame {} is out of range, '{}' has {} frame(s)",
+            index, input_path, count
+        )
+        .into()
+    })
+}
+
 /// Container for sharing pertinent image information
 #[derive(Debug, Clone, Default)]
 pub struct ImageInfo {
@@ -130,15 +416,12 @@ pub struct ImageInfo {
 
 /// Returns the image's pertinent information
 pub fn info(input_path: &str, print: bool) -> Result<ImageInfo, Box<dyn Error>> {
-    let mut info = ImageInfo::default();
-    info.path = input_path.to_string();
+    let mut info = ImageInfo {
+        path: input_path.to_string(),
+        ..Default::default()
+    };
 
-    if Path::new(input_path)
-        .extension()
-        .and_then(|s| s.to_str())
-        .map(|ext| ext.eq_ignore_ascii_case("ppm"))
-        .unwrap_or(false)
-    {
+    if has_netpbm_extension(input_path) {
         let img = Image::from_file(input_path)?;
 
         // printing (optional)
@@ -155,8 +438,12 @@ pub fn info(input_path: &str, print: bool) -> Result<ImageInfo, Box<dyn Error>>
         info.format_str = format!("PPM - {}", img.format);
         info.intensity = Some(img.max_intensity);
     } else {
-        let path = Path::new(input_path);
-        let reader = ImageReader::open(&path)?.with_guessed_format()?;
+        let data = if is_url(input_path) {
+            fetch_url(input_path)?
+        } else {
+            fs::read(input_path)?
+        };
+        let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
         if let Some(format) = reader.format() {
             let img = reader.decode()?;
             let img_fmt_str = format!("{:?}", format).to_uppercase();
@@ -182,10 +469,54 @@ pub fn info(input_path: &str, print: bool) -> Result<ImageInfo, Box<dyn Error>>
 }
 
 impl Image {
-    /// Initializes an Image from a valid PPM file
+    /// Initializes an Image from a valid PPM file, or from stdin when the path is "-"
     pub fn from_file(filepath: &str) -> Result<Image, Box<dyn Error>> {
-        let mut bytes = to_ppm(filepath)?;
-        Self::from_bytes(&mut bytes)
+        if filepath == STDIO_PATH {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            return Self::decode(&bytes);
+        }
+        if is_url(filepath) {
+            return Self::from_url(filepath);
+        }
+
+        let bytes = to_ppm(filepath)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Downloads and decodes the image at an http or https URL
+    pub fn from_url(url: &str) -> Result<Image, Box<dyn Error>> {
+        Self::decode(&fetch_url(url)?)
+    }
+
+    /// Initializes an Image from the bytes of any supported format, guessing it from the contents
+    pub fn decode(data: &[u8]) -> Result<Image, Box<dyn Error>> {
+        if matches!(data, [b'P', b'1' | b'3' | b'4' | b'6' | b'7', ..]) {
+            return Self::from_bytes(data);
+        }
+        Ok(Self::from_dynamic_image(&load_from_memory(data)?))
+    }
+
+    /// Encodes the image in the given format using the given encoder settings
+    pub fn encode(
+        &self,
+        format: ImageFormat,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if format == ImageFormat::Pnm {
+            return match self.alpha_channel {
+                Some(_) => self.bytes_format(PPMFormat::P7),
+                None => self.bytes_format(PPMFormat::P6),
+            };
+        }
+
+        let img = match self.alpha_channel {
+            Some(_) => self.to_rgba_dynamic_image()?,
+            None => load_from_memory(&self.bytes_format(PPMFormat::P6)?)?,
+        };
+        let mut cursor = Cursor::new(Vec::new());
+        write_dynamic_image(&img, &mut cursor, format, options)?;
+        Ok(cursor.into_inner())
     }
 
     /// Initializes an Image from the bytes of a PPM file
@@ -194,64 +525,143 @@ impl Image {
         Self::from_reader(&mut cursor)
     }
 
-    fn from_reader<R: Read>(reader: &mut R) -> Result<Image, Box<dyn Error>> {
-        let mut header = [0; 2];
-        reader.read_exact(&mut header)?;
+    /// Copies an image crate image without an encode and decode cycle. Sixteen bit sources keep
+    /// their precision with a max intensity of 65535, with or without alpha, everything else
+    /// becomes 8-bit
+    pub fn from_dynamic_image(img: &DynamicImage) -> Image {
+        match (img.color().has_alpha(), has_16_bit_samples(img)) {
+            (true, true) => Self::from_rgba_buffer(&img.to_rgba16(), u16::MAX as usize),
+            (true, false) => Self::from_rgba_buffer(&img.to_rgba8(), u8::MAX as usize),
+            (false, true) => Self::from_rgb_buffer(&img.to_rgb16(), u16::MAX as usize),
+            (false, false) => Self::from_rgb_buffer(&img.to_rgb8(), u8::MAX as usize),
+        }
+    }
 
-        match &header {
-            b"P3" => {
-                let buf = io::BufReader::new(reader);
-                Self::parse_ppm_ascii(buf.lines())
-            }
-            b"P6" => Self::parse_ppm_binary(reader),
-            _ => Err("Unsupported PPM format".into()),
+    /// Initializes an Image from a decoded RGB buffer whose samples top out at max_intensity
+    fn from_rgb_buffer<P>(buffer: &ImageBuffer<Rgb<P>, Vec<P>>, max_intensity: usize) -> Image
+    where
+        Rgb<P>: Pixel<Subpixel = P>,
+        P: Copy + Into<usize>,
+    {
+        let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+        let mut image = Image::new(width, height, max_intensity, PPMFormat::P6);
+
+        for (col, row, pixel) in buffer.enumerate_pixels() {
+            let (row, col) = (row as usize, col as usize);
+            image.red_channel[(row, col)] = pixel[0].into();
+            image.green_channel[(row, col)] = pixel[1].into();
+            image.blue_channel[(row, col)] = pixel[2].into();
         }
+
+        image
+    }
+
+    /// Copies the image into an 8-bit image crate buffer, rescaling from max_intensity to 255
+    pub fn to_dynamic_image(&self) -> RgbImage {
+        let max = self.max_intensity.max(1) as f64;
+        let to_u8 = |v: usize| clamp_channel(v as f64 * 255.0 / max, 255) as u8;
+
+        RgbImage::from_fn(self.width as u32, self.height as u32, |col, row| {
+            let (row, col) = (row as usize, col as usize);
+            Rgb([
+                to_u8(self.red_channel[(row, col)]),
+                to_u8(self.green_channel[(row, col)]),
+                to_u8(self.blue_channel[(row, col)]),
+            ])
+        })
     }
 
-    fn parse_ppm_ascii<I>(mut lines: I) -> Result<Image, Box<dyn Error>>
+    /// Copies the image and its alpha channel into an image crate buffer, 8-bit when max_intensity
+    /// fits and 16-bit otherwise, rescaling samples to the full range of that depth
+    fn to_rgba_dynamic_image(&self) -> Result<DynamicImage, Box<dyn Error>> {
+        if self.max_intensity > u16::MAX as usize {
+            return Err("RGBA max intensity cannot exceed 65535".into());
+        }
+        let max = self.max_intensity.max(1) as f64;
+        let (width, height) = (self.width as u32, self.height as u32);
+        let pixel = |col: u32, row: u32| {
+            let p = self.get_pixel_rgba(row as usize, col as usize).unwrap();
+            [p.r, p.g, p.b, p.a]
+        };
+
+        if self.max_intensity > 255 {
+            let rescale = |v: usize| clamp_channel(v as f64 * 65535.0 / max, 65535) as u16;
+            let buffer =
+                ImageBuffer::from_fn(width, height, |col, row| Rgba(pixel(col, row).map(rescale)));
+            Ok(DynamicImage::ImageRgba16(buffer))
+        } else {
+            let rescale = |v: usize| clamp_channel(v as f64 * 255.0 / max, 255) as u8;
+            let buffer =
+                RgbaImage::from_fn(width, height, |col, row| Rgba(pixel(col, row).map(rescale)));
+            Ok(DynamicImage::ImageRgba8(buffer))
+        }
+    }
+
+    /// Initializes an Image from a decoded RGBA buffer whose samples top out at max_intensity,
+    /// keeping the alpha samples
+    fn from_rgba_buffer<P>(buffer: &ImageBuffer<Rgba<P>, Vec<P>>, max_intensity: usize) -> Image
     where
-        I: Iterator<Item = Result<String, io::Error>>,
+        Rgba<P>: Pixel<Subpixel = P>,
+        P: Copy + Into<usize>,
     {
-        let _magic = lines.next().ok_or("Missing PPM header")??;
+        let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+        let mut image = Image::new(width, height, max_intensity, PPMFormat::P6);
+        let mut alpha = Matrix::new_filled(width, height, 0);
 
-        let mut dimensions_line = String::new();
-        for line in &mut lines {
-            let l = line?;
-            if !l.starts_with('#') {
-                dimensions_line = l;
-                break;
-            }
+        for (col, row, pixel) in buffer.enumerate_pixels() {
+            let (row, col) = (row as usize, col as usize);
+            image.red_channel[(row, col)] = pixel[0].into();
+            image.green_channel[(row, col)] = pixel[1].into();
+            image.blue_channel[(row, col)] = pixel[2].into();
+            alpha[(row, col)] = pixel[3].into();
         }
 
-        let mut dims = dimensions_line
-            .split_whitespace()
-            .map(|s| s.parse::<usize>());
-        let width = dims.next().ok_or("Missing width dimension")??;
-        let height = dims.next().ok_or("Missing height dimension")??;
+        image.alpha_channel = Some(alpha);
+        image
+    }
 
-        let mut intensity_line = String::new();
-        for line in &mut lines {
-            let l = line?;
-            if !l.starts_with('#') {
-                intensity_line = l;
-                break;
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Image, Box<dyn Error>> {
+        let mut header = [0; 2];
+        reader.read_exact(&mut header)?;
+
+        match &header {
+            b"P3" => Self::parse_ppm_ascii(reader),
+            b"P6" => Self::parse_ppm_binary(reader),
+            b"P1" => {
+                let buf = io::BufReader::new(reader);
+                Self::parse_pbm_ascii(buf.lines())
             }
+            b"P4" => Self::parse_pbm_binary(reader),
+            b"P7" => Self::parse_pam(reader),
+            _ => Err("Unsupported PPM format".into()),
         }
+    }
 
-        let intensity = intensity_line.trim().parse::<usize>()?;
+    /// Reads a plain PPM after its magic number as a stream of whitespace separated tokens, so
+    /// header fields may share lines and # comments may appear anywhere
+    fn parse_ppm_ascii<R: Read>(reader: &mut R) -> Result<Image, Box<dyn Error>> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
 
-        let pixel_values = lines
-            .flat_map(|line| {
-                line.ok()
-                    .map(|l| l.split_whitespace().map(str::to_string).collect::<Vec<_>>())
-            })
-            .flatten()
-            .map(|s| s.parse::<usize>())
-            .collect::<Result<Vec<_>, _>>()?;
+        let mut tokens = text
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace())
+            .map(|token| token.parse::<usize>());
+        let width = tokens.next().ok_or("Missing width dimension")??;
+        let height = tokens.next().ok_or("Missing height dimension")??;
+        let intensity = tokens.next().ok_or("Missing max intensity")??;
+        if intensity == 0 || intensity > u16::MAX as usize {
+            return Err("PPM max intensity must be between 1 and 65535".into());
+        }
+
+        let pixel_values = tokens.collect::<Result<Vec<_>, _>>()?;
 
         if pixel_values.len() != width * height * 3 {
             return Err("Incorrect number of pixel values".into());
         }
+        if pixel_values.iter().any(|&value| value > intensity) {
+            return Err("Pixel value exceeds the max intensity".into());
+        }
 
         let mut red_pixels = Vec::with_capacity(width * height);
         let mut blue_pixels = Vec::with_capacity(width * height);
@@ -263,8 +673,8 @@ impl Image {
         }
 
         Ok(Image {
-            width: width,
-            height: height,
+            width,
+            height,
             max_intensity: intensity,
             red_channel: Matrix::from_vec(width, height, red_pixels)
                 .expect("Invalid red channel values"),
@@ -272,6 +682,7 @@ impl Image {
                 .expect("Invalid green channel values"),
             blue_channel: Matrix::from_vec(width, height, blue_pixels)
                 .expect("Invalid blue channel values"),
+            alpha_channel: None,
             format: PPMFormat::P3,
         })
     }
@@ -279,45 +690,31 @@ impl Image {
     fn parse_ppm_binary<R: io::Read>(reader: &mut R) -> Result<Image, Box<dyn Error>> {
         let mut buf_reader = io::BufReader::new(reader);
 
-        let mut header = String::new();
-        buf_reader.read_line(&mut header)?;
-
-        let dimensions = loop {
-            let mut line = String::new();
-            buf_reader.read_line(&mut line)?;
-            if !line.trim().starts_with('#') {
-                let mut parts = line.split_whitespace();
-                let w = parts.next().ok_or("Missing width")?.parse::<usize>()?;
-                let h = parts.next().ok_or("Missing height")?.parse::<usize>()?;
-                break (w, h);
-            }
-        };
-
-        let (width, height) = dimensions;
-
-        let intensity = loop {
-            let mut line = String::new();
-            buf_reader.read_line(&mut line)?;
-            if !line.trim().starts_with('#') {
-                break line.trim().parse::<usize>()?;
-            }
-        };
+        let width = read_header_number(&mut buf_reader, "width")?;
+        let height = read_header_number(&mut buf_reader, "height")?;
+        let intensity = read_header_number(&mut buf_reader, "max intensity")?;
+        if intensity == 0 || intensity > u16::MAX as usize {
+            return Err("PPM max intensity must be between 1 and 65535".into());
+        }
 
-        let mut raw = Vec::new();
-        buf_reader.read_to_end(&mut raw)?;
+        // Maxvals above 255 store every sample as two big-endian bytes
+        let sample_bytes = if intensity > 255 { 2 } else { 1 };
 
-        if raw.len() != width * height * 3 {
-            return Err("Binary pixel data length mismatch".into());
+        // Anything after the pixel data, such as a trailing newline, is ignored
+        let samples = read_binary_samples(&mut buf_reader, &[width, height, 3], sample_bytes)?;
+        if samples.iter().any(|&sample| sample > intensity) {
+            return Err("Pixel value exceeds the max intensity".into());
         }
 
         let mut red = Vec::with_capacity(width * height);
         let mut green = Vec::with_capacity(width * height);
         let mut blue = Vec::with_capacity(width * height);
 
-        for chunk in raw.chunks_exact(3) {
-            red.push(chunk[0] as usize);
-            green.push(chunk[1] as usize);
-            blue.push(chunk[2] as usize);
+        let mut samples = samples.into_iter();
+        while let (Some(r), Some(g), Some(b)) = (samples.next(), samples.next(), samples.next()) {
+            red.push(r);
+            green.push(g);
+            blue.push(b);
         }
 
         Ok(Image {
@@ -330,42 +727,253 @@ impl Image {
                 .ok_or("Invalid green channel values")?,
             blue_channel: Matrix::from_vec(width, height, blue)
                 .ok_or("Invalid blue channel values")?,
+            alpha_channel: None,
             format: PPMFormat::P6,
         })
     }
 
-    /// Creates a file and writes the images data to it in valid PPM format
-    pub fn write_ppm_file(&self, filepath: &str) -> Result<(), Box<dyn Error>> {
-        let file = File::create(filepath)?;
-        let mut writer = BufWriter::new(file);
+    /// Parses a binary PAM with a grayscale or RGB tuple type, either with or without alpha
+    fn parse_pam<R: io::Read>(reader: &mut R) -> Result<Image, Box<dyn Error>> {
+        let mut buf_reader = io::BufReader::new(reader);
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+
+        // The rest of the magic number line is empty
+        let mut line = String::new();
+        buf_reader.read_line(&mut line)?;
+        loop {
+            line.clear();
+            if buf_reader.read_line(&mut line)? == 0 {
+                return Err("PAM header is missing ENDHDR".into());
+            }
+            let mut tokens = line.split_whitespace();
+            let (Some(key), value) = (tokens.next(), tokens.next()) else {
+                continue;
+            };
+            let number = || -> Result<Option<usize>, Box<dyn Error>> {
+                let value = value.ok_or("PAM header field is missing a value")?;
+                Ok(Some(value.parse()?))
+            };
+            match key {
+                "ENDHDR" => break,
+                "WIDTH" => width = number()?,
+                "HEIGHT" => height = number()?,
+                "DEPTH" => depth = number()?,
+                "MAXVAL" => maxval = number()?,
+                // Depth already says which channels are present
+                "TUPLTYPE" => {}
+                _ if key.starts_with('#') => {}
+                _ => return Err(format!("Unknown PAM header field '{}'", key).into()),
+            }
+        }
+
+        let width = width.ok_or("PAM header is missing WIDTH")?;
+        let height = height.ok_or("PAM header is missing HEIGHT")?;
+        let depth = depth.ok_or("PAM header is missing DEPTH")?;
+        let maxval = maxval.ok_or("PAM header is missing MAXVAL")?;
+        if !(1..=4).contains(&depth) {
+            return Err(format!("Unsupported PAM depth {}, expected 1 to 4", depth).into());
+        }
+        if maxval == 0 || maxval > u16::MAX as usize {
+            return Err("PAM max value must be between 1 and 65535".into());
+        }
+
+        let sample_bytes = if maxval > 255 { 2 } else { 1 };
+        let samples = read_binary_samples(&mut buf_reader, &[width, height, depth], sample_bytes)?;
+        if samples.iter().any(|&sample| sample > maxval) {
+            return Err("Pixel value exceeds the max value".into());
+        }
+
+        // Grayscale tuples fill all three color channels
+        let color_depth = if depth <= 2 { 1 } else { 3 };
+        let plane = |offset: usize| -> Vec<usize> {
+            samples
+                .chunks_exact(depth)
+                .map(|tuple| tuple[offset])
+                .collect()
+        };
+        let matrix = |values| Matrix::from_vec(width, height, values).ok_or("Invalid PAM values");
+
+        Ok(Image {
+            width,
+            height,
+            max_intensity: maxval,
+            red_channel: matrix(plane(0))?,
+            green_channel: matrix(plane(1 % color_depth))?,
+            blue_channel: matrix(plane(2 % color_depth))?,
+            alpha_channel: match depth {
+                2 | 4 => Some(matrix(plane(depth - 1))?),
+                _ => None,
+            },
+            format: PPMFormat::P7,
+        })
+    }
 
-        match self.format {
-            PPMFormat::P3 => self.write_ascii(&mut writer),
-            PPMFormat::P6 => self.write_binary(&mut writer),
+    /// Builds a 255-intensity image from PBM bits, where a set bit is black
+    fn from_pbm_bits(width: usize, height: usize, bits: &[bool], format: PPMFormat) -> Image {
+        let values: Vec<usize> = bits
+            .iter()
+            .map(|&black| if black { 0 } else { 255 })
+            .collect();
+
+        Image {
+            width,
+            height,
+            max_intensity: 255,
+            red_channel: Matrix::from_vec(width, height, values.clone())
+                .expect("Invalid red channel values"),
+            green_channel: Matrix::from_vec(width, height, values.clone())
+                .expect("Invalid green channel values"),
+            blue_channel: Matrix::from_vec(width, height, values)
+                .expect("Invalid blue channel values"),
+            alpha_channel: None,
+            format,
         }
     }
 
+    fn parse_pbm_ascii<I>(mut lines: I) -> Result<Image, Box<dyn Error>>
+    where
+        I: Iterator<Item = Result<String, io::Error>>,
+    {
+        let _magic = lines.next().ok_or("Missing PBM header")??;
+
+        let mut dimensions_line = String::new();
+        for line in &mut lines {
+            let l = line?;
+            if !l.starts_with('#') {
+                dimensions_line = l;
+                break;
+            }
+        }
+
+        let mut dims = dimensions_line
+            .split_whitespace()
+            .map(|s| s.parse::<usize>());
+        let width = dims.next().ok_or("Missing width dimension")??;
+        let height = dims.next().ok_or("Missing height dimension")??;
+
+        // P1 bits may be packed together without separating whitespace
+        let mut bits = Vec::with_capacity(width * height);
+        for line in lines {
+            let l = line?;
+            if l.starts_with('#') {
+                continue;
+            }
+            for c in l.chars().filter(|c| !c.is_whitespace()) {
+                match c {
+                    '0' => bits.push(false),
+                    '1' => bits.push(true),
+                    _ => return Err("Invalid PBM bit value".into()),
+                }
+            }
+        }
+
+        if bits.len() != width * height {
+            return Err("Incorrect number of pixel values".into());
+        }
+
+        Ok(Self::from_pbm_bits(width, height, &bits, PPMFormat::P3))
+    }
+
+    fn parse_pbm_binary<R: io::Read>(reader: &mut R) -> Result<Image, Box<dyn Error>> {
+        let mut buf_reader = io::BufReader::new(reader);
+
+        let mut header = String::new();
+        buf_reader.read_line(&mut header)?;
+
+        let (width, height) = loop {
+            let mut line = String::new();
+            buf_reader.read_line(&mut line)?;
+            if !line.trim().starts_with('#') {
+                let mut parts = line.split_whitespace();
+                let w = parts.next().ok_or("Missing width")?.parse::<usize>()?;
+                let h = parts.next().ok_or("Missing height")?.parse::<usize>()?;
+                break (w, h);
+            }
+        };
+
+        // Each row is packed 8 pixels per byte, padded out to a whole byte
+        let row_bytes = width.div_ceil(8);
+        let mut raw = vec![0; row_bytes * height];
+        buf_reader.read_exact(&mut raw)?;
+
+        let mut bits = Vec::with_capacity(width * height);
+        for row in raw.chunks_exact(row_bytes) {
+            for col in 0..width {
+                bits.push(row[col / 8] & (0x80 >> (col % 8)) != 0);
+            }
+        }
+
+        Ok(Self::from_pbm_bits(width, height, &bits, PPMFormat::P6))
+    }
+
+    /// Writes a strictly black and white image as a PBM bitmap, P1 when ascii and P4 otherwise
+    pub fn write_pbm(&self, filepath: &str, ascii: bool) -> Result<(), Box<dyn Error>> {
+        let mut bits = Vec::with_capacity(self.width * self.height);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let pixel = self.get_pixel(row, col).ok_or("Pixel out of bounds")?;
+                let black = [pixel.r, pixel.g, pixel.b].iter().all(|&v| v == 0);
+                let white = [pixel.r, pixel.g, pixel.b]
+                    .iter()
+                    .all(|&v| v == self.max_intensity);
+                if !black && !white {
+                    return Err("Image is not strictly bilevel".into());
+                }
+                bits.push(black);
+            }
+        }
+
+        let mut writer = Vec::new();
+
+        if ascii {
+            writeln!(writer, "P1")?;
+            writeln!(writer, "{} {}", self.width, self.height)?;
+            for row in bits.chunks(self.width.max(1)) {
+                let line: Vec<&str> = row.iter().map(|&b| if b { "1" } else { "0" }).collect();
+                writeln!(writer, "{}", line.join(" "))?;
+            }
+        } else {
+            writeln!(writer, "P4")?;
+            writeln!(writer, "{} {}", self.width, self.height)?;
+            for row in bits.chunks(self.width.max(1)) {
+                let mut packed = vec![0u8; self.width.div_ceil(8)];
+                for (col, &black) in row.iter().enumerate() {
+                    if black {
+                        packed[col / 8] |= 0x80 >> (col % 8);
+                    }
+                }
+                writer.write_all(&packed)?;
+            }
+        }
+
+        write_atomic(filepath, &writer)
+    }
+
+    /// Creates a file and writes the images data to it in valid PPM format
+    pub fn write_ppm_file(&self, filepath: &str) -> Result<(), Box<dyn Error>> {
+        write_atomic(filepath, &self.bytes()?)
+    }
+
     /// Writes the Image's data to the given writer according to the given ppm format
     pub fn write_ppm_file_format(
         &self,
         filepath: &str,
         format: PPMFormat,
     ) -> Result<(), Box<dyn Error>> {
-        let file = File::create(filepath)?;
-        let mut writer = BufWriter::new(file);
+        write_atomic(filepath, &self.bytes_format(format)?)
+    }
 
-        match format {
-            PPMFormat::P3 => self.write_ascii(&mut writer),
-            PPMFormat::P6 => self.write_binary(&mut writer),
+    /// The format write and bytes use, which is PAM whenever there is alpha to keep
+    fn output_format(&self) -> PPMFormat {
+        match self.alpha_channel {
+            Some(_) => PPMFormat::P7,
+            None => self.format.clone(),
         }
     }
 
     /// Writes the Image's data to the given writer in its internal ppm format
     pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
-        match self.format {
-            PPMFormat::P3 => self.write_ascii(writer),
-            PPMFormat::P6 => self.write_binary(writer),
-        }
+        self.write_format(writer, self.output_format())
     }
 
     /// Writes the Image's data to the given writer according to the given ppm format
@@ -377,31 +985,33 @@ impl Image {
         match format {
             PPMFormat::P3 => self.write_ascii(writer),
             PPMFormat::P6 => self.write_binary(writer),
+            PPMFormat::P7 => self.write_pam(writer),
         }
     }
 
     /// Gets the Image's data abiding by the internal format
     pub fn bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut buffer = Vec::new();
-        match self.format {
-            PPMFormat::P3 => self.write_ascii(&mut buffer)?,
-            PPMFormat::P6 => self.write_binary(&mut buffer)?,
-        }
-        Ok(buffer)
+        self.bytes_format(self.output_format())
     }
 
     /// Gets the Image's data abiding by the externally provided format
     pub fn bytes_format(&self, format: PPMFormat) -> Result<Vec<u8>, Box<dyn Error>> {
         let mut buffer = Vec::new();
-
-        match format {
-            PPMFormat::P3 => self.write_ascii(&mut buffer)?,
-            PPMFormat::P6 => self.write_binary(&mut buffer)?,
-        }
-
+        self.write_format_into(&mut buffer, format)?;
         Ok(buffer)
     }
 
+    /// Like bytes_format, but clears and refills the caller's buffer so encoding many images in a
+    /// loop can reuse one allocation
+    pub fn write_format_into(
+        &self,
+        buffer: &mut Vec<u8>,
+        format: PPMFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        buffer.clear();
+        self.write_format(buffer, format)
+    }
+
     fn write_ascii<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
         writeln!(writer, "P3")?;
         writeln!(writer, "{} {}", self.width, self.height)?;
@@ -421,16 +1031,679 @@ impl Image {
     }
 
     fn write_binary<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
+        if self.max_intensity > u16::MAX as usize {
+            return Err("PPM max intensity cannot exceed 65535".into());
+        }
+
         writeln!(writer, "P6")?;
         writeln!(writer, "{} {}", self.width, self.height)?;
         writeln!(writer, "{}", self.max_intensity)?;
 
+        let wide = self.max_intensity > 255;
         for row in 0..self.height {
             for col in 0..self.width {
                 let pixel = self.get_pixel(row, col).unwrap();
-                writer.write_all(&[pixel.r as u8, pixel.g as u8, pixel.b as u8])?;
+                for sample in [pixel.r, pixel.g, pixel.b] {
+                    if wide {
+                        writer.write_all(&(sample as u16).to_be_bytes())?;
+                    } else {
+                        writer.write_all(&[sample as u8])?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a binary PAM, with an alpha plane whenever the image has one
+    fn write_pam<W: Write>(&self, writer: &mut W) -> Result<(), Box<dyn Error>> {
+        if self.max_intensity > u16::MAX as usize {
+            return Err("PAM max intensity cannot exceed 65535".into());
+        }
+        if let Some(alpha) = &self.alpha_channel
+            && (alpha.width, alpha.height) != (self.width, self.height)
+        {
+            return Err("Alpha channel dimensions must match the image".into());
+        }
+
+        let (depth, tuple_type) = match self.alpha_channel {
+            Some(_) => (4, "RGB_ALPHA"),
+            None => (3, "RGB"),
+        };
+        writeln!(writer, "P7")?;
+        writeln!(writer, "WIDTH {}", self.width)?;
+        writeln!(writer, "HEIGHT {}", self.height)?;
+        writeln!(writer, "DEPTH {}", depth)?;
+        writeln!(writer, "MAXVAL {}", self.max_intensity)?;
+        writeln!(writer, "TUPLTYPE {}", tuple_type)?;
+        writeln!(writer, "ENDHDR")?;
+
+        let wide = self.max_intensity > 255;
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let pixel = self.get_pixel_rgba(row, col).unwrap();
+                let samples = [pixel.r, pixel.g, pixel.b, pixel.a];
+                for &sample in &samples[..depth] {
+                    if wide {
+                        writer.write_all(&(sample as u16).to_be_bytes())?;
+                    } else {
+                        writer.write_all(&[sample as u8])?;
+                    }
+                }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{load_fixture, noise_image, temp_path};
+    use crate::img::utils::PixelRGB;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame as AnimationFrame};
+
+    /// Writes an animated GIF with one solid frame per delay
+    fn write_gif(path: &str, delays_ms: &[u32]) {
+        let mut encoder = GifEncoder::new(File::create(path).unwrap());
+        for (i, &delay) in delays_ms.iter().enumerate() {
+            let buffer = RgbaImage::from_pixel(4, 3, Rgba([(i * 60) as u8, 0, 0, 255]));
+            let delay = Delay::from_numer_denom_ms(delay, 1);
+            encoder
+                .encode_frame(AnimationFrame::from_parts(buffer, 0, 0, delay))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn gif_frames_keep_their_delays() {
+        let path = temp_path("delays.gif");
+        write_gif(&path, &[100, 250, 40]);
+        let frames = frames_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let delays: Vec<u16> = frames.iter().map(|frame| frame.delay_ms).collect();
+        assert_eq!(delays, [100, 250, 40]);
+        assert_eq!(frames[1].image.get_pixel(0, 0).unwrap().r, 60);
+    }
+
+    #[test]
+    fn single_frame_formats_have_no_delay() {
+        let path = temp_path("single.ppm");
+        load_fixture("fixture").save(&path).unwrap();
+        let frames = frames_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].delay_ms, 0);
+        assert_eq!(frames[0].image.width, 5);
+    }
+
+    /// 11x3 black and white image, so P4 rows need a padded second byte
+    fn bilevel_image() -> Image {
+        let mut image = Image::new(11, 3, 255, PPMFormat::P6);
+        image.fill(PixelRGB::from_name("white").unwrap());
+        for (row, col) in [(0, 0), (0, 9), (1, 3), (1, 10), (2, 7)] {
+            image.set_pixel(row, col, PixelRGB { r: 0, g: 0, b: 0 });
+        }
+        image
+    }
+
+    #[test]
+    fn bilevel_images_round_trip_through_pbm() {
+        let image = bilevel_image();
+        for ascii in [false, true] {
+            let path = temp_path(if ascii { "ascii.pbm" } else { "binary.pbm" });
+            image.write_pbm(&path, ascii).unwrap();
+            let bytes = fs::read(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            if !ascii {
+                assert_eq!(bytes.len(), b"P4\n11 3\n".len() + 2 * 3);
+            }
+            let read = Image::from_bytes(&bytes).unwrap();
+            assert_eq!(read.red_channel, image.red_channel);
+            assert_eq!(read.blue_channel, image.blue_channel);
+        }
+    }
+
+    #[test]
+    fn write_pbm_rejects_gray_pixels() {
+        let mut image = bilevel_image();
+        image.set_pixel(2, 2, PixelRGB { r: 0, g: 0, b: 1 });
+        let path = temp_path("gray.pbm");
+        assert!(image.write_pbm(&path, false).is_err());
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn trailing_bytes_after_binary_pixels_are_ignored() {
+        let mut bytes = b"P6\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&[10, 20, 30, 40, 50, 60]);
+        bytes.push(b'\n');
+
+        let image = Image::from_bytes(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(
+            image.get_pixel(0, 1),
+            Some(PixelRGB {
+                r: 40,
+                g: 50,
+                b: 60
+            })
+        );
+    }
+
+    #[test]
+    fn sixteen_bit_p6_round_trips() {
+        let mut bytes = b"P6\n2 2\n65535\n".to_vec();
+        for sample in [
+            0u16, 1, 255, 256, 4095, 65535, 12345, 54321, 2, 300, 65534, 40000,
+        ] {
+            bytes.extend_from_slice(&sample.to_be_bytes());
+        }
+
+        let image = Image::from_bytes(&bytes).unwrap();
+        assert_eq!(image.max_intensity, 65535);
+        assert_eq!(
+            image.get_pixel(0, 1),
+            Some(PixelRGB {
+                r: 256,
+                g: 4095,
+                b: 65535
+            })
+        );
+
+        let written = image.bytes_format(PPMFormat::P6).unwrap();
+        assert_eq!(written, bytes);
+        assert_eq!(Image::from_bytes(&written).unwrap(), image);
+    }
+
+    /// Inserts an APP1 Exif segment holding only the orientation tag right after the JPEG's SOI
+    fn with_exif_orientation(jpeg: &[u8], orientation: u8) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, orientation, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend_from_slice(&[0xFF, 0xE1]);
+        tagged.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        tagged.extend_from_slice(&exif);
+        tagged.extend_from_slice(&jpeg[2..]);
+        tagged
+    }
+
+    #[test]
+    fn orientation_6_jpeg_comes_out_upright() {
+        // Stored sideways, red on the left and blue on the right
+        let mut stored = Image::new(16, 8, 255, PPMFormat::P6);
+        for (row, col, _) in stored.clone().iter_pixels() {
+            let color = if col < 8 { "red" } else { "blue" };
+            stored.set_pixel(row, col, PixelRGB::from_name(color).unwrap());
+        }
+        let jpeg = stored
+            .encode(ImageFormat::Jpeg, &EncodeOptions::default())
+            .unwrap();
+        let path = temp_path("orientation6.jpg");
+        fs::write(&path, with_exif_orientation(&jpeg, 6)).unwrap();
+
+        let orientation = read_orientation(&path).unwrap();
+        let mut image = Image::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(orientation, Orientation::Rotate90);
+        image.apply_orientation(orientation);
+
+        // Turning clockwise brings the left half up top
+        assert_eq!((image.width, image.height), (8, 16));
+        let top = image.get_pixel(3, 4).unwrap();
+        let bottom = image.get_pixel(12, 4).unwrap();
+        assert!(top.r > 200 && top.b < 60, "{:?}", top);
+        assert!(bottom.b > 200 && bottom.r < 60, "{:?}", bottom);
+    }
+
+    #[test]
+    fn dynamic_image_round_trips_without_encoding() {
+        let buffer = RgbImage::from_fn(5, 3, |col, row| {
+            Rgb([(col * 50) as u8, (row * 100) as u8, (col * row * 7) as u8])
+        });
+        let image = Image::from_dynamic_image(&DynamicImage::ImageRgb8(buffer.clone()));
+
+        assert_eq!(
+            (image.width, image.height, image.max_intensity),
+            (5, 3, 255)
+        );
+        assert!(image.alpha_channel.is_none());
+        assert_eq!(
+            image.get_pixel(2, 4),
+            Some(PixelRGB {
+                r: 200,
+                g: 200,
+                b: 56
+            })
+        );
+        assert_eq!(image.to_dynamic_image(), buffer);
+    }
+
+    #[test]
+    fn dynamic_image_keeps_16_bit_samples_and_alpha() {
+        let buffer = ImageBuffer::from_fn(2, 2, |col, row| {
+            Rgba([1000 * col as u16, 65535, 7, 30000 * row as u16])
+        });
+        let image = Image::from_dynamic_image(&DynamicImage::ImageRgba16(buffer));
+
+        assert_eq!(image.max_intensity, 65535);
+        let pixel = image.get_pixel_rgba(1, 1).unwrap();
+        assert_eq!(
+            (pixel.r, pixel.g, pixel.b, pixel.a),
+            (1000, 65535, 7, 30000)
+        );
+    }
+
+    #[test]
+    fn ascii_max_intensity_must_fit_in_16_bits() {
+        assert!(Image::from_bytes(b"P3\n1 1\n0\n0 0 0\n").is_err());
+        assert!(Image::from_bytes(b"P3\n1 1\n65536\n0 0 0\n").is_err());
+        assert!(Image::from_bytes(b"P3\n1 1\n65535\n65535 0 1\n").is_ok());
+    }
+
+    #[test]
+    fn ascii_maxval_1000_saves_as_p6_without_corruption() {
+        let image = Image::from_bytes(b"P3\n2 1\n1000\n1000 999 256  0 255 512\n").unwrap();
+        assert_eq!(image.max_intensity, 1000);
+
+        let binary = image.bytes_format(PPMFormat::P6).unwrap();
+        assert!(binary.starts_with(b"P6\n2 1\n1000\n"));
+        let reread = Image::from_bytes(&binary).unwrap();
+        assert_eq!(reread.max_intensity, 1000);
+        assert_eq!(
+            reread.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 1000,
+                g: 999,
+                b: 256
+            })
+        );
+        assert_eq!(reread.red_channel, image.red_channel);
+        assert_eq!(reread.green_channel, image.green_channel);
+        assert_eq!(reread.blue_channel, image.blue_channel);
+    }
+
+    #[test]
+    fn lower_jpeg_quality_writes_a_smaller_file() {
+        let image = noise_image(64, 64, 2015);
+        let size_at = |quality: u8| {
+            let path = temp_path(&format!("quality-{}.jpg", quality));
+            let options = EncodeOptions {
+                jpeg_quality: Some(quality),
+                ..Default::default()
+            };
+            image.save_with_options(&path, &options).unwrap();
+            let size = fs::metadata(&path).unwrap().len();
+            fs::remove_file(&path).unwrap();
+            size
+        };
+        assert!(size_at(10) < size_at(95));
+    }
+
+    #[test]
+    fn png_compression_keeps_the_pixels() {
+        let image = noise_image(16, 9, 2015);
+        let path = temp_path("compressed.png");
+        let options = EncodeOptions {
+            png_compression: Some(CompressionType::Best),
+            ..Default::default()
+        };
+        image.save_with_options(&path, &options).unwrap();
+        let reread = Image::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(reread.red_channel, image.red_channel);
+        assert_eq!(reread.green_channel, image.green_channel);
+        assert_eq!(reread.blue_channel, image.blue_channel);
+    }
+
+    #[test]
+    fn format_names_match_file_extensions() {
+        assert_eq!(format_from_extension("PNG").unwrap(), ImageFormat::Png);
+        assert_eq!(format_from_extension("jpeg").unwrap(), ImageFormat::Jpeg);
+        assert_eq!(infer_type("dir/photo.JPG").unwrap(), ImageFormat::Jpeg);
+        assert_eq!(infer_type("out.pam").unwrap(), ImageFormat::Pnm);
+        assert!(format_from_extension("docx").is_err());
+        assert!(infer_type(STDIO_PATH).is_err());
+    }
+
+    #[test]
+    fn encoded_bytes_round_trip_through_a_cursor() {
+        let image = noise_image(6, 4, 2022);
+        for format in [ImageFormat::Png, ImageFormat::Pnm, ImageFormat::Bmp] {
+            let mut cursor = Cursor::new(Vec::new());
+            cursor
+                .write_all(&image.encode(format, &EncodeOptions::default()).unwrap())
+                .unwrap();
+            cursor.set_position(0);
+
+            let mut bytes = Vec::new();
+            cursor.read_to_end(&mut bytes).unwrap();
+            let decoded = Image::decode(&bytes).unwrap();
+            assert_eq!(decoded.red_channel, image.red_channel, "{:?}", format);
+            assert_eq!(decoded.green_channel, image.green_channel, "{:?}", format);
+            assert_eq!(decoded.blue_channel, image.blue_channel, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn failed_save_leaves_no_partial_output() {
+        let dir = temp_path("atomic");
+        let _ = fs::remove_dir_all(&dir);
+        // A non-empty directory sits where the output should go, so the final rename fails
+        let target = Path::new(&dir).join("out.png");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("keep"), b"kept").unwrap();
+
+        let image = noise_image(6, 4, 2025);
+        assert!(image.save(&target.to_string_lossy()).is_err());
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["out.png"]);
+        assert_eq!(fs::read(target.join("keep")).unwrap(), b"kept");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn successful_save_replaces_the_file_without_a_temp() {
+        let dir = temp_path("atomic-ok");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let target = Path::new(&dir).join("out.ppm");
+        fs::write(&target, b"old contents").unwrap();
+
+        let image = noise_image(6, 4, 2025);
+        image.save(&target.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), image.bytes().unwrap());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A noise image carrying a noise alpha channel
+    fn rgba_noise(width: usize, height: usize, seed: u64) -> Image {
+        let mut image = noise_image(width, height, seed);
+        let alpha = noise_image(width, height, seed + 1).red_channel;
+        image.alpha_channel = Some(alpha);
+        image
+    }
+
+    #[test]
+    fn four_channel_pam_round_trips() {
+        let image = rgba_noise(7, 5, 2026);
+        let bytes = image.bytes().unwrap();
+        assert!(bytes.starts_with(b"P7\nWIDTH 7\nHEIGHT 5\nDEPTH 4\nMAXVAL 255\n"));
+
+        let reread = Image::from_bytes(&bytes).unwrap();
+        assert_eq!(reread.format, PPMFormat::P7);
+        assert_eq!(reread.red_channel, image.red_channel);
+        assert_eq!(reread.green_channel, image.green_channel);
+        assert_eq!(reread.blue_channel, image.blue_channel);
+        assert_eq!(reread.alpha_channel, image.alpha_channel);
+    }
+
+    #[test]
+    fn sixteen_bit_pam_round_trips() {
+        let mut image = rgba_noise(3, 2, 2026);
+        image.max_intensity = 4000;
+        for channel in image.all_channels_mut() {
+            channel.apply_in_place(|value| *value *= 15);
+        }
+
+        let reread = Image::from_bytes(&image.bytes().unwrap()).unwrap();
+        assert_eq!(reread.max_intensity, 4000);
+        assert_eq!(reread.red_channel, image.red_channel);
+        assert_eq!(reread.alpha_channel, image.alpha_channel);
+    }
+
+    #[test]
+    fn gray_alpha_pam_fills_every_color_channel() {
+        let mut bytes = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\n".to_vec();
+        bytes.extend_from_slice(b"# comment\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n");
+        bytes.extend_from_slice(&[10, 255, 200, 0]);
+
+        let image = Image::from_bytes(&bytes).unwrap();
+        let pixel = image.get_pixel_rgba(0, 1).unwrap();
+        assert_eq!((pixel.r, pixel.g, pixel.b, pixel.a), (200, 200, 200, 0));
+        assert_eq!(image.get_pixel_rgba(0, 0).unwrap().a, 255);
+    }
+
+    #[test]
+    fn malformed_pam_headers_are_rejected() {
+        let header = |fields: &str| format!("P7\n{}ENDHDR\n", fields).into_bytes();
+        assert!(Image::from_bytes(&header("WIDTH 1\nHEIGHT 1\nMAXVAL 255\n")).is_err());
+        assert!(Image::from_bytes(&header("WIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\n")).is_err());
+        assert!(Image::from_bytes(&header("WIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 0\n")).is_err());
+        assert!(Image::from_bytes(&header("WIDTH 1\nHEIGHT 1\nDEPTH 3\nCOLOR 3\n")).is_err());
+        assert!(Image::from_bytes(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\n").is_err());
+
+        // Too few samples for the declared size
+        let mut short = header("WIDTH 2\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\n");
+        short.extend_from_slice(&[1, 2, 3]);
+        assert!(Image::from_bytes(&short).is_err());
+
+        // Sizes that overflow, or that the data cannot back, fail without allocating them
+        let overflowing = header("WIDTH 4000000000\nHEIGHT 4000000000\nDEPTH 4\nMAXVAL 255\n");
+        assert!(Image::from_bytes(&overflowing).is_err());
+        let mut oversized = header("WIDTH 200000\nHEIGHT 200000\nDEPTH 4\nMAXVAL 65535\n");
+        oversized.extend_from_slice(b"abc");
+        assert!(Image::from_bytes(&oversized).is_err());
+    }
+
+    #[test]
+    fn transparency_survives_load_crop_and_save() {
+        let (source, output) = (temp_path("alpha-in.png"), temp_path("alpha-out.png"));
+        let buffer = RgbaImage::from_fn(6, 4, |col, row| {
+            Rgba([
+                (col * 40) as u8,
+                (row * 60) as u8,
+                90,
+                (col * 50 + row) as u8,
+            ])
+        });
+        buffer.save(&source).unwrap();
+
+        let mut image = Image::from_file(&source).unwrap();
+        image.crop_rect(3, 2, 2, 1).unwrap();
+        image.save(&output).unwrap();
+        let reread = image::open(&output).unwrap();
+        fs::remove_file(&source).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        assert!(reread.color().has_alpha());
+        let expected = RgbaImage::from_fn(3, 2, |col, row| *buffer.get_pixel(col + 2, row + 1));
+        assert_eq!(reread.to_rgba8(), expected);
+    }
+
+    #[test]
+    fn ascii_headers_may_share_or_split_lines() {
+        let expected = Image::from_bytes(b"P3\n2 1\n255\n1 2 3 4 5 6\n").unwrap();
+        for bytes in [
+            &b"P3 2 1 255 1 2 3 4 5 6"[..],
+            b"P3\n2\n1\n255\n1 2 3\n4 5 6\n",
+            b"P3\t2   1\r\n255 1 2 3 4 5 6\r\n",
+        ] {
+            let image = Image::from_bytes(bytes).unwrap();
+            assert_eq!(image, expected, "{}", String::from_utf8_lossy(bytes));
+        }
+    }
+
+    #[test]
+    fn comments_may_appear_anywhere_in_the_header() {
+        let expected = Image::from_bytes(b"P3\n2 1\n255\n1 2 3 4 5 6\n").unwrap();
+        for bytes in [
+            &b"P3\n# made by hand\n2 1\n255\n1 2 3 4 5 6\n"[..],
+            b"P3 2 # width then height\n1 # rows\n255\n1 2 3 4 5 6\n",
+            b"P3\n2#no space before the comment\n1\n# before maxval\n255\n1 2 3 # mid pixels\n4 5 6\n",
+        ] {
+            let image = Image::from_bytes(bytes).unwrap();
+            assert_eq!(image, expected, "{}", String::from_utf8_lossy(bytes));
+        }
+
+        let binary = Image::from_bytes(
+            b"P6\n# comment\n2 # width\n1\n#maxval next\n255\n\x01\x02\x03\x04\x05\x06",
+        )
+        .unwrap();
+        assert_eq!(binary.get_pixel(0, 1), expected.get_pixel(0, 1));
+        assert!(Image::from_bytes(b"P3 2 1 255 1 2 3 4 5").is_err());
+    }
+
+    #[test]
+    fn sixteen_bit_png_and_tiff_keep_their_low_bits() {
+        let buffer = ImageBuffer::from_fn(3, 2, |col, row| {
+            Rgb([257 * col as u16 + 1, 40000 + row as u16, 300])
+        });
+        for extension in ["png", "tiff"] {
+            let path = temp_path(&format!("sixteen.{}", extension));
+            DynamicImage::ImageRgb16(buffer.clone())
+                .save(&path)
+                .unwrap();
+            let image = Image::from_file(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(image.max_intensity, 65535, "{}", extension);
+            assert_eq!(
+                image.get_pixel(1, 2),
+                Some(PixelRGB {
+                    r: 515,
+                    g: 40001,
+                    b: 300
+                }),
+                "{}",
+                extension
+            );
+        }
+    }
+
+    #[test]
+    fn binary_pixels_may_look_like_whitespace_or_comments() {
+        // A single whitespace ends the header, so pixel bytes that read as newlines, spaces or
+        // comment markers are still samples
+        let pixels = [b'\n', b' ', b'#', b'\n', b'\r', b'\t'];
+        let mut bytes = b"P6\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&pixels);
+
+        let image = Image::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            (image.get_pixel(0, 0), image.get_pixel(0, 1)),
+            (
+                Some(PixelRGB {
+                    r: 10,
+                    g: 32,
+                    b: 35
+                }),
+                Some(PixelRGB { r: 10, g: 13, b: 9 })
+            )
+        );
+        assert_eq!(image.bytes_format(PPMFormat::P6).unwrap(), bytes);
+    }
+
+    #[test]
+    fn sixteen_bit_binary_samples_may_contain_newline_bytes() {
+        let mut bytes = b"P6 1 1 65535\n".to_vec();
+        bytes.extend_from_slice(&[0x0A, 0x0A, 0x00, 0x0A, 0x0A, 0x20]);
+
+        let image = Image::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            image.get_pixel(0, 0),
+            Some(PixelRGB {
+                r: 0x0A0A,
+                g: 0x000A,
+                b: 0x0A20
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_binary_pixels_are_rejected() {
+        let mut bytes = b"P6\n2 2\n255\n".to_vec();
+        bytes.extend_from_slice(&[10; 11]);
+        assert!(Image::from_bytes(&bytes).is_err());
+
+        let mut deep = b"P6\n1 1\n65535\n".to_vec();
+        deep.extend_from_slice(&[10; 5]);
+        assert!(Image::from_bytes(&deep).is_err());
+    }
+
+    #[test]
+    fn frame_from_file_picks_the_indexed_frame() {
+        let path = temp_path("two-frames.gif");
+        write_gif(&path, &[100, 200]);
+        let second = frame_from_file(&path, 1).unwrap();
+        let missing = frame_from_file(&path, 2).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(second.delay_ms, 200);
+        assert_eq!((second.image.width, second.image.height), (4, 3));
+        assert!(
+            second
+                .image
+                .iter_pixels()
+                .all(|(_, _, pixel)| pixel == PixelRGB { r: 60, g: 0, b: 0 })
+        );
+        assert!(missing.to_string().contains("2 frame(s)"), "{}", missing);
+    }
+
+    #[test]
+    fn reused_buffers_match_bytes_format() {
+        let frames = [
+            noise_image(9, 7, 1),
+            noise_image(3, 2, 2),
+            noise_image(5, 4, 3),
+        ];
+        let mut buffer = Vec::new();
+        for format in [PPMFormat::P3, PPMFormat::P6, PPMFormat::P7] {
+            for frame in &frames {
+                frame
+                    .write_format_into(&mut buffer, format.clone())
+                    .unwrap();
+                assert_eq!(buffer, frame.bytes_format(format.clone()).unwrap());
+            }
+        }
+
+        // Once large enough, the buffer is written in place
+        frames[0]
+            .write_format_into(&mut buffer, PPMFormat::P3)
+            .unwrap();
+        let allocation = buffer.as_ptr();
+        frames[1]
+            .write_format_into(&mut buffer, PPMFormat::P6)
+            .unwrap();
+        assert_eq!(buffer.as_ptr(), allocation);
+        assert!(buffer.starts_with(b"P6\n3 2\n255\n"));
+        assert_eq!(buffer.len(), 11 + 3 * 2 * 3);
+    }
+
+    #[test]
+    fn oversized_binary_headers_are_errors() {
+        // Dimensions whose product overflows usize
+        let overflowing = Image::from_bytes(b"P6\n4000000000 4000000000\n255\n").unwrap_err();
+        assert!(
+            overflowing.to_string().contains("too large"),
+            "{}",
+            overflowing
+        );
+        let overflowing = Image::from_bytes(b"P6\n18446744073709551615 1\n255\n\0\0\0");
+        assert!(overflowing.is_err());
+
+        // A plausible size with almost no data behind it must not allocate the full raster
+        let truncated = Image::from_bytes(b"P6\n200000 200000\n255\nabc").unwrap_err();
+        assert!(truncated.to_string().contains("shorter"), "{}", truncated);
+    }
+
+    #[test]
+    fn binary_samples_above_the_max_intensity_are_rejected() {
+        assert!(Image::from_bytes(b"P6\n1 1\n100\n\x64\x64\x65").is_err());
+        assert!(Image::from_bytes(b"P6\n1 1\n300\n\x01\x2c\x01\x2c\x01\x2d").is_err());
+        assert!(Image::from_bytes(b"P6\n1 1\n300\n\x01\x2c\x01\x2c\x01\x2c").is_ok());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::img::image::*;
+use crate::img::utils::{PixelRGB, clamp_channel};
+
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+
+/// Largest 3D LUT accepted, well past the 65 point cubes grading tools export
+const MAX_LUT_SIZE: usize = 256;
+
+/// A 3D color lookup table sampled on a size x size x size lattice over the RGB cube
+#[derive(Debug, Clone)]
+pub struct Lut3D {
+    pub size: usize,
+    pub domain_min: [f64; 3],
+    pub domain_max: [f64; 3],
+    /// Output colors with red varying fastest, then green, then blue, as .cube files list them
+    pub table: Vec<[f64; 3]>,
+}
+
+impl Lut3D {
+    /// Reads a .cube file
+    pub fn from_file(path: &str) -> Result<Lut3D, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(contents
+            .parse()
+            .map_err(|e| format!("Invalid LUT '{}': {}", path, e))?)
+    }
+
+    /// The identity table of the given size, which maps every color to itself
+    pub fn identity(size: usize) -> Lut3D {
+        let step = |i: usize| i as f64 / (size - 1) as f64;
+        let table = (0..size * size * size)
+            .map(|i| {
+                [
+                    step(i % size),
+                    step(i / size % size),
+                    step(i / (size * size)),
+                ]
+            })
+            .collect();
+
+        Lut3D {
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table,
+        }
+    }
+
+    fn at(&self, r: usize, g: usize, b: usize) -> [f64; 3] {
+        self.table[(b * self.size + g) * self.size + r]
+    }
+
+    /// Trilinearly interpolates the table at a color given in the LUT's domain
+    pub fn lookup(&self, color: [f64; 3]) -> [f64; 3] {
+        let last = (self.size - 1) as f64;
+        let mut lower = [0; 3];
+        let mut fraction = [0.0; 3];
+        for c in 0..3 {
+            let span = self.domain_max[c] - self.domain_min[c];
+            let position = ((color[c] - self.domain_min[c]) / span * last).clamp(0.0, last);
+            // The top lattice point interpolates toward itself so it never reads past the table
+            lower[c] = (position.floor() as usize).min(self.size - 2);
+            fraction[c] = position - lower[c] as f64;
+        }
+
+        let mut result = [0.0; 3];
+        for corner in 0..8 {
+            let offset = |c: usize| (corner >> c) & 1;
+            let weight: f64 = (0..3)
+                .map(|c| match offset(c) {
+                    0 => 1.0 - fraction[c],
+                    _ => fraction[c],
+                })
+                .product();
+            if weight == 0.0 {
+                continue;
+            }
+
+            let value = self.at(
+                lower[0] + offset(0),
+                lower[1] + offset(1),
+                lower[2] + offset(2),
+            );
+            for c in 0..3 {
+                result[c] += weight * value[c];
+            }
+        }
+
+        result
+    }
+}
+
+/// Parses three whitespace separated numbers
+fn parse_triple<'a>(mut values: impl Iterator<Item = &'a str>) -> Result<[f64; 3], String> {
+    let mut triple = [0.0; 3];
+    for value in triple.iter_mut() {
+        let token = values.next().ok_or("expected three values")?;
+        *value = token
+            .parse()
+            .map_err(|_| format!("'{}' is not a number", token))?;
+    }
+    if values.next().is_some() {
+        return Err("expected three values".into());
+    }
+
+    Ok(triple)
+}
+
+impl FromStr for Lut3D {
+    type Err = String;
+
+    /// Parses the text of an Adobe/Resolve .cube file holding a 3D table
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap_or("");
+            let located = |e: String| format!("line {}: {}", number + 1, e);
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err("1D LUTs are not supported".into()),
+                "LUT_3D_SIZE" => {
+                    let value: usize = tokens
+                        .next()
+                        .and_then(|token| token.parse().ok())
+                        .ok_or_else(|| located("LUT_3D_SIZE needs a whole number".into()))?;
+                    if !(2..=MAX_LUT_SIZE).contains(&value) {
+                        return Err(located(format!(
+                            "LUT_3D_SIZE must be between 2 and {}",
+                            MAX_LUT_SIZE
+                        )));
+                    }
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain_min = parse_triple(tokens).map_err(located)?,
+                "DOMAIN_MAX" => domain_max = parse_triple(tokens).map_err(located)?,
+                // Resolve's spelling of a domain shared by all three channels
+                "LUT_3D_INPUT_RANGE" => {
+                    let range: Vec<f64> = tokens.filter_map(|token| token.parse().ok()).collect();
+                    let [min, max] = range[..] else {
+                        return Err(located("LUT_3D_INPUT_RANGE needs two numbers".into()));
+                    };
+                    (domain_min, domain_max) = ([min; 3], [max; 3]);
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(located(format!("unknown keyword '{}'", keyword)));
+                }
+                _ => table.push(parse_triple(line.split_whitespace()).map_err(located)?),
+            }
+        }
+
+        let size = size.ok_or("missing LUT_3D_SIZE")?;
+        if table.len() != size * size * size {
+            return Err(format!(
+                "expected {} table entries for size {} but found {}",
+                size * size * size,
+                size,
+                table.len()
+            ));
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err("DOMAIN_MAX must exceed DOMAIN_MIN on every channel".into());
+        }
+
+        Ok(Lut3D {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+}
+
+impl Image {
+    /// Maps every pixel through the 3D LUT with trilinear interpolation. Channels are scaled from
+    /// [0, max_intensity] into the LUT's domain and results are clamped back into range
+    pub fn apply_lut3d(&mut self, lut: &Lut3D) {
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+        let to_domain = |value: usize, c: usize| {
+            lut.domain_min[c] + value as f64 / max * (lut.domain_max[c] - lut.domain_min[c])
+        };
+
+        for (_, _, mut pixel) in self.iter_pixels_mut() {
+            let source = pixel.get();
+            let color = [
+                to_domain(source.r, 0),
+                to_domain(source.g, 1),
+                to_domain(source.b, 2),
+            ];
+            let [r, g, b] = lut
+                .lookup(color)
+                .map(|value| clamp_channel(value * max, max_intensity));
+            pixel.set(PixelRGB { r, g, b });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::noise_image;
+
+    /// The text of a .cube file whose table is the given function of the lattice color
+    fn cube(size: usize, f: impl Fn([f64; 3]) -> [f64; 3]) -> String {
+        let mut text = format!("TITLE \"test\"\n# generated\nLUT_3D_SIZE {}\n", size);
+        for color in Lut3D::identity(size).table {
+            let [r, g, b] = f(color);
+            text.push_str(&format!("{} {} {}\n", r, g, b));
+        }
+        text
+    }
+
+    #[test]
+    fn identity_cube_leaves_the_image_alone() {
+        let source = noise_image(10, 7, 2034);
+        for size in [2, 5, 17] {
+            let lut: Lut3D = cube(size, |color| color).parse().unwrap();
+            let mut graded = source.clone();
+            graded.apply_lut3d(&lut);
+            for ((_, _, graded), (_, _, original)) in graded.iter_pixels().zip(source.iter_pixels())
+            {
+                assert!(graded.squared_difference(&original) <= 3, "size {}", size);
+            }
+        }
+    }
+
+    #[test]
+    fn inverting_cube_matches_invert() {
+        let lut: Lut3D = cube(2, |color| color.map(|value| 1.0 - value))
+            .parse()
+            .unwrap();
+        let source = noise_image(6, 5, 2034);
+        let mut graded = source.clone();
+        graded.apply_lut3d(&lut);
+        let mut inverted = source.clone();
+        inverted.invert();
+        assert_eq!(graded, inverted);
+    }
+
+    #[test]
+    fn malformed_cubes_are_rejected() {
+        let identity = cube(2, |color| color);
+        for text in [
+            identity.replace("1 1 1\n", ""),
+            identity.replace("0 0 0\n", "0 0\n"),
+            format!("GAIN 2\n{}", identity),
+            format!("DOMAIN_MIN 1 1 1\nDOMAIN_MAX 0 0 0\n{}", identity),
+            "0 0 0\n".to_string(),
+            "LUT_3D_SIZE 1\n0 0 0\n".to_string(),
+            "LUT_1D_SIZE 4\n".to_string(),
+        ] {
+            assert!(text.parse::<Lut3D>().is_err(), "{}", text);
+        }
+    }
+}
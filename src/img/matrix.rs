@@ -1,8 +1,43 @@
 use std::fmt;
 use std::ops::{Index, IndexMut};
 
+/// How neighborhoods sample positions that fall outside the matrix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderMode {
+    /// Repeats the nearest edge value
+    Clamp,
+    /// Uses the type's default value
+    Zero,
+    /// Mirrors about the edge value, so -1 samples index 1
+    Reflect,
+}
+
+impl BorderMode {
+    /// Maps a possibly out of range index onto [0, len), or None when the default should be used
+    fn resolve(self, index: isize, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        if (0..len as isize).contains(&index) {
+            return Some(index as usize);
+        }
+
+        let len = len as isize;
+        match self {
+            Self::Clamp => Some(index.clamp(0, len - 1) as usize),
+            Self::Zero => None,
+            Self::Reflect if len == 1 => Some(0),
+            Self::Reflect => {
+                let period = 2 * (len - 1);
+                let folded = index.rem_euclid(period);
+                Some(folded.min(period - folded) as usize)
+            }
+        }
+    }
+}
+
 /// A generic matrix type
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Matrix<T> {
     pub width: usize,
     pub height: usize,
@@ -36,7 +71,7 @@ where
 
     pub fn from_vec(width: usize, height: usize, data: Vec<T>) -> Option<Self> {
         if data.len() != width * height {
-            return None;
+            None
         } else {
             Some(Self {
                 width,
@@ -68,18 +103,45 @@ where
 
     pub fn fill_border(&mut self, value: T) {
         let (width, height) = (self.width, self.height);
+        if width == 0 || height == 0 {
+            return;
+        }
 
         for col in 0..width {
-            self[(0, col)] = value.clone();
-            self[(height - 1, col)] = value.clone();
+            self[(0, col)] = value;
+            self[(height - 1, col)] = value;
         }
 
         for row in 0..height {
-            self[(row, 0)] = value.clone();
-            self[(row, width - 1)] = value.clone();
+            self[(row, 0)] = value;
+            self[(row, width - 1)] = value;
         }
     }
 
+    /// Returns the (2 * radius + 1)² window centered on (row, col) in row-major order, sampling
+    /// positions outside the matrix according to the border mode
+    pub fn neighborhood(&self, row: usize, col: usize, radius: usize, border: BorderMode) -> Vec<T>
+    where
+        T: Default,
+    {
+        let radius = radius as isize;
+        let (row, col) = (row as isize, col as isize);
+        let mut window = Vec::with_capacity((2 * radius as usize + 1).pow(2));
+
+        for offset_row in -radius..=radius {
+            let src_row = border.resolve(row + offset_row, self.height);
+            for offset_col in -radius..=radius {
+                let src_col = border.resolve(col + offset_col, self.width);
+                window.push(match src_row.zip(src_col) {
+                    Some(position) => self[position],
+                    None => T::default(),
+                });
+            }
+        }
+
+        window
+    }
+
     pub fn min(&self) -> Option<T> {
         self.datum.iter().copied().min()
     }
@@ -118,6 +180,39 @@ where
         Some((min_index, min_val))
     }
 
+    /// Returns minimum in column's range as: (index, val)
+    pub fn min_in_col_range(
+        &self,
+        col: usize,
+        row_start: usize,
+        row_end: usize,
+    ) -> Option<(usize, T)> {
+        if col >= self.width || row_start >= row_end || row_end > self.height {
+            return None;
+        }
+
+        let mut min_val = self[(row_start, col)];
+        let mut min_index = row_start;
+
+        for row in (row_start + 1)..row_end {
+            let val = self[(row, col)];
+            if val < min_val {
+                min_val = val;
+                min_index = row;
+            }
+        }
+
+        Some((min_index, min_val))
+    }
+
+    /// Drops every row past the new height
+    pub fn trim_height(&mut self, new_height: usize) {
+        assert!(new_height <= self.height);
+
+        self.datum.truncate(new_height * self.width);
+        self.height = new_height;
+    }
+
     pub fn trim_width(&mut self, new_width: usize) {
         assert!(new_width <= self.width);
 
@@ -134,17 +229,65 @@ where
         self.width = new_width;
     }
 
-    pub fn transpose(&mut self) {
-        let mut new_data = vec![self.datum[0]; self.width * self.height];
-        for row in 0..self.height {
-            for col in 0..self.width {
-                new_data[col * self.height + row] = self[(row, col)];
+    /// Removes one element per row at the given columns, shrinking the width by one
+    pub fn remove_vertical_seam(&mut self, seam: &[usize]) {
+        for (row, &seam_col) in seam.iter().enumerate() {
+            let start = row * self.width;
+            self.datum[start + seam_col..start + self.width].rotate_left(1);
+        }
+        self.trim_width(self.width - 1);
+    }
+
+    /// Removes one element per column at the given rows, shrinking the height by one
+    pub fn remove_horizontal_seam(&mut self, seam: &[usize]) {
+        for (col, &seam_row) in seam.iter().enumerate() {
+            for row in seam_row..self.height - 1 {
+                self[(row, col)] = self[(row + 1, col)];
             }
         }
+        self.trim_height(self.height - 1);
+    }
+
+    pub fn transpose(&mut self) {
+        // Built column by column so empty matrices never need a seed element
+        let new_data = (0..self.width)
+            .flat_map(|col| (0..self.height).map(move |row| (row, col)))
+            .map(|(row, col)| self[(row, col)])
+            .collect();
         std::mem::swap(&mut self.width, &mut self.height);
         self.datum = new_data;
     }
 
+    /// Returns a copy rotated 90 degrees in the given direction, with the dimensions swapped
+    pub fn rotate90(&self, clockwise: bool) -> Matrix<T> {
+        let (width, height) = (self.width, self.height);
+        let datum = (0..width)
+            .flat_map(|new_row| (0..height).map(move |new_col| (new_row, new_col)))
+            .map(|(new_row, new_col)| {
+                if clockwise {
+                    self[(height - 1 - new_col, new_row)]
+                } else {
+                    self[(new_col, width - 1 - new_row)]
+                }
+            })
+            .collect();
+
+        Matrix {
+            width: height,
+            height: width,
+            datum,
+        }
+    }
+
+    /// Returns a copy rotated 180 degrees, which in row-major order is the data reversed
+    pub fn rotate180(&self) -> Matrix<T> {
+        Matrix {
+            width: self.width,
+            height: self.height,
+            datum: self.datum.iter().rev().copied().collect(),
+        }
+    }
+
     pub fn mirror_y(&mut self) {
         for row in 0..self.height {
             for col in 0..self.width / 2 {
@@ -167,6 +310,13 @@ where
     }
 }
 
+impl<T> Matrix<T> {
+    /// Runs f on every element in row-major order, mutating the matrix without reallocating
+    pub fn apply_in_place<F: FnMut(&mut T)>(&mut self, f: F) {
+        self.datum.iter_mut().for_each(f);
+    }
+}
+
 impl<T> Index<(usize, usize)> for Matrix<T> {
     type Output = T;
 
@@ -201,3 +351,97 @@ impl<T: fmt::Debug> fmt::Debug for Matrix<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::Image;
+    use crate::img::io::PPMFormat;
+
+    #[test]
+    fn transposing_empty_matrices_swaps_dimensions() {
+        for (width, height) in [(0, 5), (5, 0), (0, 0)] {
+            let mut matrix = Matrix::<usize>::new(width, height);
+            matrix.transpose();
+            assert_eq!((matrix.width, matrix.height), (height, width));
+            assert!(matrix.datum.is_empty());
+        }
+    }
+
+    #[test]
+    fn transpose_moves_rows_into_columns() {
+        let mut matrix = Matrix::from_vec(3, 2, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        matrix.transpose();
+        assert_eq!((matrix.width, matrix.height), (2, 3));
+        assert_eq!(matrix.datum, [1, 4, 2, 5, 3, 6]);
+    }
+
+    #[test]
+    fn transposing_an_empty_image_swaps_dimensions() {
+        let mut image = Image::new(0, 4, 255, PPMFormat::P6);
+        image.transpose();
+        assert_eq!((image.width, image.height), (4, 0));
+        assert_eq!((image.red_channel.width, image.red_channel.height), (4, 0));
+    }
+
+    #[test]
+    fn rotate90_turns_a_2x3_matrix() {
+        // Two wide and three tall
+        let matrix = Matrix::from_vec(2, 3, vec![1, 2, 3, 4, 5, 6]).unwrap();
+
+        let clockwise = matrix.rotate90(true);
+        assert_eq!((clockwise.width, clockwise.height), (3, 2));
+        assert_eq!(clockwise.datum, [5, 3, 1, 6, 4, 2]);
+
+        let counterclockwise = matrix.rotate90(false);
+        assert_eq!(counterclockwise.datum, [2, 4, 6, 1, 3, 5]);
+        assert_eq!(counterclockwise.rotate90(true), matrix);
+    }
+
+    #[test]
+    fn neighborhood_border_modes_at_the_corners() {
+        let matrix = Matrix::from_vec(3, 3, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+
+        let top_left = |border| matrix.neighborhood(0, 0, 1, border);
+        assert_eq!(top_left(BorderMode::Clamp), [1, 1, 2, 1, 1, 2, 4, 4, 5]);
+        assert_eq!(top_left(BorderMode::Zero), [0, 0, 0, 0, 1, 2, 0, 4, 5]);
+        assert_eq!(top_left(BorderMode::Reflect), [5, 4, 5, 2, 1, 2, 5, 4, 5]);
+
+        let bottom_right = |border| matrix.neighborhood(2, 2, 1, border);
+        assert_eq!(bottom_right(BorderMode::Clamp), [5, 6, 6, 8, 9, 9, 8, 9, 9]);
+        assert_eq!(bottom_right(BorderMode::Zero), [5, 6, 0, 8, 9, 0, 0, 0, 0]);
+        assert_eq!(
+            bottom_right(BorderMode::Reflect),
+            [5, 6, 5, 8, 9, 8, 5, 6, 5]
+        );
+
+        // The interior never touches the border
+        assert_eq!(
+            matrix.neighborhood(1, 1, 1, BorderMode::Zero),
+            [1, 2, 3, 4, 5, 6, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn apply_in_place_doubles_without_reallocating() {
+        let mut matrix = Matrix::from_vec(3, 2, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let buffer = matrix.datum.as_ptr();
+        matrix.apply_in_place(|value| *value *= 2);
+        assert_eq!(matrix.datum, [2, 4, 6, 8, 10, 12]);
+        assert_eq!(matrix.datum.as_ptr(), buffer);
+        assert_eq!((matrix.width, matrix.height), (3, 2));
+
+        // Elements need not be Copy or Default
+        let mut names = Matrix {
+            width: 2,
+            height: 1,
+            datum: vec![String::from("a"), String::from("b")],
+        };
+        let mut visited = 0;
+        names.apply_in_place(|name| {
+            name.push('!');
+            visited += 1;
+        });
+        assert_eq!((names.datum, visited), (vec!["a!".into(), "b!".into()], 2));
+    }
+}
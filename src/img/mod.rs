@@ -1,7 +1,25 @@
+#[cfg(feature = "ndarray")]
+pub mod array;
+pub mod ascii;
+pub mod bench;
+pub mod braille;
+pub mod concat;
 pub mod crop;
+pub mod cvd;
+pub mod dither;
+pub mod effects;
+pub mod filters;
+pub mod histogram;
 pub mod image;
 pub mod io;
+pub mod lut;
 pub mod matrix;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod palette;
+pub mod pipeline;
+pub mod presets;
+pub mod random;
 pub mod scale;
 pub mod seam;
 pub mod utils;
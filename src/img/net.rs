@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::time::Duration;
+
+use ureq::Agent;
+
+/// Redirects followed before a fetch gives up
+const MAX_REDIRECTS: u32 = 5;
+
+/// How long the whole request may take before the server is treated as unresponsive
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest body read into memory, well above any image snap is expected to decode
+const MAX_BODY_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Fetches the body of an http or https URL, following redirects. Responses outside 2xx are
+/// errors
+pub fn fetch(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .max_redirects(MAX_REDIRECTS)
+        .http_status_as_error(false)
+        .build()
+        .into();
+
+    let mut response = agent.get(url).call()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Request for '{}' failed with HTTP {}", url, status.as_u16()).into());
+    }
+
+    Ok(response
+        .body_mut()
+        .with_config()
+        .limit(MAX_BODY_BYTES)
+        .read_to_vec()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::Image;
+    use crate::img::image::tests::noise_image;
+    use crate::img::io::EncodeOptions;
+
+    use image::ImageFormat;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves each connection from a background thread: the PNG at /image.png, a relative
+    /// redirect to it at /moved/image, and a 404 for every other path. Returns the base URL
+    fn serve(png: Vec<u8>, connections: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Drain the headers so closing the socket does not reset the connection
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or("");
+                let (status, extra, body): (_, _, &[u8]) = match path {
+                    "/image.png" => ("200 OK", "Content-Type: image/png\r\n", &png),
+                    "/moved/image" => ("302 Found", "Location: ../image.png\r\n", b""),
+                    _ => ("404 Not Found", "", b"missing"),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    extra,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn fetches_follows_redirects_and_rejects_missing_files() {
+        let image = noise_image(7, 5, 2030);
+        let png = image
+            .encode(ImageFormat::Png, &EncodeOptions::default())
+            .unwrap();
+        // The redirect takes two connections, the direct fetch and the 404 one each
+        let base = serve(png.clone(), 4);
+
+        assert_eq!(fetch(&format!("{}/image.png", base)).unwrap(), png);
+        let redirected = Image::from_url(&format!("{}/moved/image", base)).unwrap();
+        assert_eq!(redirected, image);
+
+        let missing = fetch(&format!("{}/nothing.png", base)).unwrap_err();
+        assert!(missing.to_string().contains("HTTP 404"), "{}", missing);
+    }
+}
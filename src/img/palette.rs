@@ -0,0 +1,156 @@
+use crate::img::image::*;
+use crate::img::io::PPMFormat;
+use crate::img::utils::PixelRGB;
+
+use std::error::Error;
+
+/// Side length of each swatch when rendering a palette
+pub const SWATCH_SIZE: usize = 64;
+
+impl Image {
+    /// Extracts up to the given number of dominant colors using median cut
+    pub fn palette(&self, colors: usize) -> Vec<PixelRGB> {
+        let pixels: Vec<[usize; 3]> = (0..self.height)
+            .flat_map(|row| (0..self.width).map(move |col| (row, col)))
+            .map(|(row, col)| {
+                [
+                    self.red_channel[(row, col)],
+                    self.green_channel[(row, col)],
+                    self.blue_channel[(row, col)],
+                ]
+            })
+            .collect();
+
+        if pixels.is_empty() || colors == 0 {
+            return Vec::new();
+        }
+
+        let mut buckets = vec![pixels];
+        while buckets.len() < colors {
+            // Split the bucket spanning the widest range on any single channel
+            let widest = buckets
+                .iter()
+                .enumerate()
+                .filter(|(_, bucket)| bucket.len() > 1)
+                .map(|(index, bucket)| {
+                    let (channel, range) = (0..3)
+                        .map(|c| {
+                            let min = bucket.iter().map(|p| p[c]).min().unwrap_or(0);
+                            let max = bucket.iter().map(|p| p[c]).max().unwrap_or(0);
+                            (c, max - min)
+                        })
+                        .max_by_key(|&(_, range)| range)
+                        .unwrap_or((0, 0));
+                    (index, channel, range)
+                })
+                .max_by_key(|&(_, _, range)| range);
+
+            let Some((index, channel, range)) = widest else {
+                break;
+            };
+            if range == 0 {
+                break;
+            }
+
+            let mut bucket = buckets.swap_remove(index);
+            bucket.sort_unstable_by_key(|p| p[channel]);
+            let upper = bucket.split_off(bucket.len() / 2);
+            buckets.push(bucket);
+            buckets.push(upper);
+        }
+
+        let mut palette: Vec<PixelRGB> = buckets
+            .iter()
+            .map(|bucket| {
+                let count = bucket.len();
+                let sum = |c: usize| bucket.iter().map(|p| p[c]).sum::<usize>();
+                PixelRGB {
+                    r: (sum(0) + count / 2) / count,
+                    g: (sum(1) + count / 2) / count,
+                    b: (sum(2) + count / 2) / count,
+                }
+            })
+            .collect();
+        palette.sort_by_key(|p| p.r + p.g + p.b);
+        palette
+    }
+
+    /// Renders the colors as equally sized square swatches laid out left to right
+    pub fn from_palette(
+        colors: &[PixelRGB],
+        max_intensity: usize,
+    ) -> Result<Image, Box<dyn Error>> {
+        if colors.is_empty() {
+            return Err("Cannot render an empty palette".into());
+        }
+
+        let mut image = Image::new(
+            colors.len() * SWATCH_SIZE,
+            SWATCH_SIZE,
+            max_intensity,
+            PPMFormat::P6,
+        );
+
+        for (index, color) in colors.iter().enumerate() {
+            for row in 0..SWATCH_SIZE {
+                for col in index * SWATCH_SIZE..(index + 1) * SWATCH_SIZE {
+                    image.set_pixel(row, col, *color);
+                }
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, solid_image};
+
+    /// Distinct colors of the image in first-seen order, scanning rows left to right
+    fn distinct_colors(image: &Image) -> Vec<PixelRGB> {
+        let mut colors = Vec::new();
+        for (_, _, pixel) in image.iter_pixels() {
+            if !colors.contains(&pixel) {
+                colors.push(pixel);
+            }
+        }
+        colors
+    }
+
+    #[test]
+    fn palette_finds_the_blocks_of_a_four_color_image() {
+        let blocks = [(0, 0, 200), (30, 30, 30), (250, 10, 10), (90, 240, 90)];
+        let mut image = solid_image(8, 8, PixelRGB { r: 0, g: 0, b: 0 });
+        for (row, col, _) in image.clone().iter_pixels() {
+            let (r, g, b) = blocks[row / 4 * 2 + col / 4];
+            image.set_pixel(row, col, PixelRGB { r, g, b });
+        }
+
+        let palette = image.palette(4);
+        let mut expected: Vec<PixelRGB> = blocks
+            .iter()
+            .map(|&(r, g, b)| PixelRGB { r, g, b })
+            .collect();
+        expected.sort_by_key(|p| p.r + p.g + p.b);
+        assert_eq!(palette, expected);
+    }
+
+    #[test]
+    fn swatches_contain_exactly_the_palette() {
+        let palette = noise_image(20, 15, 2002).palette(8);
+        assert_eq!(palette.len(), 8);
+
+        let swatches = Image::from_palette(&palette, 255).unwrap();
+        assert_eq!(
+            (swatches.width, swatches.height),
+            (8 * SWATCH_SIZE, SWATCH_SIZE)
+        );
+        assert_eq!(distinct_colors(&swatches), palette);
+        for (index, color) in palette.iter().enumerate() {
+            let col = index * SWATCH_SIZE + SWATCH_SIZE / 2;
+            assert_eq!(swatches.get_pixel(SWATCH_SIZE - 1, col), Some(*color));
+        }
+    }
+}
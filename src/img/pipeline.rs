@@ -0,0 +1,433 @@
+use crate::img::crop::CropMethod;
+use crate::img::filters::DOWNSCALE_SHARPEN_SIGMA;
+use crate::img::image::*;
+use crate::img::scale::ScaleMethod;
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A single step of a pipeline, parsed from a spec like "scale:640,480" or "grayscale". Serialized
+/// with the spec names, as "grayscale" or {"scale": {"width": 640, "height": 480}}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Scale { width: usize, height: usize },
+    Resize { width: usize, height: usize },
+    SeamCarve { width: usize, height: usize },
+    CenterCrop { width: usize, height: usize },
+    Crop { width: usize, height: usize },
+    CropRect { x: usize, y: usize, width: usize, height: usize },
+    Square,
+    Thumbnail { width: usize, height: usize },
+    FitWithin { width: usize, height: usize },
+    #[serde(rename = "scale-rgb")]
+    ScaleRGB { r: f64, g: f64, b: f64 },
+    Adjust { brightness: i32, contrast: f64 },
+    Invert,
+    Gamma { gamma: f64 },
+    HueShift { degrees: f64 },
+    Vibrance { amount: f64 },
+    Saturation { factor: f64 },
+    Grayscale,
+    Sepia,
+    Threshold { level: usize },
+    Edges,
+    Median { radius: usize },
+    Blur { sigma: f64 },
+    BoxBlur { radius: usize },
+    Sharpen { amount: f64 },
+    Dither { levels: usize },
+    Posterize { levels: usize },
+    RotateLeft,
+    RotateRight,
+    Flip,
+    MirrorX,
+    MirrorY,
+    Transpose,
+    Pixelate { block_size: usize },
+    Kaleidoscope { segments: usize },
+    Linear,
+    Srgb,
+}
+
+/// Parses exactly count comma separated arguments for the named operation
+fn parse_args<T: FromStr>(name: &str, args: &str, count: usize) -> Result<Vec<T>, Box<dyn Error>> {
+    let values: Vec<&str> = if args.is_empty() {
+        Vec::new()
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+    if values.len() != count {
+        return Err(format!(
+            "Operation '{}' takes {} argument(s) but {} were given",
+            name,
+            count,
+            values.len()
+        )
+        .into());
+    }
+
+    values
+        .iter()
+        .map(|value| {
+            value.parse().map_err(|_| {
+                format!("Invalid argument '{}' for operation '{}'", value, name).into()
+            })
+        })
+        .collect()
+}
+
+impl FromStr for Operation {
+    type Err = Box<dyn Error>;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (name, args) = spec.split_once(':').unwrap_or((spec, ""));
+        let name = name.trim();
+
+        let operation = match name {
+            "scale" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::Scale {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "resize" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::Resize {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "seam-carve" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::SeamCarve {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "center-crop" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::CenterCrop {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "crop" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::Crop {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "crop-rect" => {
+                let a = parse_args(name, args, 4)?;
+                Operation::CropRect {
+                    x: a[0],
+                    y: a[1],
+                    width: a[2],
+                    height: a[3],
+                }
+            }
+            "thumbnail" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::Thumbnail {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "fit-within" | "fit" => {
+                let a = parse_args(name, args, 2)?;
+                Operation::FitWithin {
+                    width: a[0],
+                    height: a[1],
+                }
+            }
+            "scale-rgb" | "tint" => {
+                let a = parse_args(name, args, 3)?;
+                Operation::ScaleRGB {
+                    r: a[0],
+                    g: a[1],
+                    b: a[2],
+                }
+            }
+            "adjust" => {
+                let a: Vec<f64> = parse_args(name, args, 2)?;
+                Operation::Adjust {
+                    brightness: a[0].round() as i32,
+                    contrast: a[1],
+                }
+            }
+            "gamma" => Operation::Gamma {
+                gamma: parse_args(name, args, 1)?[0],
+            },
+            "hue-shift" | "hue" => Operation::HueShift {
+                degrees: parse_args(name, args, 1)?[0],
+            },
+            "vibrance" => Operation::Vibrance {
+                amount: parse_args(name, args, 1)?[0],
+            },
+            "saturation" | "sat" => Operation::Saturation {
+                factor: parse_args(name, args, 1)?[0],
+            },
+            "median" | "denoise" => Operation::Median {
+                radius: parse_args(name, args, 1)?[0],
+            },
+            "blur" | "gaussian" => Operation::Blur {
+                sigma: parse_args(name, args, 1)?[0],
+            },
+            "box-blur" => Operation::BoxBlur {
+                radius: parse_args(name, args, 1)?[0],
+            },
+            "sharpen" | "unsharp" => Operation::Sharpen {
+                amount: parse_args(name, args, 1)?[0],
+            },
+            "threshold" | "binarize" => Operation::Threshold {
+                level: parse_args(name, args, 1)?[0],
+            },
+            "dither" => Operation::Dither {
+                levels: parse_args(name, args, 1)?[0],
+            },
+            "posterize" => Operation::Posterize {
+                levels: parse_args(name, args, 1)?[0],
+            },
+            "pixelate" => Operation::Pixelate {
+                block_size: parse_args(name, args, 1)?[0],
+            },
+            "kaleidoscope" => Operation::Kaleidoscope {
+                segments: parse_args(name, args, 1)?[0],
+            },
+            _ => {
+                let operation = match name {
+                    "square" => Operation::Square,
+                    "invert" => Operation::Invert,
+                    "grayscale" | "gray" => Operation::Grayscale,
+                    "sepia" => Operation::Sepia,
+                    "edges" | "sobel" => Operation::Edges,
+                    "rotate-left" => Operation::RotateLeft,
+                    "rotate-right" => Operation::RotateRight,
+                    "flip" => Operation::Flip,
+                    "mirror-x" => Operation::MirrorX,
+                    "mirror-y" => Operation::MirrorY,
+                    "transpose" => Operation::Transpose,
+                    "linear" => Operation::Linear,
+                    "srgb" => Operation::Srgb,
+                    _ => return Err(format!("Unknown operation '{}'", name).into()),
+                };
+                // Everything else takes no arguments
+                parse_args::<f64>(name, args, 0)?;
+                operation
+            }
+        };
+
+        Ok(operation)
+    }
+}
+
+impl fmt::Display for Operation {
+    /// Writes the spec the operation parses back from
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Scale { width, height } => write!(f, "scale:{},{}", width, height),
+            Operation::Resize { width, height } => write!(f, "resize:{},{}", width, height),
+            Operation::SeamCarve { width, height } => write!(f, "seam-carve:{},{}", width, height),
+            Operation::CenterCrop { width, height } => {
+                write!(f, "center-crop:{},{}", width, height)
+            }
+            Operation::Crop { width, height } => write!(f, "crop:{},{}", width, height),
+            Operation::CropRect {
+                x,
+                y,
+                width,
+                height,
+            } => write!(f, "crop-rect:{},{},{},{}", x, y, width, height),
+            Operation::Square => write!(f, "square"),
+            Operation::Thumbnail { width, height } => write!(f, "thumbnail:{},{}", width, height),
+            Operation::FitWithin { width, height } => write!(f, "fit-within:{},{}", width, height),
+            Operation::ScaleRGB { r, g, b } => write!(f, "scale-rgb:{},{},{}", r, g, b),
+            Operation::Adjust {
+                brightness,
+                contrast,
+            } => write!(f, "adjust:{},{}", brightness, contrast),
+            Operation::Invert => write!(f, "invert"),
+            Operation::Gamma { gamma } => write!(f, "gamma:{}", gamma),
+            Operation::HueShift { degrees } => write!(f, "hue-shift:{}", degrees),
+            Operation::Vibrance { amount } => write!(f, "vibrance:{}", amount),
+            Operation::Saturation { factor } => write!(f, "saturation:{}", factor),
+            Operation::Grayscale => write!(f, "grayscale"),
+            Operation::Sepia => write!(f, "sepia"),
+            Operation::Threshold { level } => write!(f, "threshold:{}", level),
+            Operation::Edges => write!(f, "edges"),
+            Operation::Median { radius } => write!(f, "median:{}", radius),
+            Operation::Blur { sigma } => write!(f, "blur:{}", sigma),
+            Operation::BoxBlur { radius } => write!(f, "box-blur:{}", radius),
+            Operation::Sharpen { amount } => write!(f, "sharpen:{}", amount),
+            Operation::Dither { levels } => write!(f, "dither:{}", levels),
+            Operation::Posterize { levels } => write!(f, "posterize:{}", levels),
+            Operation::RotateLeft => write!(f, "rotate-left"),
+            Operation::RotateRight => write!(f, "rotate-right"),
+            Operation::Flip => write!(f, "flip"),
+            Operation::MirrorX => write!(f, "mirror-x"),
+            Operation::MirrorY => write!(f, "mirror-y"),
+            Operation::Transpose => write!(f, "transpose"),
+            Operation::Pixelate { block_size } => write!(f, "pixelate:{}", block_size),
+            Operation::Kaleidoscope { segments } => write!(f, "kaleidoscope:{}", segments),
+            Operation::Linear => write!(f, "linear"),
+            Operation::Srgb => write!(f, "srgb"),
+        }
+    }
+}
+
+impl Operation {
+    /// Applies the operation to the image in place
+    pub fn apply(&self, image: &mut Image) -> Result<(), Box<dyn Error>> {
+        match *self {
+            Operation::Scale { width, height } => image.scale(width, height, ScaleMethod::Bilinear),
+            Operation::Resize { width, height } => {
+                image.resize(width, height, None, None, None, true)?
+            }
+            Operation::SeamCarve { width, height } => image.seam_carve(width, height)?,
+            Operation::CenterCrop { width, height } => image.center_crop(width, height)?,
+            Operation::Crop { width, height } => {
+                image.crop(width, height, CropMethod::Rectangular, None, None)
+            }
+            Operation::CropRect {
+                x,
+                y,
+                width,
+                height,
+            } => image.crop_rect(width, height, x, y)?,
+            Operation::Square => image.center_crop_square()?,
+            Operation::Thumbnail { width, height } => image.thumbnail(width, height),
+            Operation::FitWithin { width, height } => image.fit_within(width, height, true),
+            Operation::ScaleRGB { r, g, b } => image.scale_rgb(r, g, b)?,
+            Operation::Adjust {
+                brightness,
+                contrast,
+            } => image.adjust(brightness, contrast),
+            Operation::Invert => image.invert(),
+            Operation::Gamma { gamma } => image.gamma(gamma)?,
+            Operation::HueShift { degrees } => image.hue_shift(degrees)?,
+            Operation::Vibrance { amount } => image.vibrance(amount),
+            Operation::Saturation { factor } => image.adjust_saturation(factor),
+            Operation::Grayscale => image.grayscale(None),
+            Operation::Sepia => image.sepia(),
+            Operation::Threshold { level } => image.threshold(level),
+            Operation::Edges => image.sobel(),
+            Operation::Median { radius } => image.median_filter(radius),
+            Operation::Blur { sigma } => image.fast_gaussian(sigma)?,
+            Operation::BoxBlur { radius } => image.box_blur(radius),
+            Operation::Sharpen { amount } => image.unsharp_mask(DOWNSCALE_SHARPEN_SIGMA, amount)?,
+            Operation::Dither { levels } => image.ordered_dither(levels, 4)?,
+            Operation::Posterize { levels } => image.posterize(levels)?,
+            Operation::RotateLeft => image.rotate_left(),
+            Operation::RotateRight => image.rotate_right(),
+            Operation::Flip => {
+                image.rotate_left();
+                image.rotate_left();
+            }
+            Operation::MirrorX => image.mirror_x(),
+            Operation::MirrorY => image.mirror_y(),
+            Operation::Transpose => image.transpose(),
+            Operation::Pixelate { block_size } => image.pixelate(block_size)?,
+            Operation::Kaleidoscope { segments } => image.kaleidoscope(segments)?,
+            Operation::Linear => image.to_linear(),
+            Operation::Srgb => image.to_srgb(),
+        }
+        Ok(())
+    }
+}
+
+impl Image {
+    /// Applies each operation in order to the same in-memory image. Linear light is held at 16
+    /// bits, so srgb returns the image to the depth it had at the matching linear
+    pub fn apply_pipeline(&mut self, operations: &[Operation]) -> Result<(), Box<dyn Error>> {
+        let mut encoded_max_intensity = None;
+        for operation in operations {
+            if matches!(operation, Operation::Linear) {
+                encoded_max_intensity.get_or_insert(self.max_intensity);
+            }
+            operation.apply(self)?;
+            if matches!(operation, Operation::Srgb)
+                && let Some(max_intensity) = encoded_max_intensity.take()
+            {
+                self.rescale_intensity(max_intensity);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::noise_image;
+
+    #[test]
+    fn specs_parse_back_from_their_display() {
+        for spec in [
+            "scale:640,480",
+            "resize:320,200",
+            "crop:64,48",
+            "crop-rect:4,8,64,48",
+            "scale-rgb:1.5,1,0.5",
+            "adjust:-20,1.5",
+            "blur:3",
+            "threshold:128",
+            "grayscale",
+            "rotate-left",
+        ] {
+            let operation: Operation = spec.parse().unwrap();
+            assert_eq!(operation.to_string(), spec);
+        }
+        assert_eq!(
+            "gray".parse::<Operation>().unwrap().to_string(),
+            "grayscale"
+        );
+    }
+
+    #[test]
+    fn bad_specs_are_rejected() {
+        for spec in ["scale:640", "blur:fast", "invert:1", "swirl"] {
+            assert!(spec.parse::<Operation>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn pipeline_matches_applying_each_step_in_turn() {
+        let source = noise_image(12, 9, 2017);
+        let operations: Vec<Operation> = ["blur:1.5", "grayscale"]
+            .iter()
+            .map(|spec| spec.parse().unwrap())
+            .collect();
+
+        let mut piped = source.clone();
+        piped.apply_pipeline(&operations).unwrap();
+
+        let mut sequential = source.clone();
+        sequential.fast_gaussian(1.5).unwrap();
+        sequential.grayscale(None);
+        assert_eq!(piped, sequential);
+        assert_ne!(piped, source);
+    }
+
+    #[test]
+    fn linear_spans_return_to_the_original_depth() {
+        let source = noise_image(6, 4, 2005);
+        let operations: Vec<Operation> = ["linear", "scale:6,4", "srgb"]
+            .iter()
+            .map(|spec| spec.parse().unwrap())
+            .collect();
+
+        let mut image = source.clone();
+        image.apply_pipeline(&operations).unwrap();
+        assert_eq!(image, source);
+
+        // Without a closing srgb the linear values stay at 16 bits
+        let mut open = source.clone();
+        open.apply_pipeline(&operations[..2]).unwrap();
+        assert_eq!(open.max_intensity, 65535);
+    }
+}
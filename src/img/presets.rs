@@ -0,0 +1,106 @@
+use crate::img::pipeline::Operation;
+
+use std::error::Error;
+
+/// A named pipeline of operations with sensible parameters for a common task
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    build: fn() -> Vec<Operation>,
+}
+
+impl Preset {
+    /// The operations the preset applies, in order
+    pub fn operations(&self) -> Vec<Operation> {
+        (self.build)()
+    }
+}
+
+/// Every preset available by name
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "instagram-1x1",
+        description: "Square 1080x1080 crop with a mild contrast and saturation boost",
+        build: || {
+            vec![
+                Operation::Square,
+                Operation::Scale {
+                    width: 1080,
+                    height: 1080,
+                },
+                Operation::Adjust {
+                    brightness: 0,
+                    contrast: 1.1,
+                },
+                Operation::Saturation { factor: 1.15 },
+            ]
+        },
+    },
+    Preset {
+        name: "thumbnail",
+        description: "Fits within 256x256, keeping the aspect ratio",
+        build: || {
+            vec![Operation::Thumbnail {
+                width: 256,
+                height: 256,
+            }]
+        },
+    },
+    Preset {
+        name: "print-gray",
+        description: "Grayscale with a slight contrast boost for printing",
+        build: || {
+            vec![
+                Operation::Grayscale,
+                Operation::Adjust {
+                    brightness: 0,
+                    contrast: 1.2,
+                },
+            ]
+        },
+    },
+];
+
+/// Looks up a preset by name
+pub fn find(name: &str) -> Result<&'static Preset, Box<dyn Error>> {
+    PRESETS
+        .iter()
+        .find(|preset| preset.name == name)
+        .ok_or_else(|| format!("Unknown preset '{}', run 'snap presets' to list them", name).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::noise_image;
+
+    #[test]
+    fn thumbnail_fits_within_256() {
+        for (width, height) in [(600, 300), (90, 700), (512, 512)] {
+            let mut image = noise_image(width, height, 2024);
+            image
+                .apply_pipeline(&find("thumbnail").unwrap().operations())
+                .unwrap();
+            assert!(image.width <= 256 && image.height <= 256);
+            assert!(image.width == 256 || image.height == 256);
+        }
+    }
+
+    #[test]
+    fn instagram_preset_is_square() {
+        let mut image = noise_image(40, 30, 2024);
+        image
+            .apply_pipeline(&find("instagram-1x1").unwrap().operations())
+            .unwrap();
+        assert_eq!((image.width, image.height), (1080, 1080));
+    }
+
+    #[test]
+    fn presets_are_found_by_unique_names() {
+        for (index, preset) in PRESETS.iter().enumerate() {
+            assert!(PRESETS[..index].iter().all(|p| p.name != preset.name));
+            assert_eq!(find(preset.name).unwrap().name, preset.name);
+        }
+        assert!(find("polaroid").is_err());
+    }
+}
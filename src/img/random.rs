@@ -0,0 +1,103 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Seed shared by every generator created during the run
+static SEED: OnceLock<u64> = OnceLock::new();
+
+/// Number of generators handed out so far, used to give each its own stream
+static STREAMS: AtomicU64 = AtomicU64::new(0);
+
+/// Fixes the seed for all randomness in this run. Returns false if a seed was already chosen
+pub fn set_seed(seed: u64) -> bool {
+    SEED.set(seed).is_ok()
+}
+
+/// The seed for this run, drawn from system entropy if none was set
+pub fn seed() -> u64 {
+    *SEED.get_or_init(|| RandomState::new().build_hasher().finish())
+}
+
+/// Creates a generator for one randomized operation. Generators are derived from the run seed in
+/// creation order, so the same seed and the same sequence of operations reproduce the same output
+pub fn rng() -> Rng {
+    stream_rng(seed(), STREAMS.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The generator for the given stream of a run seeded with seed
+fn stream_rng(seed: u64, stream: u64) -> Rng {
+    let mut mixer = Rng::new(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    Rng::new(mixer.next_u64())
+}
+
+/// Small, fast SplitMix64 generator. Not suitable for anything security related
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform value in [0, bound), or 0 when the bound is 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            return 0;
+        }
+        ((self.next_u64() as u128 * bound as u128) >> 64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws from the first few streams of a run the way a sequence of operations would
+    fn run(seed: u64) -> Vec<u64> {
+        (0..4)
+            .flat_map(|stream| {
+                let mut rng = stream_rng(seed, stream);
+                [rng.next_u64(), rng.below(1000) as u64]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_reproduces_the_run() {
+        assert_eq!(run(2015), run(2015));
+        assert_ne!(run(2015), run(2016));
+    }
+
+    #[test]
+    fn streams_of_one_run_differ() {
+        let first = stream_rng(2015, 0).next_u64();
+        let second = stream_rng(2015, 1).next_u64();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn draws_stay_in_range() {
+        let mut rng = Rng::new(2015);
+        for _ in 0..1000 {
+            assert!((0.0..1.0).contains(&rng.next_f64()));
+            assert!(rng.below(7) < 7);
+        }
+        assert_eq!(rng.below(0), 0);
+    }
+}
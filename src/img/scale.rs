@@ -1,93 +1,362 @@
 use crate::img::image::*;
 use crate::img::matrix::*;
+use crate::img::utils::clamp_channel;
 
 use clap::ValueEnum;
 
 /// Options available for Scaling UP an image
-#[derive(Debug, Clone, ValueEnum)]
+#[derive(Debug, Clone, Default, PartialEq, ValueEnum)]
 #[clap(rename_all = "kebab_case")]
 pub enum ScaleMethod {
     Linear,
+    #[default]
     Bilinear,
+    /// Averages every source pixel a target pixel covers, weighted by overlap. Meant for
+    /// downscaling, where sampling methods skip most of the source and alias
+    Area,
+}
+
+/// For each of the new_len target cells along an axis, the source indices it overlaps and the
+/// fraction of the cell each one covers. The fractions of every cell sum to 1
+fn area_weights(len: usize, new_len: usize) -> Vec<Vec<(usize, f64)>> {
+    let scale = len as f64 / new_len as f64;
+    (0..new_len)
+        .map(|cell| {
+            let (start, end) = (cell as f64 * scale, (cell + 1) as f64 * scale);
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(len);
+            (first..last)
+                .map(|index| {
+                    let overlap = end.min(index as f64 + 1.0) - start.max(index as f64);
+                    (index, overlap / scale)
+                })
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect()
+        })
+        .collect()
 }
 
 impl Image {
-    pub fn linear_scale(&mut self, new_width: usize, new_height: usize) {
-        let mut new_red = Matrix::new_filled(new_width, new_height, 0);
-        let mut new_green = Matrix::new_filled(new_width, new_height, 0);
-        let mut new_blue = Matrix::new_filled(new_width, new_height, 0);
-
-        for new_row in 0..new_height {
-            for new_col in 0..new_width {
-                let orig_row = new_row * self.height / new_height;
-                let orig_col = new_col * self.width / new_width;
-
-                let pixel = self.get_pixel(orig_row, orig_col).unwrap();
-                new_red[(new_row, new_col)] = pixel.r;
-                new_green[(new_row, new_col)] = pixel.g;
-                new_blue[(new_row, new_col)] = pixel.b;
+    /// Runs the resampling on color premultiplied by alpha, so fully transparent pixels carry no
+    /// color into their neighbors and leave no halo, then divides it back out. Fully transparent
+    /// results come out black. Images without alpha are resampled as they are
+    pub fn with_premultiplied_alpha<F: FnOnce(&mut Image)>(&mut self, resample: F) {
+        match self.premultiply_alpha() {
+            Some(max_intensity) => {
+                resample(self);
+                self.unpremultiply_alpha(max_intensity);
             }
+            None => resample(self),
         }
+    }
+
+    /// Multiplies each color channel by its pixel's alpha. Products are kept whole by raising the
+    /// max intensity to its square, so dividing them back out loses nothing. Returns the original
+    /// max intensity, or None when there is no alpha
+    fn premultiply_alpha(&mut self) -> Option<usize> {
+        let alpha = self.alpha_channel.as_ref()?;
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            for (value, &a) in channel.datum.iter_mut().zip(&alpha.datum) {
+                *value *= a;
+            }
+        }
+
+        let max_intensity = self.max_intensity;
+        self.max_intensity = max_intensity * max_intensity;
+        Some(max_intensity)
+    }
+
+    /// Divides premultiplied color by alpha and restores the original max intensity
+    fn unpremultiply_alpha(&mut self, max_intensity: usize) {
+        self.max_intensity = max_intensity;
+        let Some(alpha) = &self.alpha_channel else {
+            return;
+        };
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            for (value, &a) in channel.datum.iter_mut().zip(&alpha.datum) {
+                *value = match a {
+                    0 => 0,
+                    a => clamp_channel(*value as f64 / a as f64, max_intensity),
+                };
+            }
+        }
+    }
+
+    /// Downscales by whole factors, replacing each block_width x block_height block with its mean
+    pub fn block_average(&mut self, block_width: usize, block_height: usize) {
+        if block_width == 0 || block_height == 0 {
+            return;
+        }
+
+        let new_width = self.width / block_width;
+        let new_height = self.height / block_height;
+        let count = block_width * block_height;
+        let average = |channel: &Matrix<usize>| {
+            let mut averaged = Matrix::new_filled(new_width, new_height, 0);
+            for new_row in 0..new_height {
+                for new_col in 0..new_width {
+                    let mut sum = 0;
+                    for row in new_row * block_height..(new_row + 1) * block_height {
+                        for col in new_col * block_width..(new_col + 1) * block_width {
+                            sum += channel[(row, col)];
+                        }
+                    }
+                    averaged[(new_row, new_col)] = (sum + count / 2) / count;
+                }
+            }
+            averaged
+        };
 
+        for channel in self.all_channels_mut() {
+            *channel = average(channel);
+        }
         self.width = new_width;
         self.height = new_height;
-        self.red_channel = new_red;
-        self.green_channel = new_green;
-        self.blue_channel = new_blue;
     }
 
-    pub fn bilinear_scale(&mut self, new_width: usize, new_height: usize) {
-        let mut new_red = Matrix::new_filled(new_width, new_height, 0);
-        let mut new_green = Matrix::new_filled(new_width, new_height, 0);
-        let mut new_blue = Matrix::new_filled(new_width, new_height, 0);
-
-        for new_y in 0..new_height {
-            for new_x in 0..new_width {
-                // Map the target pixel (new_x, new_y) to source image space
-                let src_x = (new_x as f64) * (self.width as f64) / (new_width as f64);
-                let src_y = (new_y as f64) * (self.height as f64) / (new_height as f64);
-
-                let x0 = src_x.floor() as usize;
-                let x1 = x0.min(self.width - 1).saturating_add(1).min(self.width - 1);
-                let y0 = src_y.floor() as usize;
-                let y1 = y0
-                    .min(self.height - 1)
-                    .saturating_add(1)
-                    .min(self.height - 1);
-
-                let dx = src_x - x0 as f64;
-                let dy = src_y - y0 as f64;
-
-                let p00 = self.get_pixel(y0, x0).unwrap();
-                let p10 = self.get_pixel(y0, x1).unwrap();
-                let p01 = self.get_pixel(y1, x0).unwrap();
-                let p11 = self.get_pixel(y1, x1).unwrap();
-
-                let interpolate =
-                    |a, b, t: f64| (a as f64 * (1.0 - t) + b as f64 * t).round() as usize;
-
-                let r_top = interpolate(p00.r, p10.r, dx);
-                let r_bottom = interpolate(p01.r, p11.r, dx);
-                let r = interpolate(r_top, r_bottom, dy);
-
-                let g_top = interpolate(p00.g, p10.g, dx);
-                let g_bottom = interpolate(p01.g, p11.g, dx);
-                let g = interpolate(g_top, g_bottom, dy);
-
-                let b_top = interpolate(p00.b, p10.b, dx);
-                let b_bottom = interpolate(p01.b, p11.b, dx);
-                let b = interpolate(b_top, b_bottom, dy);
-
-                new_red[(new_y, new_x)] = r;
-                new_green[(new_y, new_x)] = g;
-                new_blue[(new_y, new_x)] = b;
+    /// Resamples with a box filter, averaging the source area behind each target pixel. Handles
+    /// fractional factors, where block_average needs the dimensions to divide evenly
+    pub fn area_scale(&mut self, new_width: usize, new_height: usize) {
+        let (width, height) = (self.width, self.height);
+        let max_intensity = self.max_intensity;
+        let col_weights = area_weights(width, new_width);
+        let row_weights = area_weights(height, new_height);
+
+        // Separable, so rows are filtered first and the narrowed result is filtered by column
+        let sample = |channel: &Matrix<usize>| {
+            let mut narrowed = vec![0.0; height * new_width];
+            for row in 0..height {
+                for (new_col, weights) in col_weights.iter().enumerate() {
+                    narrowed[row * new_width + new_col] = weights
+                        .iter()
+                        .map(|&(col, weight)| channel[(row, col)] as f64 * weight)
+                        .sum();
+                }
+            }
+
+            let mut scaled = Matrix::new_filled(new_width, new_height, 0);
+            for (new_row, weights) in row_weights.iter().enumerate() {
+                for new_col in 0..new_width {
+                    let value = weights
+                        .iter()
+                        .map(|&(row, weight)| narrowed[row * new_width + new_col] * weight)
+                        .sum();
+                    scaled[(new_row, new_col)] = clamp_channel(value, max_intensity);
+                }
+            }
+            scaled
+        };
+
+        for channel in self.all_channels_mut() {
+            *channel = sample(channel);
+        }
+        self.width = new_width;
+        self.height = new_height;
+    }
+
+    pub fn linear_scale(&mut self, new_width: usize, new_height: usize) {
+        let (width, height) = (self.width, self.height);
+        let sample = |channel: &Matrix<usize>| {
+            let mut scaled = Matrix::new_filled(new_width, new_height, 0);
+            for new_row in 0..new_height {
+                for new_col in 0..new_width {
+                    let orig_row = new_row * height / new_height;
+                    let orig_col = new_col * width / new_width;
+                    scaled[(new_row, new_col)] = channel[(orig_row, orig_col)];
+                }
             }
+            scaled
+        };
+
+        for channel in self.all_channels_mut() {
+            *channel = sample(channel);
         }
+        self.width = new_width;
+        self.height = new_height;
+    }
+
+    pub fn bilinear_scale(&mut self, new_width: usize, new_height: usize) {
+        let (width, height) = (self.width, self.height);
+        let max_intensity = self.max_intensity;
+        let interpolate =
+            |a, b, t: f64| clamp_channel(a as f64 * (1.0 - t) + b as f64 * t, max_intensity);
+
+        let sample = |channel: &Matrix<usize>| {
+            let mut scaled = Matrix::new_filled(new_width, new_height, 0);
+            for new_y in 0..new_height {
+                for new_x in 0..new_width {
+                    // Map the target pixel (new_x, new_y) to source image space
+                    let src_x = (new_x as f64) * (width as f64) / (new_width as f64);
+                    let src_y = (new_y as f64) * (height as f64) / (new_height as f64);
+
+                    let x0 = src_x.floor() as usize;
+                    let x1 = x0.min(width - 1).saturating_add(1).min(width - 1);
+                    let y0 = src_y.floor() as usize;
+                    let y1 = y0.min(height - 1).saturating_add(1).min(height - 1);
 
+                    let dx = src_x - x0 as f64;
+                    let dy = src_y - y0 as f64;
+
+                    let top = interpolate(channel[(y0, x0)], channel[(y0, x1)], dx);
+                    let bottom = interpolate(channel[(y1, x0)], channel[(y1, x1)], dx);
+                    scaled[(new_y, new_x)] = interpolate(top, bottom, dy);
+                }
+            }
+            scaled
+        };
+
+        for channel in self.all_channels_mut() {
+            *channel = sample(channel);
+        }
         self.width = new_width;
         self.height = new_height;
-        self.red_channel = new_red;
-        self.green_channel = new_green;
-        self.blue_channel = new_blue;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, solid_image};
+    use crate::img::utils::PixelRGB;
+
+    /// Opaque red on the left and fully transparent green on the right, meeting in a hard edge
+    /// that whole-factor block averaging straddles
+    fn sprite() -> Image {
+        let mut sprite = solid_image(8, 4, PixelRGB { r: 255, g: 0, b: 0 });
+        let mut alpha = Matrix::new_filled(8, 4, 255);
+        for row in 0..4 {
+            for col in 5..8 {
+                sprite.set_pixel(row, col, PixelRGB { r: 0, g: 255, b: 0 });
+                alpha[(row, col)] = 0;
+            }
+        }
+        sprite.alpha_channel = Some(alpha);
+        sprite
+    }
+
+    /// Whether any visible pixel picked up color from the transparent side. Alpha is rounded on
+    /// its own, so the red can land one step off without any green mixed in
+    fn has_halo(image: &Image) -> bool {
+        (0..image.height)
+            .flat_map(|row| (0..image.width).map(move |col| (row, col)))
+            .map(|(row, col)| image.get_pixel_rgba(row, col).unwrap())
+            .any(|pixel| pixel.a > 0 && (pixel.r < 254 || pixel.g != 0 || pixel.b != 0))
+    }
+
+    #[test]
+    fn premultiplied_scaling_leaves_no_halo() {
+        for (width, height, method) in [
+            (19, 9, ScaleMethod::Bilinear),
+            (5, 3, ScaleMethod::Area),
+            (13, 7, ScaleMethod::Area),
+            (4, 2, ScaleMethod::Bilinear),
+        ] {
+            let mut image = sprite();
+            image.scale(width, height, method.clone());
+            assert!(!has_halo(&image), "{:?} to {}x{}", method, width, height);
+            assert_eq!(image.max_intensity, 255);
+
+            // The straight alpha path blends the hidden green into the edge
+            let mut straight = sprite();
+            straight.scale_with(width, height, method, false);
+            assert!(has_halo(&straight));
+        }
+    }
+
+    #[test]
+    fn premultiplying_keeps_partly_transparent_colors() {
+        let mut image = sprite();
+        image.alpha_channel.as_mut().unwrap().fill(3);
+        image.set_pixel(
+            0,
+            0,
+            PixelRGB {
+                r: 7,
+                g: 200,
+                b: 254,
+            },
+        );
+        let original = image.clone();
+
+        image.with_premultiplied_alpha(|_| {});
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn whole_factor_downscale_gives_exact_block_means() {
+        let a = PixelRGB {
+            r: 0,
+            g: 40,
+            b: 200,
+        };
+        let b = PixelRGB {
+            r: 200,
+            g: 100,
+            b: 0,
+        };
+        for method in [
+            ScaleMethod::Linear,
+            ScaleMethod::Bilinear,
+            ScaleMethod::Area,
+        ] {
+            let mut image = solid_image(8, 8, a);
+            for row in 0..8 {
+                for col in 0..8 {
+                    if (row + col) % 2 == 1 {
+                        image.set_pixel(row, col, b);
+                    }
+                }
+            }
+
+            image.scale(2, 2, method.clone());
+            assert_eq!((image.width, image.height), (2, 2));
+            assert!(
+                image.iter_pixels().all(|(_, _, pixel)| pixel
+                    == PixelRGB {
+                        r: 100,
+                        g: 70,
+                        b: 100
+                    }),
+                "{:?}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn scale_per_axis_uses_each_axis_method() {
+        let source = noise_image(6, 9, 2027);
+        let scaled_with = |x_method: ScaleMethod, y_method: ScaleMethod| {
+            let mut image = source.clone();
+            image.scale_with(10, 9, x_method, true);
+            image.scale_with(10, 4, y_method, true);
+            image
+        };
+
+        let mut image = source.clone();
+        image.scale_per_axis(10, 4, ScaleMethod::Linear, ScaleMethod::Area, true);
+        assert_eq!((image.width, image.height), (10, 4));
+        assert_eq!(image, scaled_with(ScaleMethod::Linear, ScaleMethod::Area));
+        // Swapping either method changes the result, so each axis really used its own
+        assert_ne!(image, scaled_with(ScaleMethod::Bilinear, ScaleMethod::Area));
+        assert_ne!(
+            image,
+            scaled_with(ScaleMethod::Linear, ScaleMethod::Bilinear)
+        );
+
+        // When both axes move the same way the matching method scales them together
+        let mut both = source.clone();
+        both.scale_per_axis(12, 13, ScaleMethod::Bilinear, ScaleMethod::Area, true);
+        let mut expected = source.clone();
+        expected.scale_with(12, 13, ScaleMethod::Bilinear, true);
+        assert_eq!(both, expected);
     }
 }
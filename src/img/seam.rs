@@ -1,73 +1,230 @@
 use crate::img::image::*;
+use crate::img::io::PPMFormat;
 use crate::img::matrix::*;
+use crate::img::utils::{PixelRGB, clamp_channel};
 
-impl Image {
-    pub fn rotate_left(&mut self) {
-        if self.width == 0 || self.height == 0 {
-            return;
-        }
+use clap::ValueEnum;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
 
-        let (width, height) = (self.width, self.height);
+/// Which seams to remove when carving. Vertical seams reduce width, horizontal seams reduce height
+#[derive(Debug, Clone, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum SeamDirection {
+    Vertical,
+    Horizontal,
+    Both,
+}
 
-        let mut new_red = Matrix::new_filled(height, width, 0);
-        let mut new_green = Matrix::new_filled(height, width, 0);
-        let mut new_blue = Matrix::new_filled(height, width, 0);
+/// How the energy of border pixels, which lack a full neighborhood, is assigned
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum EnergyBorder {
+    /// Borders take the maximum interior energy so seams avoid them
+    #[default]
+    MaxEnergy,
+    /// Borders have no energy so seams remove them freely
+    ZeroEnergy,
+    /// Borders copy the energy of the nearest interior pixel
+    Replicate,
+}
 
-        for row in 0..height {
-            for col in 0..width {
-                let pixel = self.get_pixel(row, col).expect("Invalid pixel coordinate");
-                let new_row = width - 1 - col;
-                let new_col = row;
+/// How the cost of removing a pixel is measured
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum EnergyMode {
+    /// Gradient energy of the pixel before removal
+    #[default]
+    Backward,
+    /// Strength of the new edges created once the pixel is removed and its neighbors meet
+    Forward,
+}
+
+/// Which values the gradient energy is measured on
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum EnergySource {
+    /// Sum of the squared gradients of the red, green, and blue channels
+    #[default]
+    Rgb,
+    /// Squared gradient of the Rec. 601 luminance alone, so correlated channels count once
+    Luminance,
+}
 
-                new_red[(new_row, new_col)] = pixel.r;
-                new_green[(new_row, new_col)] = pixel.g;
-                new_blue[(new_row, new_col)] = pixel.b;
+/// Settings applied to every seam found during a carve. The border and source only affect
+/// backward energy
+#[derive(Debug, Clone, Default)]
+pub struct SeamOptions {
+    pub border: EnergyBorder,
+    pub mode: EnergyMode,
+    pub source: EnergySource,
+}
+
+/// Total time spent in each phase of seam carving, accumulated across every removed seam
+#[derive(Debug, Clone, Default)]
+pub struct SeamProfile {
+    pub energy: Duration,
+    pub cost: Duration,
+    pub backtrack: Duration,
+    pub removal: Duration,
+}
+
+impl SeamProfile {
+    /// Runs the phase, adding its elapsed time to the slot. Skips the timer entirely without a slot
+    fn time<T>(slot: Option<&mut Duration>, phase: impl FnOnce() -> T) -> T {
+        match slot {
+            Some(total) => {
+                let start = Instant::now();
+                let out = phase();
+                *total += start.elapsed();
+                out
             }
+            None => phase(),
         }
+    }
+}
 
-        self.red_channel = new_red;
-        self.green_channel = new_green;
-        self.blue_channel = new_blue;
-        self.height = width;
-        self.width = height;
+impl fmt::Display for SeamProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Seam carving profile:")?;
+        writeln!(f, "  energy = {:?}", self.energy)?;
+        writeln!(f, "  cost = {:?}", self.cost)?;
+        writeln!(f, "  backtrack = {:?}", self.backtrack)?;
+        writeln!(f, "  removal = {:?}", self.removal)
     }
+}
 
-    pub fn rotate_right(&mut self) {
-        if self.width == 0 || self.height == 0 {
-            return;
+/// Energy added to pixels a mask protects, far above any gradient so seams route around them
+const PROTECT_ENERGY: isize = 1 << 40;
+
+/// Energy taken from pixels a mask marks for removal, so seams are drawn through them first
+const REMOVE_ENERGY: isize = 1 << 40;
+
+/// Rec. 601 luminance computed in integer thousandths and rounded to the nearest whole value, which
+/// keeps floating point out of the per-pixel energy loop
+fn fixed_point_luma(r: usize, g: usize, b: usize) -> usize {
+    (299 * r + 587 * g + 114 * b + 500) / 1000
+}
+
+/// Number of pixels processed per step by the vectorized energy path
+#[cfg(feature = "simd")]
+const LANES: usize = 8;
+
+/// Adds (north - south)^2 + (east - west)^2 into each output, working in fixed-width lane blocks
+/// the compiler lowers to vector instructions, with a scalar loop for the remainder
+#[cfg(feature = "simd")]
+fn accumulate_squared_differences(
+    out: &mut [isize],
+    north: &[usize],
+    south: &[usize],
+    east: &[usize],
+    west: &[usize],
+) {
+    let split = out.len() / LANES * LANES;
+    let (blocks, remainder) = out.split_at_mut(split);
+
+    for (chunk, block) in blocks.chunks_exact_mut(LANES).enumerate() {
+        let start = chunk * LANES;
+        let n: &[usize; LANES] = north[start..start + LANES].try_into().unwrap();
+        let s: &[usize; LANES] = south[start..start + LANES].try_into().unwrap();
+        let e: &[usize; LANES] = east[start..start + LANES].try_into().unwrap();
+        let w: &[usize; LANES] = west[start..start + LANES].try_into().unwrap();
+
+        for lane in 0..LANES {
+            let dy = n[lane] as isize - s[lane] as isize;
+            let dx = e[lane] as isize - w[lane] as isize;
+            block[lane] += dy * dy + dx * dx;
         }
+    }
 
-        let (width, height) = (self.width, self.height);
+    for (offset, value) in remainder.iter_mut().enumerate() {
+        let index = split + offset;
+        let dy = north[index] as isize - south[index] as isize;
+        let dx = east[index] as isize - west[index] as isize;
+        *value += dy * dy + dx * dx;
+    }
+}
 
-        let mut new_red = Matrix::new_filled(height, width, 0);
-        let mut new_green = Matrix::new_filled(height, width, 0);
-        let mut new_blue = Matrix::new_filled(height, width, 0);
+impl Image {
+    /// Rotates the image 90 degrees counterclockwise
+    pub fn rotate_left(&mut self) {
+        self.rotate(false);
+    }
 
-        for row in 0..height {
-            for col in 0..width {
-                let pixel = self.get_pixel(row, col).expect("Invalid pixel coordinate");
-                let new_row = col;
-                let new_col = height - 1 - row;
+    /// Rotates the image 90 degrees clockwise
+    pub fn rotate_right(&mut self) {
+        self.rotate(true);
+    }
 
-                new_red[(new_row, new_col)] = pixel.r;
-                new_green[(new_row, new_col)] = pixel.g;
-                new_blue[(new_row, new_col)] = pixel.b;
-            }
+    /// Copy of the image rotated counterclockwise by the given number of quarter turns, leaving the
+    /// original untouched. Turns are taken mod 4, so 3 is a single clockwise turn
+    pub fn rotated(&self, quarter_turns: usize) -> Image {
+        let turns = quarter_turns % 4;
+        let turn = |channel: &Matrix<usize>| match turns {
+            1 => channel.rotate90(false),
+            2 => channel.rotate180(),
+            3 => channel.rotate90(true),
+            _ => channel.clone(),
+        };
+        let (width, height) = match turns % 2 {
+            1 => (self.height, self.width),
+            _ => (self.width, self.height),
+        };
+
+        Image {
+            width,
+            height,
+            max_intensity: self.max_intensity,
+            red_channel: turn(&self.red_channel),
+            blue_channel: turn(&self.blue_channel),
+            green_channel: turn(&self.green_channel),
+            alpha_channel: self.alpha_channel.as_ref().map(turn),
+            format: self.format.clone(),
         }
+    }
 
-        self.red_channel = new_red;
-        self.green_channel = new_green;
-        self.blue_channel = new_blue;
-        self.height = width;
-        self.width = height;
+    /// Rotates the image 90 degrees in the given direction, swapping its dimensions
+    fn rotate(&mut self, clockwise: bool) {
+        for channel in self.all_channels_mut() {
+            *channel = channel.rotate90(clockwise);
+        }
+        std::mem::swap(&mut self.width, &mut self.height);
     }
 
     pub fn energy(&self) -> Matrix<isize> {
-        let mut energy = Matrix::new_filled(self.width, self.height, 0);
+        self.energy_with(EnergyBorder::MaxEnergy, EnergySource::Rgb)
+    }
+
+    /// Rounded Rec. 601 luminance of a single pixel
+    fn luma(&self, row: usize, col: usize) -> usize {
+        let pixel = self.get_pixel(row, col).expect("Invalid pixel coordinate");
+        fixed_point_luma(pixel.r, pixel.g, pixel.b)
+    }
+
+    /// Rounded Rec. 601 luminance of every pixel in the row
+    fn luma_row(&self, row: usize) -> Vec<usize> {
+        let range = row * self.width..(row + 1) * self.width;
+        (self.red_channel.datum[range.clone()].iter())
+            .zip(&self.green_channel.datum[range.clone()])
+            .zip(&self.blue_channel.datum[range])
+            .map(|((&r, &g), &b)| fixed_point_luma(r, g, b))
+            .collect()
+    }
+
+    /// Fills the interior of the energy matrix, returning the largest value written
+    pub fn interior_energy(&self, energy: &mut Matrix<isize>, source: EnergySource) -> isize {
+        if let EnergySource::Luminance = source {
+            return self.interior_luma_energy(energy);
+        }
+
         let mut max_energy = 0;
 
-        for row in 1..self.height - 1 {
-            for col in 1..self.width - 1 {
+        // Images narrower or shorter than 3 pixels have no interior and are all border
+        for row in 1..self.height.saturating_sub(1) {
+            for col in 1..self.width.saturating_sub(1) {
                 let n = self.get_pixel(row - 1, col).unwrap();
                 let s = self.get_pixel(row + 1, col).unwrap();
                 let e = self.get_pixel(row, col + 1).unwrap();
@@ -84,16 +241,306 @@ impl Image {
             }
         }
 
-        if max_energy == 0 {
-            max_energy = 1;
+        max_energy
+    }
+
+    /// Luminance counterpart of interior_energy. Only the three rows around the current one are
+    /// converted at a time, so no full luminance matrix is ever allocated
+    fn interior_luma_energy(&self, energy: &mut Matrix<isize>) -> isize {
+        let (width, height) = (self.width, self.height);
+        if width < 3 || height < 3 {
+            return 0;
         }
-        energy.fill_border(max_energy);
 
+        let mut max_energy = 0;
+        let mut rows = [self.luma_row(0), self.luma_row(1), Vec::new()];
+        for row in 1..height - 1 {
+            rows[2] = self.luma_row(row + 1);
+            let [north, current, south] = &rows;
+            for col in 1..width - 1 {
+                let dy = north[col] as isize - south[col] as isize;
+                let dx = current[col + 1] as isize - current[col - 1] as isize;
+                let energy_val = dy * dy + dx * dx;
+                energy[(row, col)] = energy_val;
+                max_energy = max_energy.max(energy_val);
+            }
+            rows.rotate_left(1);
+        }
+
+        max_energy
+    }
+
+    /// Fills the interior of the energy matrix a fixed number of lanes at a time, returning the
+    /// largest value written. Produces exactly the same values as interior_energy
+    #[cfg(feature = "simd")]
+    pub fn interior_energy_simd(&self, energy: &mut Matrix<isize>, source: EnergySource) -> isize {
+        let (width, height) = (self.width, self.height);
+        if width < 3 || height < 3 {
+            return 0;
+        }
+
+        let inner = width - 2;
+        let mut row_energy = vec![0; inner];
+        let mut max_energy = 0;
+        // Luminance rows above, at, and below the current row, rolled forward as it advances
+        let mut luma_rows = match source {
+            EnergySource::Rgb => Default::default(),
+            EnergySource::Luminance => [self.luma_row(0), self.luma_row(1), Vec::new()],
+        };
+
+        for row in 1..height - 1 {
+            row_energy.fill(0);
+            match source {
+                EnergySource::Rgb => {
+                    for channel in [&self.red_channel, &self.green_channel, &self.blue_channel] {
+                        let data = &channel.datum;
+                        accumulate_squared_differences(
+                            &mut row_energy,
+                            &data[(row - 1) * width + 1..][..inner],
+                            &data[(row + 1) * width + 1..][..inner],
+                            &data[row * width + 2..][..inner],
+                            &data[row * width..][..inner],
+                        );
+                    }
+                }
+                EnergySource::Luminance => {
+                    luma_rows[2] = self.luma_row(row + 1);
+                    let [north, current, south] = &luma_rows;
+                    accumulate_squared_differences(
+                        &mut row_energy,
+                        &north[1..][..inner],
+                        &south[1..][..inner],
+                        &current[2..][..inner],
+                        &current[..inner],
+                    );
+                    luma_rows.rotate_left(1);
+                }
+            }
+
+            energy.datum[row * width + 1..][..inner].copy_from_slice(&row_energy);
+            max_energy = row_energy.iter().copied().fold(max_energy, isize::max);
+        }
+
+        max_energy
+    }
+
+    /// Fills the interior of the energy matrix one row per task, returning the largest value
+    /// written. Produces exactly the same values as interior_energy
+    #[cfg(feature = "parallel")]
+    pub fn interior_energy_parallel(
+        &self,
+        energy: &mut Matrix<isize>,
+        source: EnergySource,
+    ) -> isize {
+        let (width, height) = (self.width, self.height);
+        if width < 3 || height < 3 {
+            return 0;
+        }
+
+        energy.datum[width..(height - 1) * width]
+            .par_chunks_mut(width)
+            .enumerate()
+            .map(|(index, row_energy)| {
+                let mut max_energy = 0;
+                for (col, value) in row_energy.iter_mut().enumerate().take(width - 1).skip(1) {
+                    *value = self.pixel_energy(index + 1, col, source);
+                    max_energy = max_energy.max(*value);
+                }
+                max_energy
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Fills the interior of the energy matrix with the fastest path compiled in, returning the
+    /// largest value written
+    fn fill_interior_energy(&self, energy: &mut Matrix<isize>, source: EnergySource) -> isize {
+        #[cfg(feature = "parallel")]
+        return self.interior_energy_parallel(energy, source);
+        #[cfg(all(feature = "simd", not(feature = "parallel")))]
+        return self.interior_energy_simd(energy, source);
+        #[cfg(not(any(feature = "simd", feature = "parallel")))]
+        return self.interior_energy(energy, source);
+    }
+
+    /// Computes the gradient energy of each pixel from the given source, filling the border as
+    /// requested
+    pub fn energy_with(&self, border: EnergyBorder, source: EnergySource) -> Matrix<isize> {
+        let mut energy = Matrix::new_filled(self.width, self.height, 0);
+        let max_energy = self.fill_interior_energy(&mut energy, source);
+        self.fill_energy_border(&mut energy, max_energy, border);
         energy
     }
 
+    /// Energy matrix with only the interior filled in and a border of zeros, the form kept up to
+    /// date between seam removals
+    fn interior_energy_matrix(&self, source: EnergySource) -> Matrix<isize> {
+        let mut energy = Matrix::new_filled(self.width, self.height, 0);
+        self.fill_interior_energy(&mut energy, source);
+        energy
+    }
+
+    /// Completes a copy of an interior energy matrix with the requested border
+    fn energy_from_interior(
+        &self,
+        interior: &Matrix<isize>,
+        border: EnergyBorder,
+    ) -> Matrix<isize> {
+        let mut energy = interior.clone();
+        // The border is all zeros, so the overall max is the interior max
+        let max_energy = interior.max().unwrap_or(0);
+        self.fill_energy_border(&mut energy, max_energy, border);
+        energy
+    }
+
+    /// Gradient energy of a single interior pixel, matching interior_energy
+    fn pixel_energy(&self, row: usize, col: usize, source: EnergySource) -> isize {
+        match source {
+            EnergySource::Rgb => {
+                self.pixel_difference((row - 1, col), (row + 1, col))
+                    + self.pixel_difference((row, col + 1), (row, col - 1))
+            }
+            EnergySource::Luminance => {
+                let dy = self.luma(row - 1, col) as isize - self.luma(row + 1, col) as isize;
+                let dx = self.luma(row, col + 1) as isize - self.luma(row, col - 1) as isize;
+                dy * dy + dx * dx
+            }
+        }
+    }
+
+    /// Updates an interior energy matrix for a vertical seam that was just carved from the image.
+    /// Only pixels that were beside the seam, or whose row above or below shifted differently,
+    /// gain new neighbors, so only a narrow band around the seam is recomputed
+    fn refresh_energy_after_vertical_seam(
+        &self,
+        interior: &mut Matrix<isize>,
+        seam: &[usize],
+        source: EnergySource,
+    ) {
+        interior.remove_vertical_seam(seam);
+        let (width, height) = (self.width, self.height);
+        if width == 0 {
+            return;
+        }
+
+        // Columns shifted in from the interior onto the left or right edge become border
+        for row in 0..height {
+            interior[(row, 0)] = 0;
+            interior[(row, width - 1)] = 0;
+        }
+        if width < 3 || height < 3 {
+            return;
+        }
+
+        for row in 1..height - 1 {
+            let nearby = &seam[row - 1..=row + 1];
+            let first = nearby.iter().min().unwrap().saturating_sub(1).max(1);
+            let last = (*nearby.iter().max().unwrap()).min(width - 2);
+            for col in first..=last {
+                interior[(row, col)] = self.pixel_energy(row, col, source);
+            }
+        }
+    }
+
+    /// Updates an interior energy matrix for a horizontal seam that was just carved from the
+    /// image, recomputing only the band around the seam
+    fn refresh_energy_after_horizontal_seam(
+        &self,
+        interior: &mut Matrix<isize>,
+        seam: &[usize],
+        source: EnergySource,
+    ) {
+        interior.remove_horizontal_seam(seam);
+        let (width, height) = (self.width, self.height);
+        if height == 0 {
+            return;
+        }
+
+        // Rows shifted in from the interior onto the top or bottom edge become border
+        for col in 0..width {
+            interior[(0, col)] = 0;
+            interior[(height - 1, col)] = 0;
+        }
+        if width < 3 || height < 3 {
+            return;
+        }
+
+        for col in 1..width - 1 {
+            let nearby = &seam[col - 1..=col + 1];
+            let first = nearby.iter().min().unwrap().saturating_sub(1).max(1);
+            let last = (*nearby.iter().max().unwrap()).min(height - 2);
+            for row in first..=last {
+                interior[(row, col)] = self.pixel_energy(row, col, source);
+            }
+        }
+    }
+
+    /// Fills the border of an energy matrix whose interior is already computed
+    fn fill_energy_border(
+        &self,
+        energy: &mut Matrix<isize>,
+        mut max_energy: isize,
+        border: EnergyBorder,
+    ) {
+        match border {
+            EnergyBorder::MaxEnergy => {
+                if max_energy == 0 {
+                    max_energy = 1;
+                }
+                energy.fill_border(max_energy);
+            }
+            EnergyBorder::ZeroEnergy => energy.fill_border(0),
+            EnergyBorder::Replicate if self.width >= 3 && self.height >= 3 => {
+                let (width, height) = (self.width, self.height);
+                for row in 0..height {
+                    for col in 0..width {
+                        if row == 0 || col == 0 || row == height - 1 || col == width - 1 {
+                            let inner_row = row.clamp(1, height - 2);
+                            let inner_col = col.clamp(1, width - 2);
+                            energy[(row, col)] = energy[(inner_row, inner_col)];
+                        }
+                    }
+                }
+            }
+            // Without an interior there is nothing to replicate, so every pixel stays at zero
+            EnergyBorder::Replicate => {}
+        }
+    }
+
+    pub fn energy_visualization(&self) -> Image {
+        self.energy_visualization_with(EnergyBorder::MaxEnergy, EnergySource::Rgb)
+    }
+
+    /// Renders the energy map as a grayscale image, stretching the lowest energy to black and the
+    /// highest to white. A map with a single energy value renders entirely black
+    pub fn energy_visualization_with(&self, border: EnergyBorder, source: EnergySource) -> Image {
+        let energy = self.energy_with(border, source);
+        let min = energy.min().unwrap_or(0);
+        let max = energy.max().unwrap_or(0);
+        let range = (max - min).max(1) as f64;
+
+        let mut visualization = Image::new(self.width, self.height, 255, PPMFormat::P6);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let gray = clamp_channel((energy[(row, col)] - min) as f64 * 255.0 / range, 255);
+                let gray = PixelRGB {
+                    r: gray,
+                    g: gray,
+                    b: gray,
+                };
+                visualization.set_pixel(row, col, gray);
+            }
+        }
+
+        visualization
+    }
+
     pub fn vertical_cost(&self) -> Matrix<isize> {
-        let energy = self.energy();
+        self.vertical_cost_from(&self.energy())
+    }
+
+    /// Accumulates the minimal top-to-bottom seam cost through each pixel of the energy matrix
+    pub fn vertical_cost_from(&self, energy: &Matrix<isize>) -> Matrix<isize> {
         let mut cost = Matrix::new_filled(self.width, self.height, 0);
 
         for col in 0..self.width {
@@ -118,7 +565,11 @@ impl Image {
     }
 
     pub fn minimal_vertical_seam(&self) -> Vec<usize> {
-        let cost = self.vertical_cost();
+        self.vertical_seam_from(&self.vertical_cost())
+    }
+
+    /// Backtracks the cheapest vertical seam from the bottom row of a cost matrix
+    pub fn vertical_seam_from(&self, cost: &Matrix<isize>) -> Vec<usize> {
         let mut seam = vec![0; self.height];
 
         let mut current_col = cost
@@ -145,11 +596,14 @@ impl Image {
 
     pub fn remove_vertical_seam(&mut self) {
         let seam = self.minimal_vertical_seam();
-        assert_eq!(seam.len(), self.height, "Seam must have one entry per row");
+        self.carve_vertical_seam(&seam);
+    }
 
-        for row in 0..self.height {
-            let seam_col = seam[row];
+    /// Removes the given seam, one column index per row, shrinking the width by one
+    pub fn carve_vertical_seam(&mut self, seam: &[usize]) {
+        assert_eq!(seam.len(), self.height, "Seam must have one entry per row");
 
+        for (row, &seam_col) in seam.iter().enumerate() {
             assert!(
                 seam_col < self.width,
                 "Invalid seam column {} at row {}, exceeds image width {}",
@@ -157,33 +611,1200 @@ impl Image {
                 row,
                 self.width
             );
+        }
+
+        for channel in self.all_channels_mut() {
+            channel.remove_vertical_seam(seam);
+        }
+        self.width -= 1;
+    }
+
+    pub fn horizontal_cost(&self) -> Matrix<isize> {
+        self.horizontal_cost_from(&self.energy())
+    }
+
+    /// Accumulates the minimal right-to-left seam cost through each pixel of the energy matrix.
+    /// Walking from the right edge matches the traversal of carving a rotated image
+    pub fn horizontal_cost_from(&self, energy: &Matrix<isize>) -> Matrix<isize> {
+        let mut cost = Matrix::new_filled(self.width, self.height, 0);
+        let last_col = self.width - 1;
+
+        for row in 0..self.height {
+            cost[(row, last_col)] = energy[(row, last_col)];
+        }
 
-            for col in seam_col..self.width - 1 {
-                self.red_channel[(row, col)] = self.red_channel[(row, col + 1)];
-                self.green_channel[(row, col)] = self.green_channel[(row, col + 1)];
-                self.blue_channel[(row, col)] = self.blue_channel[(row, col + 1)];
+        for col in (0..last_col).rev() {
+            for row in 0..self.height {
+                let mut min_prev = cost[(row, col + 1)];
+
+                if row > 0 {
+                    min_prev = min_prev.min(cost[(row - 1, col + 1)]);
+                }
+                if row < self.height - 1 {
+                    min_prev = min_prev.min(cost[(row + 1, col + 1)]);
+                }
+
+                cost[(row, col)] = energy[(row, col)] + min_prev;
             }
         }
+        cost
+    }
 
-        self.width -= 1;
-        self.red_channel.trim_width(self.width);
-        self.green_channel.trim_width(self.width);
-        self.blue_channel.trim_width(self.width);
+    pub fn minimal_horizontal_seam(&self) -> Vec<usize> {
+        self.horizontal_seam_from(&self.horizontal_cost())
+    }
+
+    /// Backtracks the cheapest horizontal seam from the left column of a cost matrix
+    pub fn horizontal_seam_from(&self, cost: &Matrix<isize>) -> Vec<usize> {
+        let mut seam = Vec::with_capacity(self.width);
+
+        let mut current_row = cost
+            .min_in_col_range(0, 0, self.height)
+            .expect("Left column should not be empty")
+            .0;
+
+        seam.push(current_row);
+
+        for col in 1..self.width {
+            let start = current_row.saturating_sub(1);
+            let end = (current_row + 2).min(self.height);
+
+            current_row = cost
+                .min_in_col_range(col, start, end)
+                .expect("No valid rows in range")
+                .0;
+
+            seam.push(current_row);
+        }
+
+        seam
+    }
+
+    pub fn remove_horizontal_seam(&mut self) {
+        let seam = self.minimal_horizontal_seam();
+        self.carve_horizontal_seam(&seam);
+    }
+
+    /// Removes the given seam, one row index per column, shrinking the height by one
+    pub fn carve_horizontal_seam(&mut self, seam: &[usize]) {
+        assert_eq!(
+            seam.len(),
+            self.width,
+            "Seam must have one entry per column"
+        );
+
+        for (col, &seam_row) in seam.iter().enumerate() {
+            assert!(
+                seam_row < self.height,
+                "Invalid seam row {} at column {}, exceeds image height {}",
+                seam_row,
+                col,
+                self.height
+            );
+        }
+
+        for channel in self.all_channels_mut() {
+            channel.remove_horizontal_seam(seam);
+        }
+        self.height -= 1;
+    }
+
+    /// Computes the vertical seam cost matrix under the given energy mode
+    pub fn vertical_cost_with(&self, mode: EnergyMode) -> Matrix<isize> {
+        match mode {
+            EnergyMode::Backward => self.vertical_cost(),
+            EnergyMode::Forward => self.forward_vertical_cost(),
+        }
+    }
+
+    pub fn minimal_vertical_seam_with(&self, mode: EnergyMode) -> Vec<usize> {
+        self.vertical_seam_from(&self.vertical_cost_with(mode))
+    }
+
+    /// Computes the horizontal seam cost matrix under the given energy mode
+    pub fn horizontal_cost_with(&self, mode: EnergyMode) -> Matrix<isize> {
+        match mode {
+            EnergyMode::Backward => self.horizontal_cost(),
+            EnergyMode::Forward => self.forward_horizontal_cost(),
+        }
+    }
+
+    pub fn minimal_horizontal_seam_with(&self, mode: EnergyMode) -> Vec<usize> {
+        self.horizontal_seam_from(&self.horizontal_cost_with(mode))
+    }
+
+    /// Squared color distance between the pixels at two (row, col) coordinates
+    fn pixel_difference(&self, a: (usize, usize), b: (usize, usize)) -> isize {
+        let a = self.get_pixel(a.0, a.1).expect("Invalid pixel coordinate");
+        let b = self.get_pixel(b.0, b.1).expect("Invalid pixel coordinate");
+        a.squared_difference(&b)
+    }
+
+    /// Accumulates top-to-bottom seam cost from the edges each removal would introduce
+    pub fn forward_vertical_cost(&self) -> Matrix<isize> {
+        let mut cost = Matrix::new_filled(self.width, self.height, 0);
+        let last_col = self.width - 1;
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let left = col.saturating_sub(1);
+                let right = (col + 1).min(last_col);
+
+                // Removing the pixel always joins its left and right neighbors
+                let joined = self.pixel_difference((row, left), (row, right));
+                if row == 0 {
+                    cost[(row, col)] = joined;
+                    continue;
+                }
+
+                // Stepping diagonally also joins the pixel above with a new horizontal neighbor
+                let above = (row - 1, col);
+                let mut min_cost = cost[(row - 1, col)] + joined;
+                if col > 0 {
+                    let new_edge = self.pixel_difference(above, (row, left));
+                    min_cost = min_cost.min(cost[(row - 1, col - 1)] + joined + new_edge);
+                }
+                if col < last_col {
+                    let new_edge = self.pixel_difference(above, (row, right));
+                    min_cost = min_cost.min(cost[(row - 1, col + 1)] + joined + new_edge);
+                }
+
+                cost[(row, col)] = min_cost;
+            }
+        }
+        cost
+    }
+
+    /// Accumulates right-to-left seam cost from the edges each removal would introduce
+    pub fn forward_horizontal_cost(&self) -> Matrix<isize> {
+        let mut cost = Matrix::new_filled(self.width, self.height, 0);
+        let last_row = self.height - 1;
+        let last_col = self.width - 1;
+
+        for col in (0..self.width).rev() {
+            for row in 0..self.height {
+                let up = row.saturating_sub(1);
+                let down = (row + 1).min(last_row);
+
+                // Removing the pixel always joins its upper and lower neighbors
+                let joined = self.pixel_difference((up, col), (down, col));
+                if col == last_col {
+                    cost[(row, col)] = joined;
+                    continue;
+                }
+
+                // Stepping diagonally also joins the pixel beside with a new vertical neighbor
+                let beside = (row, col + 1);
+                let mut min_cost = cost[(row, col + 1)] + joined;
+                if row > 0 {
+                    let new_edge = self.pixel_difference(beside, (up, col));
+                    min_cost = min_cost.min(cost[(row - 1, col + 1)] + joined + new_edge);
+                }
+                if row < last_row {
+                    let new_edge = self.pixel_difference(beside, (down, col));
+                    min_cost = min_cost.min(cost[(row + 1, col + 1)] + joined + new_edge);
+                }
+
+                cost[(row, col)] = min_cost;
+            }
+        }
+        cost
+    }
+
+    /// Runs the energy, cost, and backtrack phases to find a single seam. Under backward energy, a
+    /// given interior energy matrix is used in place of recomputing the energy of every pixel
+    fn find_seam(
+        &self,
+        vertical: bool,
+        options: &SeamOptions,
+        interior: Option<&Matrix<isize>>,
+        mut profile: Option<&mut SeamProfile>,
+    ) -> Vec<usize> {
+        let cost = match options.mode {
+            EnergyMode::Backward => {
+                let energy =
+                    SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.energy), || {
+                        match interior {
+                            Some(interior) => self.energy_from_interior(interior, options.border),
+                            None => self.energy_with(options.border, options.source),
+                        }
+                    });
+                SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.cost), || {
+                    if vertical {
+                        self.vertical_cost_from(&energy)
+                    } else {
+                        self.horizontal_cost_from(&energy)
+                    }
+                })
+            }
+            EnergyMode::Forward => {
+                SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.cost), || {
+                    if vertical {
+                        self.forward_vertical_cost()
+                    } else {
+                        self.forward_horizontal_cost()
+                    }
+                })
+            }
+        };
+
+        SeamProfile::time(profile.map(|p| &mut p.backtrack), || {
+            if vertical {
+                self.vertical_seam_from(&cost)
+            } else {
+                self.horizontal_seam_from(&cost)
+            }
+        })
+    }
+
+    /// Interior energy to carry between seam removals, which only backward energy can reuse
+    fn cached_interior_energy(&self, options: &SeamOptions) -> Option<Matrix<isize>> {
+        match options.mode {
+            EnergyMode::Backward => Some(self.interior_energy_matrix(options.source)),
+            EnergyMode::Forward => None,
+        }
+    }
+
+    /// Finds the given number of non-overlapping vertical seams, in original column coordinates,
+    /// by repeatedly carving a scratch copy of the image
+    pub fn lowest_vertical_seams(&self, count: usize, options: &SeamOptions) -> Vec<Vec<usize>> {
+        let mut carved = self.clone();
+        let mut columns: Vec<Vec<usize>> = vec![(0..self.width).collect(); self.height];
+        let mut seams = Vec::with_capacity(count);
+        let mut interior = carved.cached_interior_energy(options);
+
+        for _ in 0..count.min(self.width) {
+            let seam = carved.find_seam(true, options, interior.as_ref(), None);
+            seams.push(
+                seam.iter()
+                    .enumerate()
+                    .map(|(row, &col)| columns[row].remove(col))
+                    .collect(),
+            );
+            carved.carve_vertical_seam(&seam);
+            if let Some(interior) = interior.as_mut() {
+                carved.refresh_energy_after_vertical_seam(interior, &seam, options.source);
+            }
+        }
+
+        seams
+    }
+
+    /// Finds the given number of non-overlapping horizontal seams, in original row coordinates,
+    /// by repeatedly carving a scratch copy of the image
+    pub fn lowest_horizontal_seams(&self, count: usize, options: &SeamOptions) -> Vec<Vec<usize>> {
+        let mut carved = self.clone();
+        let mut rows: Vec<Vec<usize>> = vec![(0..self.height).collect(); self.width];
+        let mut seams = Vec::with_capacity(count);
+        let mut interior = carved.cached_interior_energy(options);
+
+        for _ in 0..count.min(self.height) {
+            let seam = carved.find_seam(false, options, interior.as_ref(), None);
+            seams.push(
+                seam.iter()
+                    .enumerate()
+                    .map(|(col, &row)| rows[col].remove(row))
+                    .collect(),
+            );
+            carved.carve_horizontal_seam(&seam);
+            if let Some(interior) = interior.as_mut() {
+                carved.refresh_energy_after_horizontal_seam(interior, &seam, options.source);
+            }
+        }
+
+        seams
+    }
+
+    /// Inserts a pixel to the right of each seam entry, averaging its left and right neighbors
+    pub fn insert_vertical_seam(&mut self, seam: &[usize]) {
+        assert_eq!(seam.len(), self.height, "Seam must have one entry per row");
+
+        let (width, height) = (self.width, self.height);
+        for channel in self.all_channels_mut() {
+            let mut datum = Vec::with_capacity((width + 1) * height);
+            for (row, &seam_col) in seam.iter().enumerate() {
+                let row_slice = &channel.datum[row * width..(row + 1) * width];
+                let left = row_slice[seam_col];
+                let right = row_slice[(seam_col + 1).min(width - 1)];
+
+                datum.extend_from_slice(&row_slice[..=seam_col]);
+                datum.push((left + right).div_ceil(2));
+                datum.extend_from_slice(&row_slice[seam_col + 1..]);
+            }
+            *channel = Matrix::from_vec(width + 1, height, datum).expect("Invalid seam insertion");
+        }
+
+        self.width += 1;
+    }
+
+    /// Inserts a pixel below each seam entry, averaging its upper and lower neighbors
+    pub fn insert_horizontal_seam(&mut self, seam: &[usize]) {
+        assert_eq!(
+            seam.len(),
+            self.width,
+            "Seam must have one entry per column"
+        );
+
+        let (width, height) = (self.width, self.height);
+        for channel in self.all_channels_mut() {
+            let mut grown = Matrix::new_filled(width, height + 1, 0);
+            for (col, &seam_row) in seam.iter().enumerate() {
+                for row in 0..=height {
+                    grown[(row, col)] = if row <= seam_row {
+                        channel[(row, col)]
+                    } else if row == seam_row + 1 {
+                        let above = channel[(seam_row, col)];
+                        let below = channel[((seam_row + 1).min(height - 1), col)];
+                        (above + below).div_ceil(2)
+                    } else {
+                        channel[(row - 1, col)]
+                    };
+                }
+            }
+            *channel = grown;
+        }
+
+        self.height += 1;
+    }
+
+    /// Grows the width by duplicating the lowest energy seams. At most half the current width is
+    /// added per round so the duplicated seams stay spread out
+    pub fn seam_enlarge_width(&mut self, new_width: usize, options: &SeamOptions) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        while self.width < new_width {
+            let count = (new_width - self.width).min((self.width / 2).max(1));
+            let mut seams = self.lowest_vertical_seams(count, options);
+
+            for index in 0..seams.len() {
+                let (inserted, remaining) = seams.split_at_mut(index + 1);
+                let seam = &inserted[index];
+                self.insert_vertical_seam(seam);
+
+                // Every later seam right of this one shifts over by the inserted pixel
+                for later in remaining.iter_mut() {
+                    for (col, &seam_col) in later.iter_mut().zip(seam) {
+                        if *col > seam_col {
+                            *col += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Grows the height by duplicating the lowest energy seams. At most half the current height
+    /// is added per round so the duplicated seams stay spread out
+    pub fn seam_enlarge_height(&mut self, new_height: usize, options: &SeamOptions) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        while self.height < new_height {
+            let count = (new_height - self.height).min((self.height / 2).max(1));
+            let mut seams = self.lowest_horizontal_seams(count, options);
+
+            for index in 0..seams.len() {
+                let (inserted, remaining) = seams.split_at_mut(index + 1);
+                let seam = &inserted[index];
+                self.insert_horizontal_seam(seam);
+
+                // Every later seam below this one shifts down by the inserted pixel
+                for later in remaining.iter_mut() {
+                    for (row, &seam_row) in later.iter_mut().zip(seam) {
+                        if *row > seam_row {
+                            *row += 1;
+                        }
+                    }
+                }
+            }
+        }
     }
 
     pub fn seam_carve_width(&mut self, new_width: usize) {
+        self.seam_carve_width_with(new_width, &SeamOptions::default(), None);
+    }
+
+    /// Carves the width to the new value, accumulating the time spent per removal phase when a
+    /// profile is given. Targets wider than the image are reached through seam insertion
+    pub fn seam_carve_width_with(
+        &mut self,
+        new_width: usize,
+        options: &SeamOptions,
+        mut profile: Option<&mut SeamProfile>,
+    ) {
         if self.width == new_width {
             return;
         }
+        if new_width > self.width {
+            self.seam_enlarge_width(new_width, options);
+            return;
+        }
+        // A single column or an empty image has no seam worth removing
+        if self.width < 2 || self.height == 0 {
+            return;
+        }
 
+        let mut interior = SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.energy), || {
+            self.cached_interior_energy(options)
+        });
         for _ in 0..(self.width.saturating_sub(new_width)) {
-            self.remove_vertical_seam();
+            let seam = self.find_seam(true, options, interior.as_ref(), profile.as_deref_mut());
+            SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.removal), || {
+                self.carve_vertical_seam(&seam)
+            });
+            if let Some(interior) = interior.as_mut() {
+                SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.energy), || {
+                    self.refresh_energy_after_vertical_seam(interior, &seam, options.source)
+                });
+            }
         }
     }
 
     pub fn seam_carve_height(&mut self, new_height: usize) {
-        self.rotate_left();
-        self.seam_carve_width(new_height);
-        self.rotate_right();
+        self.seam_carve_height_with(new_height, &SeamOptions::default(), None);
+    }
+
+    /// Carves the height to the new value, accumulating the time spent per removal phase when a
+    /// profile is given. Targets taller than the image are reached through seam insertion
+    pub fn seam_carve_height_with(
+        &mut self,
+        new_height: usize,
+        options: &SeamOptions,
+        mut profile: Option<&mut SeamProfile>,
+    ) {
+        if self.height == new_height {
+            return;
+        }
+        if new_height > self.height {
+            self.seam_enlarge_height(new_height, options);
+            return;
+        }
+        // A single row or an empty image has no seam worth removing
+        if self.height < 2 || self.width == 0 {
+            return;
+        }
+
+        let mut interior = SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.energy), || {
+            self.cached_interior_energy(options)
+        });
+        for _ in 0..(self.height.saturating_sub(new_height)) {
+            let seam = self.find_seam(false, options, interior.as_ref(), profile.as_deref_mut());
+            SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.removal), || {
+                self.carve_horizontal_seam(&seam)
+            });
+            if let Some(interior) = interior.as_mut() {
+                SeamProfile::time(profile.as_deref_mut().map(|p| &mut p.energy), || {
+                    self.refresh_energy_after_horizontal_seam(interior, &seam, options.source)
+                });
+            }
+        }
+    }
+
+    /// Carves the image down to the new width and height under backward energy, never cutting
+    /// through protected pixels while another seam exists and cutting removal pixels first. Both
+    /// masks must match the image's dimensions
+    pub fn seam_carve_masked(
+        &mut self,
+        new_width: usize,
+        new_height: usize,
+        protect: &Matrix<bool>,
+        remove: &Matrix<bool>,
+    ) -> Result<(), Box<dyn Error>> {
+        for mask in [protect, remove] {
+            if (mask.width, mask.height) != (self.width, self.height) {
+                return Err("Mask dimensions must match the image".into());
+            }
+        }
+        if new_width == 0 || new_height == 0 {
+            return Err("Seam carving targets must be nonzero".into());
+        }
+        if new_width > self.width || new_height > self.height {
+            return Err(format!(
+                "Masked seam carving only removes seams, {}x{} exceeds the {}x{} source",
+                new_width, new_height, self.width, self.height
+            )
+            .into());
+        }
+
+        let mut bias = Matrix::new_filled(self.width, self.height, 0);
+        for (index, value) in bias.datum.iter_mut().enumerate() {
+            if protect.datum[index] {
+                *value += PROTECT_ENERGY;
+            }
+            if remove.datum[index] {
+                *value -= REMOVE_ENERGY;
+            }
+        }
+
+        let options = SeamOptions::default();
+        let mut interior = self.interior_energy_matrix(options.source);
+        while self.width > new_width {
+            let mut energy = self.energy_from_interior(&interior, options.border);
+            add_bias(&mut energy, &bias);
+            let seam = self.vertical_seam_from(&self.vertical_cost_from(&energy));
+            self.carve_vertical_seam(&seam);
+            self.refresh_energy_after_vertical_seam(&mut interior, &seam, options.source);
+            bias.remove_vertical_seam(&seam);
+        }
+        while self.height > new_height {
+            let mut energy = self.energy_from_interior(&interior, options.border);
+            add_bias(&mut energy, &bias);
+            let seam = self.horizontal_seam_from(&self.horizontal_cost_from(&energy));
+            self.carve_horizontal_seam(&seam);
+            self.refresh_energy_after_horizontal_seam(&mut interior, &seam, options.source);
+            bias.remove_horizontal_seam(&seam);
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds the mask bias to each pixel's energy
+fn add_bias(energy: &mut Matrix<isize>, bias: &Matrix<isize>) {
+    for (value, offset) in energy.datum.iter_mut().zip(&bias.datum) {
+        *value += offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::{noise_image, solid_image};
+
+    #[test]
+    fn four_quarter_turns_return_the_original() {
+        let original = noise_image(7, 4, 1988);
+        for clockwise in [true, false] {
+            let mut image = original.clone();
+            for _ in 0..4 {
+                image.rotate(clockwise);
+            }
+            assert_eq!(image, original);
+        }
+        assert_eq!(original.rotated(4), original);
+    }
+
+    #[test]
+    fn opposite_turns_cancel() {
+        let original = noise_image(5, 3, 1988);
+        let mut image = original.clone();
+        image.rotate_right();
+        assert_eq!((image.width, image.height), (3, 5));
+        image.rotate_left();
+        assert_eq!(image, original);
+    }
+
+    #[test]
+    fn carving_one_direction_leaves_the_other_alone() {
+        let mut image = noise_image(12, 6, 1998);
+        let options = SeamOptions::default();
+        image
+            .seam_carve_directed(8, 1, SeamDirection::Vertical, &options, None)
+            .unwrap();
+        assert_eq!((image.width, image.height), (8, 6));
+
+        image
+            .seam_carve_directed(1, 4, SeamDirection::Horizontal, &options, None)
+            .unwrap();
+        assert_eq!((image.width, image.height), (8, 4));
+    }
+
+    /// Vertical seam chosen under the border mode, along with its total energy
+    fn seam_under(image: &Image, border: EnergyBorder) -> (Vec<usize>, isize) {
+        let energy = image.energy_with(border, EnergySource::Rgb);
+        let seam = image.vertical_seam_from(&image.vertical_cost_from(&energy));
+        let total = seam
+            .iter()
+            .enumerate()
+            .map(|(row, &col)| energy[(row, col)])
+            .sum();
+        (seam, total)
+    }
+
+    #[test]
+    fn border_modes_steer_seams_differently() {
+        let image = noise_image(9, 6, 1999);
+        let last = image.width - 1;
+
+        let (seam, _) = seam_under(&image, EnergyBorder::MaxEnergy);
+        assert!(seam.iter().all(|&col| col != 0 && col != last));
+
+        let (seam, total) = seam_under(&image, EnergyBorder::ZeroEnergy);
+        assert_eq!(total, 0);
+        assert!(seam.iter().all(|&col| col == 0) || seam.iter().all(|&col| col == last));
+
+        // A flat strip on the left gives column 1 no energy, which replicates onto column 0
+        let mut image = image;
+        for row in 0..image.height {
+            for col in 0..3 {
+                image.set_pixel(
+                    row,
+                    col,
+                    PixelRGB {
+                        r: 90,
+                        g: 90,
+                        b: 90,
+                    },
+                );
+            }
+        }
+        let (seam, total) = seam_under(&image, EnergyBorder::Replicate);
+        assert_eq!(total, 0);
+        assert!(seam.iter().all(|&col| col <= 1));
+
+        let (seam, _) = seam_under(&image, EnergyBorder::MaxEnergy);
+        assert!(seam[1..image.height - 1].iter().all(|&col| col == 1));
+    }
+
+    #[test]
+    fn profile_lists_every_phase() {
+        let mut image = noise_image(10, 8, 2000);
+        let mut profile = SeamProfile::default();
+        image
+            .seam_carve_directed(
+                7,
+                6,
+                SeamDirection::Both,
+                &SeamOptions::default(),
+                Some(&mut profile),
+            )
+            .unwrap();
+
+        let report = profile.to_string();
+        for phase in ["energy", "cost", "backtrack", "removal"] {
+            assert!(report.contains(&format!("  {} = ", phase)), "{}", report);
+        }
+        assert!(profile.cost > Duration::ZERO);
+    }
+
+    /// Reference height carve that rotates the image so horizontal seams become vertical ones
+    fn carve_height_by_rotation(image: &Image, new_height: usize, options: &SeamOptions) -> Image {
+        let mut rotated = image.clone();
+        rotated.rotate_left();
+        rotated.seam_carve_width_with(new_height, options, None);
+        rotated.rotate_right();
+        rotated
+    }
+
+    #[test]
+    fn direct_height_carve_matches_the_rotated_path() {
+        // Coarse levels leave plenty of tied costs, so tie breaking has to agree as well
+        let mut coarse = noise_image(11, 9, 2001);
+        for channel in coarse.all_channels_mut() {
+            channel.apply_in_place(|value| *value = *value / 128 * 255);
+        }
+
+        for image in [noise_image(13, 10, 2001), coarse] {
+            for mode in [EnergyMode::Backward, EnergyMode::Forward] {
+                let options = SeamOptions {
+                    mode,
+                    ..SeamOptions::default()
+                };
+                let mut direct = image.clone();
+                direct.seam_carve_height_with(image.height - 4, &options, None);
+                assert_eq!(
+                    direct,
+                    carve_height_by_rotation(&image, image.height - 4, &options)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn enlarging_a_gradient_by_half_stays_in_range() {
+        let mut image = Image::new(12, 6, 255, PPMFormat::P6);
+        for row in 0..image.height {
+            for col in 0..image.width {
+                let value = col * 255 / (image.width - 1);
+                image.set_pixel(
+                    row,
+                    col,
+                    PixelRGB {
+                        r: value,
+                        g: row * 40,
+                        b: 255 - value,
+                    },
+                );
+            }
+        }
+        let max_red = image.red_channel.max().unwrap();
+
+        image.seam_carve_width(18);
+        assert_eq!((image.width, image.height), (18, 6));
+        for (_, _, pixel) in image.iter_pixels() {
+            assert!(
+                [pixel.r, pixel.g, pixel.b]
+                    .iter()
+                    .all(|&v| v <= image.max_intensity)
+            );
+        }
+        // Inserted pixels average their neighbors, so each row stays a monotone gradient
+        for row in 0..image.height {
+            for col in 1..image.width {
+                assert!(image.red_channel[(row, col)] >= image.red_channel[(row, col - 1)]);
+            }
+        }
+        assert_eq!(image.red_channel.max(), Some(max_red));
+    }
+
+    /// Sum of the squared differences between every pair of horizontally or vertically adjacent
+    /// pixels, which grows with each new edge a seam removal introduces
+    fn total_edge_cost(image: &Image) -> isize {
+        let mut total = 0;
+        for row in 0..image.height {
+            for col in 0..image.width {
+                if col + 1 < image.width {
+                    total += image.pixel_difference((row, col), (row, col + 1));
+                }
+                if row + 1 < image.height {
+                    total += image.pixel_difference((row, col), (row + 1, col));
+                }
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn forward_energy_introduces_less_edge_cost_on_a_diagonal() {
+        let mut image = Image::new(24, 16, 255, PPMFormat::P6);
+        for row in 0..image.height {
+            for col in 0..image.width {
+                let value = if col > row + 4 { 230 } else { 20 };
+                image.set_pixel(
+                    row,
+                    col,
+                    PixelRGB {
+                        r: value,
+                        g: value,
+                        b: value,
+                    },
+                );
+            }
+        }
+
+        let carve = |mode: EnergyMode| {
+            let mut carved = image.clone();
+            let options = SeamOptions {
+                mode,
+                ..SeamOptions::default()
+            };
+            carved.seam_carve_width_with(16, &options, None);
+            total_edge_cost(&carved)
+        };
+        let (backward, forward) = (carve(EnergyMode::Backward), carve(EnergyMode::Forward));
+        assert!(
+            forward < backward,
+            "forward {} backward {}",
+            forward,
+            backward
+        );
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_energy_matches_scalar_energy() {
+        // Interior widths of 1 to 21 cover lane blocks with and without a scalar remainder
+        for width in (3..=23).step_by(2) {
+            let image = noise_image(width, 5, 2004 + width as u64);
+            for source in [EnergySource::Rgb, EnergySource::Luminance] {
+                let mut scalar = Matrix::new_filled(width, 5, 0);
+                let mut simd = Matrix::new_filled(width, 5, 0);
+                let scalar_max = image.interior_energy(&mut scalar, source);
+                let simd_max = image.interior_energy_simd(&mut simd, source);
+                assert_eq!(simd, scalar, "width {} source {:?}", width, source);
+                assert_eq!(simd_max, scalar_max);
+
+                for border in [
+                    EnergyBorder::MaxEnergy,
+                    EnergyBorder::ZeroEnergy,
+                    EnergyBorder::Replicate,
+                ] {
+                    let mut scalar = scalar.clone();
+                    let mut simd = simd.clone();
+                    image.fill_energy_border(&mut scalar, scalar_max, border);
+                    image.fill_energy_border(&mut simd, simd_max, border);
+                    assert_eq!(simd, scalar);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_images_have_all_border_energy() {
+        for (width, height) in [(1, 1), (2, 2), (1, 7), (7, 1), (2, 5)] {
+            let image = noise_image(width, height, 2004);
+            let energy = image.energy();
+            assert_eq!((energy.width, energy.height), (width, height));
+            // Without an interior every pixel is border, and an all-zero max is raised to 1
+            assert!(energy.datum.iter().all(|&value| value == 1));
+
+            for border in [EnergyBorder::ZeroEnergy, EnergyBorder::Replicate] {
+                let energy = image.energy_with(border, EnergySource::Rgb);
+                assert!(energy.datum.iter().all(|&value| value == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_images_carve_without_panicking() {
+        let mut single = noise_image(1, 1, 2004);
+        single.seam_carve_width(1);
+        single.seam_carve_height(1);
+        assert_eq!((single.width, single.height), (1, 1));
+
+        let mut square = noise_image(2, 2, 2004);
+        assert_eq!(square.minimal_vertical_seam().len(), 2);
+        square.seam_carve_width(1);
+        square.seam_carve_height(1);
+        assert_eq!((square.width, square.height), (1, 1));
+
+        // A single column has no seam to remove, but its height can still be carved
+        let mut column = noise_image(1, 9, 2004);
+        column.seam_carve_width(0);
+        assert_eq!((column.width, column.height), (1, 9));
+        column.seam_carve_height(4);
+        assert_eq!((column.width, column.height), (1, 4));
+
+        let mut row = noise_image(9, 1, 2004);
+        row.seam_carve_width(5);
+        assert_eq!((row.width, row.height), (5, 1));
+    }
+
+    #[test]
+    fn energy_visualization_renders_the_border_brightest() {
+        let visualization = noise_image(12, 9, 2012).energy_visualization();
+        assert_eq!((visualization.width, visualization.height), (12, 9));
+
+        let white = PixelRGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        for (row, col, pixel) in visualization.iter_pixels() {
+            assert_eq!(pixel.r, pixel.g);
+            assert_eq!(pixel.r, pixel.b);
+            if row == 0 || col == 0 || row == 8 || col == 11 {
+                assert_eq!(pixel, white, "border pixel ({}, {})", row, col);
+            }
+        }
+        assert!(
+            visualization
+                .iter_pixels()
+                .any(|(_, _, pixel)| pixel.r == 0)
+        );
+    }
+
+    #[test]
+    fn energy_visualization_of_uniform_energy_is_black() {
+        let flat = solid_image(
+            6,
+            5,
+            PixelRGB {
+                r: 90,
+                g: 90,
+                b: 90,
+            },
+        );
+        let visualization =
+            flat.energy_visualization_with(EnergyBorder::ZeroEnergy, EnergySource::Rgb);
+        assert!(
+            visualization
+                .iter_pixels()
+                .all(|(_, _, pixel)| pixel.r == 0)
+        );
+    }
+
+    #[test]
+    fn impossible_seam_carve_targets_suggest_scale() {
+        let mut column = noise_image(1, 6, 2020);
+        let error = column.seam_carve(4, 6).unwrap_err().to_string();
+        assert!(error.contains("use scale instead"), "{}", error);
+        assert_eq!(column, noise_image(1, 6, 2020));
+
+        let mut row = noise_image(6, 1, 2020);
+        let error = row.seam_carve(6, 3).unwrap_err().to_string();
+        assert!(error.contains("use scale instead"), "{}", error);
+
+        assert!(noise_image(6, 6, 2020).seam_carve(0, 6).is_err());
+        assert!(noise_image(0, 6, 2020).seam_carve(1, 6).is_err());
+
+        // With seam insertion an oversized target on a wide enough image is fine
+        let mut image = noise_image(6, 6, 2020);
+        image.seam_carve(9, 8).unwrap();
+        assert_eq!((image.width, image.height), (9, 8));
+    }
+
+    /// A patch of the bundled horses photo, small enough to carve quickly in a debug build
+    fn horses_patch() -> Image {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/horses.ppm");
+        let mut image = Image::from_file(path).unwrap();
+        image.crop_rect(48, 36, 200, 150).unwrap();
+        image
+    }
+
+    #[test]
+    fn incremental_energy_matches_full_recomputation() {
+        const STEPS: usize = 12;
+        let sources = [EnergySource::Rgb, EnergySource::Luminance];
+        let borders = [
+            EnergyBorder::MaxEnergy,
+            EnergyBorder::ZeroEnergy,
+            EnergyBorder::Replicate,
+        ];
+
+        for source in [horses_patch(), noise_image(23, 17, 2024)] {
+            for (energy_source, border, vertical) in sources
+                .iter()
+                .flat_map(|&s| borders.iter().map(move |&b| (s, b)))
+                .flat_map(|(s, b)| [(s, b, true), (s, b, false)])
+            {
+                let options = SeamOptions {
+                    border,
+                    mode: EnergyMode::Backward,
+                    source: energy_source,
+                };
+                let context = format!("{:?} {:?} vertical {}", energy_source, border, vertical);
+                let (mut incremental, mut naive) = (source.clone(), source.clone());
+                let mut interior = incremental.interior_energy_matrix(energy_source);
+
+                for step in 0..STEPS {
+                    assert_eq!(
+                        incremental.energy_from_interior(&interior, border),
+                        incremental.energy_with(border, energy_source),
+                        "{} step {}",
+                        context,
+                        step
+                    );
+
+                    let seam = incremental.find_seam(vertical, &options, Some(&interior), None);
+                    let naive_seam = naive.find_seam(vertical, &options, None, None);
+                    assert_eq!(seam, naive_seam, "{} step {}", context, step);
+
+                    if vertical {
+                        incremental.carve_vertical_seam(&seam);
+                        incremental.refresh_energy_after_vertical_seam(
+                            &mut interior,
+                            &seam,
+                            energy_source,
+                        );
+                        naive.carve_vertical_seam(&naive_seam);
+                    } else {
+                        incremental.carve_horizontal_seam(&seam);
+                        incremental.refresh_energy_after_horizontal_seam(
+                            &mut interior,
+                            &seam,
+                            energy_source,
+                        );
+                        naive.carve_horizontal_seam(&naive_seam);
+                    }
+                }
+                assert_eq!(incremental, naive, "{}", context);
+            }
+        }
+    }
+
+    #[test]
+    fn carving_with_cached_energy_matches_naive_removal() {
+        let source = horses_patch();
+        let mut carved = source.clone();
+        carved.seam_carve_width(36);
+        carved.seam_carve_height(30);
+
+        let mut naive = source;
+        let options = SeamOptions::default();
+        while naive.width > 36 {
+            let seam = naive.find_seam(true, &options, None, None);
+            naive.carve_vertical_seam(&seam);
+        }
+        while naive.height > 30 {
+            let seam = naive.find_seam(false, &options, None, None);
+            naive.carve_horizontal_seam(&seam);
+        }
+        assert_eq!(carved, naive);
+    }
+
+    #[test]
+    fn protected_stripe_survives_heavy_carving() {
+        let stripe = PixelRGB {
+            r: 255,
+            g: 0,
+            b: 255,
+        };
+        let mut image = noise_image(30, 10, 2025);
+        let mut protect = Matrix::new_filled(30, 10, false);
+        for row in 0..10 {
+            for col in 12..15 {
+                // A flat stripe is the cheapest path, so only the mask keeps seams out of it
+                image.set_pixel(row, col, stripe);
+                protect[(row, col)] = true;
+            }
+        }
+        let remove = Matrix::new_filled(30, 10, false);
+
+        image.seam_carve_masked(8, 10, &protect, &remove).unwrap();
+        assert_eq!(image.width, 8);
+        for row in 0..10 {
+            let cols: Vec<usize> = (0..8)
+                .filter(|&col| image.get_pixel(row, col) == Some(stripe))
+                .collect();
+            assert_eq!(cols.len(), 3, "row {}", row);
+            assert_eq!(cols[2] - cols[0], 2, "row {}", row);
+        }
+    }
+
+    #[test]
+    fn removal_mask_is_carved_first() {
+        let (black, white) = (
+            PixelRGB { r: 0, g: 0, b: 0 },
+            PixelRGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+        let mut image = noise_image(20, 8, 2025);
+        let mut remove = Matrix::new_filled(20, 8, false);
+        for row in 0..8 {
+            for col in 5..8 {
+                // High contrast that seams would otherwise avoid
+                image.set_pixel(row, col, if (row + col) % 2 == 0 { black } else { white });
+                remove[(row, col)] = true;
+            }
+        }
+        let protect = Matrix::new_filled(20, 8, false);
+
+        image.seam_carve_masked(17, 8, &protect, &remove).unwrap();
+        for (row, col, pixel) in image.iter_pixels() {
+            assert!(pixel != black && pixel != white, "({}, {})", row, col);
+        }
+    }
+
+    #[test]
+    fn masks_must_match_the_image() {
+        let mut image = noise_image(6, 6, 2025);
+        let small = Matrix::new_filled(5, 6, false);
+        let full = Matrix::new_filled(6, 6, false);
+        assert!(image.seam_carve_masked(4, 6, &small, &full).is_err());
+        assert!(image.seam_carve_masked(4, 6, &full, &small).is_err());
+        assert!(image.seam_carve_masked(7, 6, &full, &full).is_err());
+    }
+
+    #[test]
+    fn luminance_energy_of_grays_is_a_third_of_rgb_energy() {
+        let mut gray = noise_image(9, 7, 2029);
+        gray.grayscale(None);
+        let rgb = gray.energy_with(EnergyBorder::ZeroEnergy, EnergySource::Rgb);
+        let luma = gray.energy_with(EnergyBorder::ZeroEnergy, EnergySource::Luminance);
+        assert!(rgb.datum.iter().any(|&energy| energy > 0));
+        assert!(
+            rgb.datum
+                .iter()
+                .zip(&luma.datum)
+                .all(|(&rgb, &luma)| rgb == 3 * luma)
+        );
+    }
+
+    #[test]
+    fn luminance_seams_follow_rgb_seams_and_compute_faster() {
+        let seam = |image: &Image, source| {
+            let energy = image.energy_with(EnergyBorder::MaxEnergy, source);
+            image.vertical_seam_from(&image.vertical_cost_from(&energy))
+        };
+        let patch = horses_patch();
+        let (rgb, luma) = (
+            seam(&patch, EnergySource::Rgb),
+            seam(&patch, EnergySource::Luminance),
+        );
+        // The seams wander apart by less than a column per row on average
+        let drift = rgb
+            .iter()
+            .zip(&luma)
+            .map(|(&a, &b)| a.abs_diff(b))
+            .sum::<usize>();
+        assert!(
+            drift <= rgb.len(),
+            "drifted {} over {} rows",
+            drift,
+            rgb.len()
+        );
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/horses.ppm");
+        let image = Image::from_file(path).unwrap();
+        // Times the scalar path whatever the features, taking the best of several runs
+        // so a stall in one does not decide the comparison
+        let fastest = |source| {
+            (0..5)
+                .map(|_| {
+                    let mut energy = Matrix::new_filled(image.width, image.height, 0);
+                    let start = Instant::now();
+                    image.interior_energy(&mut energy, source);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+        let (rgb_time, luma_time) = (fastest(EnergySource::Rgb), fastest(EnergySource::Luminance));
+        assert!(luma_time < rgb_time, "{:?} vs {:?}", luma_time, rgb_time);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_energy_matches_scalar_energy() {
+        for (width, height) in [(3, 3), (9, 4), (17, 11), (2, 6)] {
+            let image = noise_image(width, height, 2033 + width as u64);
+            for source in [EnergySource::Rgb, EnergySource::Luminance] {
+                let mut scalar = Matrix::new_filled(width, height, 0);
+                let mut parallel = Matrix::new_filled(width, height, 0);
+                let scalar_max = image.interior_energy(&mut scalar, source);
+                let parallel_max = image.interior_energy_parallel(&mut parallel, source);
+                assert_eq!(parallel, scalar, "{}x{} source {:?}", width, height, source);
+                assert_eq!(parallel_max, scalar_max);
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_seam_carving_matches_serial_carving() {
+        let source = horses_patch();
+        let mut parallel = source.clone();
+        parallel.seam_carve_width(34);
+
+        // Every seam found from energy computed one row after another on this thread
+        let mut serial = source;
+        while serial.width > 34 {
+            let mut energy = Matrix::new_filled(serial.width, serial.height, 0);
+            let max_energy = serial.interior_energy(&mut energy, EnergySource::Rgb);
+            serial.fill_energy_border(&mut energy, max_energy, EnergyBorder::default());
+            let seam = serial.vertical_seam_from(&serial.vertical_cost_from(&energy));
+            serial.carve_vertical_seam(&seam);
+        }
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    fn rotated_matches_turning_a_clone_and_leaves_the_source() {
+        let mut source = noise_image(6, 4, 2039);
+        source.alpha_channel = Some(noise_image(6, 4, 2040).red_channel);
+        let before = source.clone();
+
+        let mut left = source.clone();
+        left.rotate_left();
+        assert_eq!(source.rotated(1), left);
+        assert_eq!(source.rotated(5), left);
+
+        let mut half = source.clone();
+        half.rotate_left();
+        half.rotate_left();
+        assert_eq!(source.rotated(2), half);
+
+        let mut right = source.clone();
+        right.rotate_right();
+        assert_eq!(source.rotated(3), right);
+        assert_eq!(source.rotated(0), source);
+        assert_eq!(source, before);
     }
 }
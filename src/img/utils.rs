@@ -1,13 +1,94 @@
 use crate::img::image::*;
 
+use std::error::Error;
+
 /// Representation of an RGB Pixel
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct PixelRGB {
     pub r: usize,
     pub g: usize,
     pub b: usize,
 }
 
+/// Representation of an RGB Pixel with an alpha value, where max_intensity is fully opaque
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelRGBA {
+    pub r: usize,
+    pub g: usize,
+    pub b: usize,
+    pub a: usize,
+}
+
+impl PixelRGBA {
+    /// Drops the alpha value
+    pub fn rgb(&self) -> PixelRGB {
+        PixelRGB {
+            r: self.r,
+            g: self.g,
+            b: self.b,
+        }
+    }
+}
+
+/// Mutable view of a single pixel's channel values inside an Image
+#[derive(Debug)]
+pub struct PixelRefMut<'a> {
+    pub r: &'a mut usize,
+    pub g: &'a mut usize,
+    pub b: &'a mut usize,
+}
+
+impl PixelRefMut<'_> {
+    /// Copies the current values out as a PixelRGB
+    pub fn get(&self) -> PixelRGB {
+        PixelRGB {
+            r: *self.r,
+            g: *self.g,
+            b: *self.b,
+        }
+    }
+
+    /// Overwrites all three channels with the color
+    pub fn set(&mut self, color: PixelRGB) {
+        *self.r = color.r;
+        *self.g = color.g;
+        *self.b = color.b;
+    }
+}
+
+/// Rounds a computed channel value to the nearest integer within [0, max]
+pub fn clamp_channel(value: f64, max: usize) -> usize {
+    value.round().clamp(0.0, max as f64) as usize
+}
+
+/// Rounds the value down to the nearest multiple of n, leaving it untouched when n is 0
+pub fn round_down_to(value: usize, n: usize) -> usize {
+    value
+        .checked_div(n)
+        .map_or(value, |multiples| multiples * n)
+}
+
+/// Rec. 601 luma weights for the red, green, and blue channels
+pub const REC601_WEIGHTS: (f64, f64, f64) = (0.299, 0.587, 0.114);
+
+/// Applies the sRGB transfer function in reverse, mapping a [0, 1] encoded value to linear light
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Applies the sRGB transfer function, mapping a [0, 1] linear light value to its encoded form
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 impl Image {
     pub fn fill(&mut self, color: PixelRGB) {
         self.red_channel.fill(color.r);
@@ -15,6 +96,34 @@ impl Image {
         self.blue_channel.fill(color.b);
     }
 
+    /// Iterates every pixel in row-major order along with its row and column
+    pub fn iter_pixels(&self) -> impl Iterator<Item = (usize, usize, PixelRGB)> + '_ {
+        let width = self.width.max(1);
+        self.red_channel
+            .datum
+            .iter()
+            .zip(&self.green_channel.datum)
+            .zip(&self.blue_channel.datum)
+            .enumerate()
+            .map(move |(index, ((&r, &g), &b))| {
+                (index / width, index % width, PixelRGB { r, g, b })
+            })
+    }
+
+    /// Iterates every pixel in row-major order with mutable access to its channels
+    pub fn iter_pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, PixelRefMut<'_>)> {
+        let width = self.width.max(1);
+        self.red_channel
+            .datum
+            .iter_mut()
+            .zip(&mut self.green_channel.datum)
+            .zip(&mut self.blue_channel.datum)
+            .enumerate()
+            .map(move |(index, ((r, g), b))| {
+                (index / width, index % width, PixelRefMut { r, g, b })
+            })
+    }
+
     pub fn get_pixel(&self, row: usize, col: usize) -> Option<PixelRGB> {
         if row < self.height && col < self.width {
             Some(PixelRGB {
@@ -27,6 +136,17 @@ impl Image {
         }
     }
 
+    /// Like get_pixel, also reading the alpha value. Images without an alpha channel report every
+    /// pixel fully opaque at max_intensity
+    pub fn get_pixel_rgba(&self, row: usize, col: usize) -> Option<PixelRGBA> {
+        let PixelRGB { r, g, b } = self.get_pixel(row, col)?;
+        let a = match &self.alpha_channel {
+            Some(alpha) => *alpha.get(row, col)?,
+            None => self.max_intensity,
+        };
+        Some(PixelRGBA { r, g, b, a })
+    }
+
     pub fn set_pixel(&mut self, row: usize, col: usize, color: PixelRGB) {
         if row < self.height && col < self.width {
             self.red_channel[(row, col)] = color.r;
@@ -35,11 +155,53 @@ impl Image {
         }
     }
 
-    pub fn rgb_to_hsl(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
-        let r = r / 255.0;
-        let g = g / 255.0;
-        let b = b / 255.0;
+    /// Applies the mapping to every pixel in HSL space, normalizing channels by max_intensity
+    pub fn map_hsl<F>(&mut self, f: F)
+    where
+        F: Fn(f64, f64, f64) -> (f64, f64, f64),
+    {
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+        let normalize = |v: usize| v as f64 / max;
+        let scale = |v: f64| clamp_channel(v * max, max_intensity);
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let r = normalize(self.red_channel[(row, col)]);
+                let g = normalize(self.green_channel[(row, col)]);
+                let b = normalize(self.blue_channel[(row, col)]);
+
+                let (h, s, l) = Self::rgb_to_hsl(r, g, b);
+                let (h, s, l) = f(h, s, l);
+                let (r, g, b) = Self::hsl_to_rgb(h, s, l);
+
+                self.red_channel[(row, col)] = scale(r);
+                self.green_channel[(row, col)] = scale(g);
+                self.blue_channel[(row, col)] = scale(b);
+            }
+        }
+    }
 
+    /// Applies the mapping to every channel value, normalized by max_intensity to [0, 1]
+    pub fn map_channels<F>(&mut self, f: F)
+    where
+        F: Fn(f64) -> f64,
+    {
+        let max_intensity = self.max_intensity;
+        let max = max_intensity.max(1) as f64;
+        let map = |v: usize| clamp_channel(f(v as f64 / max) * max, max_intensity);
+
+        for channel in [
+            &mut self.red_channel,
+            &mut self.green_channel,
+            &mut self.blue_channel,
+        ] {
+            channel.apply_in_place(|value| *value = map(*value));
+        }
+    }
+
+    /// Converts normalized [0, 1] channels to hue in degrees with saturation and lightness in [0, 1]
+    pub fn rgb_to_hsl(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
         let max = r.max(g).max(b);
         let min = r.min(g).min(b);
         let delta = max - min;
@@ -66,7 +228,8 @@ impl Image {
         (h, s, l)
     }
 
-    pub fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (usize, usize, usize) {
+    /// Converts hue in degrees with saturation and lightness back to normalized [0, 1] channels
+    pub fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (f64, f64, f64) {
         let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
         let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
         let m = l - c / 2.0;
@@ -80,17 +243,232 @@ impl Image {
             _ => (c, 0.0, x),
         };
 
-        let to_255 = |v: f64| ((v + m) * 255.0).round().clamp(0.0, 255.0) as usize;
+        let shift = |v: f64| (v + m).clamp(0.0, 1.0);
 
-        (to_255(r1), to_255(g1), to_255(b1))
+        (shift(r1), shift(g1), shift(b1))
     }
 }
 
 impl PixelRGB {
+    /// Weighted sum of the channels, left unrounded and in the same scale as the pixel
+    pub fn luminance(&self, weights: (f64, f64, f64)) -> f64 {
+        self.r as f64 * weights.0 + self.g as f64 * weights.1 + self.b as f64 * weights.2
+    }
+
     pub fn squared_difference(&self, other: &PixelRGB) -> isize {
         let dr: isize = self.r as isize - other.r as isize;
         let dg: isize = self.g as isize - other.g as isize;
         let db: isize = self.b as isize - other.b as isize;
         dr * dr + dg * dg + db * db
     }
+
+    /// Looks up an 8-bit color by its basic CSS name, ignoring case
+    pub fn from_name(name: &str) -> Option<PixelRGB> {
+        let (r, g, b) = match name.to_ascii_lowercase().as_str() {
+            "black" => (0, 0, 0),
+            "white" => (255, 255, 255),
+            "gray" | "grey" => (128, 128, 128),
+            "red" => (255, 0, 0),
+            "green" => (0, 128, 0),
+            "lime" => (0, 255, 0),
+            "blue" => (0, 0, 255),
+            "yellow" => (255, 255, 0),
+            "cyan" => (0, 255, 255),
+            "magenta" => (255, 0, 255),
+            _ => return None,
+        };
+        Some(PixelRGB { r, g, b })
+    }
+
+    /// Parses an 8-bit color written as #RRGGBB or RRGGBB, in either case
+    pub fn from_hex(s: &str) -> Result<PixelRGB, Box<dyn Error>> {
+        let digits = s.strip_prefix('#').unwrap_or(s);
+        if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid color '{}', expected #RRGGBB", s).into());
+        }
+
+        let channel = |start: usize| usize::from_str_radix(&digits[start..start + 2], 16);
+        Ok(PixelRGB {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        })
+    }
+
+    /// Formats the color as #rrggbb, clamping each channel to 8 bits
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_hex(&self) -> String {
+        let [r, g, b] = [self.r, self.g, self.b].map(|v| v.min(255));
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::img::image::tests::noise_image;
+    use crate::img::matrix::Matrix;
+
+    #[test]
+    fn clamp_channel_rounds_into_range() {
+        assert_eq!(clamp_channel(-12.0, 255), 0);
+        assert_eq!(clamp_channel(254.5, 255), 255);
+        assert_eq!(clamp_channel(300.0, 255), 255);
+        assert_eq!(clamp_channel(7.4, 15), 7);
+        assert_eq!(clamp_channel(f64::NAN, 15), 0);
+    }
+
+    #[test]
+    fn round_down_to_snaps_to_multiples() {
+        assert_eq!(round_down_to(101, 2), 100);
+        assert_eq!(round_down_to(101, 16), 96);
+        assert_eq!(round_down_to(96, 16), 96);
+        assert_eq!(round_down_to(101, 0), 101);
+    }
+
+    #[test]
+    fn srgb_transfer_functions_are_inverses() {
+        for step in 0..=1000 {
+            let value = step as f64 / 1000.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-12);
+            assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn iter_pixels_matches_get_pixel_everywhere() {
+        let image = noise_image(7, 5, 2019);
+        let pixels: Vec<_> = image.iter_pixels().collect();
+        assert_eq!(pixels.len(), 35);
+        for (index, &(row, col, pixel)) in pixels.iter().enumerate() {
+            assert_eq!((row, col), (index / 7, index % 7));
+            assert_eq!(image.get_pixel(row, col), Some(pixel));
+        }
+    }
+
+    #[test]
+    fn iter_pixels_mut_writes_back() {
+        let mut image = noise_image(7, 5, 2019);
+        for (row, col, mut pixel) in image.iter_pixels_mut() {
+            pixel.set(PixelRGB {
+                r: row,
+                g: col,
+                b: 9,
+            });
+        }
+        for row in 0..5 {
+            for col in 0..7 {
+                assert_eq!(
+                    image.get_pixel(row, col),
+                    Some(PixelRGB {
+                        r: row,
+                        g: col,
+                        b: 9
+                    })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn get_pixel_rgba_reads_alpha_or_reports_opaque() {
+        let mut image = noise_image(4, 3, 2026);
+        let rgb = image.get_pixel(2, 3).unwrap();
+        assert_eq!(image.get_pixel_rgba(2, 3).map(|p| p.a), Some(255));
+        assert_eq!(image.get_pixel_rgba(2, 3).map(|p| p.rgb()), Some(rgb));
+
+        let mut alpha = Matrix::new_filled(4, 3, 255);
+        alpha[(2, 3)] = 40;
+        image.alpha_channel = Some(alpha);
+        assert_eq!(
+            image.get_pixel_rgba(2, 3),
+            Some(PixelRGBA {
+                r: rgb.r,
+                g: rgb.g,
+                b: rgb.b,
+                a: 40
+            })
+        );
+        assert_eq!(image.get_pixel_rgba(0, 0).map(|p| p.a), Some(255));
+        assert_eq!(image.get_pixel_rgba(3, 0), None);
+        assert_eq!(image.get_pixel_rgba(0, 4), None);
+    }
+
+    #[test]
+    fn hex_colors_parse_with_or_without_the_hash() {
+        let orange = PixelRGB {
+            r: 255,
+            g: 136,
+            b: 0,
+        };
+        assert_eq!(PixelRGB::from_hex("#ff8800").unwrap(), orange);
+        assert_eq!(PixelRGB::from_hex("FF8800").unwrap(), orange);
+        assert_eq!(orange.to_hex(), "#ff8800");
+
+        for invalid in [
+            "", "#", "#fff", "ff880", "#ff88001", "##ff8800", "#gg8800", "+f8800",
+        ] {
+            assert!(PixelRGB::from_hex(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn hex_colors_round_trip() {
+        for hex in ["#000000", "#ffffff", "#0a1b2c", "#7f8081"] {
+            assert_eq!(PixelRGB::from_hex(hex).unwrap().to_hex(), hex);
+        }
+        let deep = PixelRGB {
+            r: 1000,
+            g: 255,
+            b: 16,
+        };
+        assert_eq!(deep.to_hex(), "#ffff10");
+    }
+
+    #[test]
+    fn fill_paints_every_pixel_and_keeps_alpha() {
+        let mut image = noise_image(4, 3, 2047);
+        image.alpha_channel = Some(Matrix::new_filled(4, 3, 7));
+        let teal = PixelRGB::from_hex("#008080").unwrap();
+        image.fill(teal);
+
+        assert!(image.iter_pixels().all(|(_, _, pixel)| pixel == teal));
+        assert_eq!(image.iter_pixels().count(), 12);
+        assert_eq!(image.alpha_channel, Some(Matrix::new_filled(4, 3, 7)));
+    }
+
+    #[test]
+    fn color_names_ignore_case() {
+        assert_eq!(
+            PixelRGB::from_name("Yellow"),
+            Some(PixelRGB {
+                r: 255,
+                g: 255,
+                b: 0
+            })
+        );
+        assert_eq!(PixelRGB::from_name("GREY"), PixelRGB::from_name("gray"));
+        assert_eq!(PixelRGB::from_name("#ffffff"), None);
+    }
+
+    #[test]
+    fn map_hsl_keeps_the_low_bits_of_16_bit_images() {
+        let mut image = noise_image(9, 7, 2041);
+        image.max_intensity = u16::MAX as usize;
+        for channel in image.all_channels_mut() {
+            channel.apply_in_place(|value| *value = *value * 257 - *value % 199);
+        }
+
+        let mut rotated = image.clone();
+        rotated.map_hsl(|h, s, l| ((h + 360.0) % 360.0, s, l));
+        for (row, col, pixel) in image.iter_pixels() {
+            assert_eq!(
+                rotated.get_pixel(row, col),
+                Some(pixel),
+                "({}, {})",
+                row,
+                col
+            );
+        }
+    }
 }
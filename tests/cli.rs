@@ -0,0 +1,473 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the snap binary with the given arguments
+fn snap(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_snap"))
+        .args(args)
+        .output()
+        .expect("Failed to run snap")
+}
+
+#[test]
+fn json_errors_reports_a_parseable_object() {
+    let output = snap(&[
+        "--json-errors",
+        "img",
+        "invert",
+        "does-not-exist.ppm",
+        "out.ppm",
+    ]);
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error: serde_json::Value = serde_json::from_str(stderr.trim()).unwrap();
+    assert_eq!(error["kind"], "io");
+    assert!(
+        error["error"]
+            .as_str()
+            .is_some_and(|message| !message.is_empty())
+    );
+}
+
+#[test]
+fn errors_stay_plain_text_without_the_flag() {
+    let output = snap(&["img", "invert", "does-not-exist.ppm", "out.ppm"]);
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(serde_json::from_str::<serde_json::Value>(stderr.trim()).is_err());
+}
+
+/// Path of a scratch file unique to this test process
+fn temp_path(name: &str) -> String {
+    let file = format!("snap-cli-test-{}-{}", std::process::id(), name);
+    std::env::temp_dir()
+        .join(file)
+        .to_string_lossy()
+        .into_owned()
+}
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/fixture.ppm");
+
+#[test]
+fn pipeline_matches_running_the_commands_in_turn() {
+    let (blurred, sequential, piped) = (
+        temp_path("blurred.ppm"),
+        temp_path("sequential.ppm"),
+        temp_path("piped.ppm"),
+    );
+
+    assert!(
+        snap(&["img", "blur", FIXTURE, &blurred, "1.5"])
+            .status
+            .success()
+    );
+    assert!(
+        snap(&["img", "grayscale", &blurred, &sequential])
+            .status
+            .success()
+    );
+    let pipeline = snap(&[
+        "img",
+        "pipeline",
+        FIXTURE,
+        &piped,
+        "--op",
+        "blur:1.5",
+        "--op",
+        "grayscale",
+    ]);
+    assert!(pipeline.status.success());
+
+    let sequential_bytes = std::fs::read(&sequential).unwrap();
+    let piped_bytes = std::fs::read(&piped).unwrap();
+    for path in [blurred, sequential, piped] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_eq!(piped_bytes, sequential_bytes);
+}
+
+#[test]
+fn bench_prints_a_line_per_operation() {
+    let output = snap(&["bench", FIXTURE]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert!(lines.next().unwrap().starts_with("Benchmarking 5x3"));
+    let timings: Vec<&str> = lines.collect();
+    assert!(timings.len() >= 8);
+    assert!(timings.iter().all(|line| line.ends_with(" ms")));
+}
+
+#[test]
+fn dash_reads_stdin_and_writes_stdout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_snap"))
+        .args(["--format", "ppm", "img", "invert", "-", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run snap");
+    let input = std::fs::read(FIXTURE).unwrap();
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    // Inverting the streamed result once more must give back the fixture as P6
+    let (piped, reinverted) = (temp_path("piped.ppm"), temp_path("reinverted.ppm"));
+    std::fs::write(&piped, &output.stdout).unwrap();
+    assert!(output.stdout.starts_with(b"P6\n5 3\n255\n"));
+    assert!(
+        snap(&["img", "invert", &piped, &reinverted])
+            .status
+            .success()
+    );
+    assert!(snap(&["img", "convert", FIXTURE, &piped]).status.success());
+    let expected = std::fs::read(&piped).unwrap();
+    let actual = std::fs::read(&reinverted).unwrap();
+    std::fs::remove_file(piped).unwrap();
+    std::fs::remove_file(reinverted).unwrap();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn logged_single_commands_replay_to_the_same_bytes() {
+    let sidecar = temp_path("log-ops.json");
+    for (name, args) in [
+        ("resize", vec!["resize", "20", "12"]),
+        ("rotate", vec!["rotate-right"]),
+        ("crop", vec!["crop", "9", "7"]),
+        ("blur", vec!["blur", "1.5"]),
+        ("scale", vec!["scale", "30", "30", "--linear"]),
+    ] {
+        let output = temp_path(&format!("logged-{}.ppm", name));
+        let mut command = vec!["--log-ops", &sidecar, "img", args[0], FIXTURE, &output];
+        command.extend(&args[1..]);
+        assert!(snap(&command).status.success(), "{}", name);
+        let written = std::fs::read(&output).unwrap();
+
+        std::fs::remove_file(&output).unwrap();
+        assert!(
+            snap(&["img", "replay", &sidecar]).status.success(),
+            "{}",
+            name
+        );
+        assert_eq!(std::fs::read(&output).unwrap(), written, "{}", name);
+        std::fs::remove_file(&output).unwrap();
+    }
+    std::fs::remove_file(&sidecar).unwrap();
+}
+
+#[test]
+fn options_without_an_operation_are_not_logged() {
+    let (sidecar, output) = (temp_path("unlogged.json"), temp_path("unlogged.ppm"));
+    let result = snap(&[
+        "--log-ops",
+        &sidecar,
+        "img",
+        "crop",
+        FIXTURE,
+        &output,
+        "4",
+        "4",
+        "--focus",
+        "0.7,0.3",
+    ]);
+    assert!(!result.status.success());
+    assert!(
+        String::from_utf8(result.stderr)
+            .unwrap()
+            .contains("--log-ops")
+    );
+    assert!(!std::path::Path::new(&sidecar).exists());
+}
+
+#[test]
+fn out_of_range_crop_rect_fails_cleanly() {
+    let output = temp_path("crop-rect.ppm");
+    let result = snap(&[
+        "img",
+        "crop-rect",
+        FIXTURE,
+        &output,
+        "0",
+        "0",
+        "100000",
+        "1",
+    ]);
+    assert!(!result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(!std::path::Path::new(&output).exists());
+}
+
+#[test]
+fn extreme_aspect_scaling_warns_and_keeps_one_row() {
+    let (wide, scaled) = (temp_path("wide.ppm"), temp_path("wide-scaled.ppm"));
+    assert!(snap(&["img", "new", &wide, "1000", "10"]).status.success());
+
+    let output = snap(&[
+        "img",
+        "scale",
+        &wide,
+        &scaled,
+        "3",
+        "0",
+        "--preserve-aspect",
+    ]);
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Warning")
+    );
+    assert!(std::fs::read(&scaled).unwrap().starts_with(b"P6\n3 1\n"));
+
+    std::fs::remove_file(&wide).unwrap();
+    std::fs::remove_file(&scaled).unwrap();
+}
+
+/// The byte count from a --measure report
+fn measured_size(output: &Output) -> usize {
+    assert!(output.status.success());
+    let report = String::from_utf8(output.stdout.clone()).unwrap();
+    report
+        .split_whitespace()
+        .rev()
+        .skip_while(|word| *word != "bytes")
+        .nth(1)
+        .and_then(|size| size.parse().ok())
+        .unwrap_or_else(|| panic!("No size in '{}'", report))
+}
+
+#[test]
+fn measure_reports_sizes_that_follow_the_quality() {
+    let measure = |quality: &str| {
+        measured_size(&snap(&[
+            "--format",
+            "jpg",
+            "img",
+            "convert",
+            FIXTURE,
+            "--measure",
+            "--quality",
+            quality,
+        ]))
+    };
+    let (low, high) = (measure("10"), measure("95"));
+    assert!(low < high, "{} then {}", low, high);
+
+    // With an output path the file is written and matches the reported size
+    let output = temp_path("measured.png");
+    let size = measured_size(&snap(&["img", "convert", FIXTURE, &output, "--measure"]));
+    assert_eq!(std::fs::metadata(&output).unwrap().len() as usize, size);
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn paste_overlays_a_second_file() {
+    let (base, overlay, pasted) = (
+        temp_path("paste-base.ppm"),
+        temp_path("paste-overlay.ppm"),
+        temp_path("pasted.ppm"),
+    );
+    assert!(snap(&["img", "new", &base, "3", "2"]).status.success());
+    std::fs::write(&overlay, "P3\n2 1\n255\n255 0 0 0 255 0\n").unwrap();
+
+    let output = snap(&[
+        "img", "paste", &base, &overlay, &pasted, "--x", "2", "--y", "1",
+    ]);
+    assert!(output.status.success());
+    let mut expected = b"P6\n3 2\n255\n".to_vec();
+    expected.extend_from_slice(&[0; 15]);
+    expected.extend_from_slice(&[255, 0, 0]);
+    assert_eq!(std::fs::read(&pasted).unwrap(), expected);
+
+    for path in [base, overlay, pasted] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn flatten_checker_fills_transparency_with_gray_squares() {
+    let (input, flat) = (temp_path("checker.pam"), temp_path("checker.ppm"));
+    let mut pam =
+        b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n".to_vec();
+    pam.extend_from_slice(&[255, 0, 0, 0, 255, 0, 0, 0]);
+    std::fs::write(&input, pam).unwrap();
+
+    let output = snap(&["img", "flatten", &input, &flat, "--checker", "1"]);
+    assert!(output.status.success());
+    let mut expected = b"P6\n2 1\n255\n".to_vec();
+    expected.extend_from_slice(&[204, 204, 204, 153, 153, 153]);
+    assert_eq!(std::fs::read(&flat).unwrap(), expected);
+
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&flat).unwrap();
+}
+
+#[test]
+fn concat_stacks_inputs_in_the_given_direction() {
+    let (top, bottom, stacked) = (
+        temp_path("concat-top.ppm"),
+        temp_path("concat-bottom.ppm"),
+        temp_path("concat.ppm"),
+    );
+    std::fs::write(&top, "P3\n1 1\n255\n1 2 3\n").unwrap();
+    std::fs::write(&bottom, "P3\n1 1\n255\n4 5 6\n").unwrap();
+
+    let output = snap(&[
+        "img",
+        "concat",
+        &top,
+        &bottom,
+        &stacked,
+        "--direction",
+        "vertical",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read(&stacked).unwrap(),
+        b"P6\n1 2\n255\n\x01\x02\x03\x04\x05\x06"
+    );
+
+    for path in [top, bottom, stacked] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn frame_writes_the_indexed_frame() {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    let (gif, frame) = (temp_path("frames.gif"), temp_path("frame.pam"));
+    let mut encoder = GifEncoder::new(std::fs::File::create(&gif).unwrap());
+    for red in [0, 200] {
+        let buffer = RgbaImage::from_pixel(2, 1, Rgba([red, 0, 0, 255]));
+        let delay = Delay::from_numer_denom_ms(100, 1);
+        encoder
+            .encode_frame(Frame::from_parts(buffer, 0, 0, delay))
+            .unwrap();
+    }
+    drop(encoder);
+
+    let output = snap(&["img", "frame", &gif, &frame, "--index", "1"]);
+    assert!(output.status.success());
+    // GIF frames decode with alpha, so the frame is written as an RGBA PAM
+    let written = std::fs::read(&frame).unwrap();
+    assert!(written.starts_with(b"P7\nWIDTH 2\nHEIGHT 1\n"));
+    assert!(written.ends_with(b"ENDHDR\n\xc8\x00\x00\xff\xc8\x00\x00\xff"));
+    assert!(
+        !snap(&["img", "frame", &gif, &frame, "--index", "2"])
+            .status
+            .success()
+    );
+
+    std::fs::remove_file(&gif).unwrap();
+    std::fs::remove_file(&frame).unwrap();
+}
+
+#[test]
+fn sharpen_after_only_changes_downscales() {
+    let run = |name: &str, size: &str, extra: &[&str]| {
+        let path = temp_path(name);
+        let mut args = vec!["img", "scale", FIXTURE, &path, size, size];
+        args.extend_from_slice(extra);
+        assert!(snap(&args).status.success());
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    };
+    let sharpen = ["--sharpen-after", "0.8"];
+
+    assert_ne!(
+        run("down.ppm", "4", &[]),
+        run("down-sharp.ppm", "4", &sharpen)
+    );
+    assert_eq!(
+        run("up.ppm", "32", &[]),
+        run("up-sharp.ppm", "32", &sharpen)
+    );
+}
+
+#[test]
+fn preview_prints_the_ascii_rendering() {
+    let white = temp_path("preview.ppm");
+    let pixels = "255 255 255 ".repeat(16);
+    std::fs::write(&white, format!("P3\n4 4\n255\n{}\n", pixels)).unwrap();
+
+    let output = snap(&["img", "preview", &white, "--cols", "4"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "@@@@\n@@@@\n");
+    std::fs::remove_file(&white).unwrap();
+}
+
+#[test]
+fn key_clears_the_key_color() {
+    let (input, keyed) = (temp_path("key.ppm"), temp_path("keyed.pam"));
+    std::fs::write(&input, "P3\n2 1\n255\n255 255 255 0 0 0\n").unwrap();
+
+    let output = snap(&[
+        "img",
+        "key",
+        &input,
+        &keyed,
+        "--color",
+        "white",
+        "--tolerance",
+        "20",
+    ]);
+    assert!(output.status.success());
+    let written = std::fs::read(&keyed).unwrap();
+    assert!(written.ends_with(b"ENDHDR\n\xff\xff\xff\x00\x00\x00\x00\xff"));
+
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&keyed).unwrap();
+}
+
+#[test]
+fn new_and_fill_write_uniform_colors() {
+    let (created, filled) = (temp_path("new.ppm"), temp_path("filled.ppm"));
+    let output = snap(&["img", "new", &created, "3", "2", "--color", "#ff8800"]);
+    assert!(output.status.success());
+    let mut expected = b"P6\n3 2\n255\n".to_vec();
+    expected.extend_from_slice(&[255, 136, 0].repeat(6));
+    assert_eq!(std::fs::read(&created).unwrap(), expected);
+
+    assert!(
+        snap(&["img", "fill", FIXTURE, &filled, "--color", "blue"])
+            .status
+            .success()
+    );
+    let mut expected = b"P6\n5 3\n255\n".to_vec();
+    expected.extend_from_slice(&[0, 0, 255].repeat(15));
+    assert_eq!(std::fs::read(&filled).unwrap(), expected);
+    assert!(
+        !snap(&["img", "new", &created, "3", "2", "--color", "#ff88"])
+            .status
+            .success()
+    );
+
+    std::fs::remove_file(&created).unwrap();
+    std::fs::remove_file(&filled).unwrap();
+}
+
+#[test]
+fn oversized_headers_fail_without_crashing() {
+    let path = temp_path("oversized.ppm");
+    for header in [
+        &b"P6\n4000000000 4000000000\n255\n"[..],
+        b"P6\n200000 200000\n255\nabc",
+        b"P7\nWIDTH 4000000000\nHEIGHT 4000000000\nDEPTH 3\nMAXVAL 255\nENDHDR\n",
+        b"P7\nWIDTH 200000\nHEIGHT 200000\nDEPTH 4\nMAXVAL 255\nENDHDR\nabc",
+    ] {
+        std::fs::write(&path, header).unwrap();
+        let output = snap(&["img", "info", &path]);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+    std::fs::remove_file(&path).unwrap();
+}